@@ -0,0 +1,141 @@
+use crate::diff::ChangeType;
+
+/// Leading bytes of common binary formats and their MIME types. Formats
+/// whose signature starts later in the file (WebP) are handled in
+/// `sniff_mime_type`.
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"\x00\x00\x01\x00", "image/x-icon"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"\xfd7zXZ\x00", "application/x-xz"),
+    (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (b"wOFF", "font/woff"),
+    (b"wOF2", "font/woff2"),
+    (b"\x00\x01\x00\x00", "font/ttf"),
+    (b"OTTO", "font/otf"),
+    (b"\x00asm", "application/wasm"),
+    (b"\x7fELF", "application/x-elf"),
+    (b"SQLite format 3\x00", "application/vnd.sqlite3"),
+];
+
+const UNKNOWN_MIME_TYPE: &str = "application/octet-stream";
+
+/// Leading bytes `sniff_mime_type` needs to recognise every format above.
+pub const SNIFF_BYTES: usize = 16;
+
+/// A file the diff shows only as `Binary files ... differ`, described by
+/// what can be told without reading it: its size and type, taken from the
+/// post-change contents (the pre-change ones for deletions).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BinaryFile {
+    pub path: String,
+    pub change_type: ChangeType,
+    /// `None` when the contents could not be read.
+    pub size: Option<u64>,
+    pub mime_type: &'static str,
+}
+
+impl BinaryFile {
+    /// `probe` is the file's size and its first `SNIFF_BYTES` bytes, or
+    /// `None` when it could not be read.
+    pub fn new(path: String, change_type: ChangeType, probe: Option<(u64, &[u8])>) -> Self {
+        Self {
+            path,
+            change_type,
+            size: probe.map(|(size, _)| size),
+            mime_type: probe.map_or(UNKNOWN_MIME_TYPE, |(_, head)| sniff_mime_type(head)),
+        }
+    }
+}
+
+/// The MIME type of `contents` from its magic bytes, or
+/// `application/octet-stream` for formats not in `SIGNATURES`.
+pub fn sniff_mime_type(contents: &[u8]) -> &'static str {
+    if contents.starts_with(b"RIFF") && contents.get(8..12) == Some(b"WEBP") {
+        return "image/webp";
+    }
+    SIGNATURES
+        .iter()
+        .find(|(magic, _)| contents.starts_with(magic))
+        .map_or(UNKNOWN_MIME_TYPE, |(_, mime_type)| mime_type)
+}
+
+/// The `BINARY FILES` prompt section's lines, e.g.
+/// `assets/logo.png: added, 4.0MB, image/png`.
+pub fn format_binary_files(files: &[BinaryFile]) -> String {
+    files
+        .iter()
+        .map(|file| {
+            let change = match file.change_type {
+                ChangeType::Added => "added",
+                ChangeType::Deleted => "removed",
+                ChangeType::Modified => "modified",
+                ChangeType::Renamed => "renamed",
+                ChangeType::Copied => "copied",
+            };
+            let size = file.size.map_or("size unknown".to_string(), format_size);
+            format!("{}: {}, {}, {}\n", file.path, change, size, file.mime_type)
+        })
+        .collect()
+}
+
+fn format_size(bytes: u64) -> String {
+    const KB: f64 = 1024.0;
+    let size = bytes as f64;
+    if size < KB {
+        format!("{}B", bytes)
+    } else if size < KB * KB {
+        format!("{:.1}KB", size / KB)
+    } else {
+        format!("{:.1}MB", size / (KB * KB))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniff_mime_type_reads_magic_bytes() {
+        assert_eq!(sniff_mime_type(b"\x89PNG\r\n\x1a\n\x00\x00"), "image/png");
+        assert_eq!(
+            sniff_mime_type(b"RIFF\x10\x00\x00\x00WEBPVP8 "),
+            "image/webp"
+        );
+        assert_eq!(
+            sniff_mime_type(b"RIFF\x10\x00\x00\x00WAVEfmt "),
+            UNKNOWN_MIME_TYPE
+        );
+        assert_eq!(sniff_mime_type(b"wOF2\x00\x01"), "font/woff2");
+        assert_eq!(sniff_mime_type(b""), UNKNOWN_MIME_TYPE);
+    }
+
+    #[test]
+    fn format_binary_files_shows_change_size_and_type() {
+        let png: &[u8] = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR";
+        let files = [
+            BinaryFile::new(
+                "assets/logo.png".to_string(),
+                ChangeType::Added,
+                Some((4 * 1024 * 1024, png)),
+            ),
+            BinaryFile::new(
+                "data.bin".to_string(),
+                ChangeType::Deleted,
+                Some((300, &[0; 16])),
+            ),
+            BinaryFile::new("font.ttf".to_string(), ChangeType::Modified, None),
+        ];
+        assert_eq!(
+            format_binary_files(&files),
+            "assets/logo.png: added, 4.0MB, image/png\n\
+             data.bin: removed, 300B, application/octet-stream\n\
+             font.ttf: modified, size unknown, application/octet-stream\n"
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use anyhow::{Context, Result};
+use flate2::read::MultiGzDecoder;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+
+/// Characters kept from each `--context-file`. Longer files keep their end,
+/// where build and test tools print failures and summaries.
+const MAX_FILE_CHARS: usize = 20_000;
+/// Characters of build/test output allowed in one prompt; files past this
+/// are left out.
+const MAX_TOTAL_CHARS: usize = 50_000;
+/// Bytes read into memory from the end of each file. No character takes
+/// more than four, so this always covers `MAX_FILE_CHARS`.
+const MAX_FILE_BYTES: usize = MAX_FILE_CHARS * 4;
+/// The first bytes of every gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// The end of one `--context-file`, as read.
+struct ContextFile {
+    path: String,
+    contents: String,
+    /// Bytes before `contents` that were never kept in memory.
+    dropped_bytes: u64,
+}
+
+/// The `--context-file` section: each file under a `--- path ---` header,
+/// with a notice wherever output was cut to fit the limits. Gzipped files
+/// (as CI systems often store logs) are recognised by their magic bytes and
+/// decompressed before the limits apply. Only the end of each file is held
+/// in memory, however large it (or its decompressed contents) is. Files need
+/// not be UTF-8; invalid bytes are replaced.
+pub fn build_output(paths: &[String]) -> Result<String> {
+    let files = paths
+        .iter()
+        .map(|path| {
+            let file = File::open(path)
+                .with_context(|| format!("Failed to read context file {}", path))?;
+            let mut reader = BufReader::new(file);
+            let is_gzip = reader
+                .fill_buf()
+                .with_context(|| format!("Failed to read context file {}", path))?
+                .starts_with(&GZIP_MAGIC);
+            // Every member is decompressed, since `cat a.gz b.gz` is itself
+            // a valid gzip file.
+            let (bytes, dropped_bytes) = if is_gzip {
+                read_tail(MultiGzDecoder::new(reader), MAX_FILE_BYTES)
+                    .with_context(|| format!("Failed to decompress context file {}", path))?
+            } else {
+                read_tail(reader, MAX_FILE_BYTES)
+                    .with_context(|| format!("Failed to read context file {}", path))?
+            };
+            Ok(ContextFile {
+                path: path.clone(),
+                contents: String::from_utf8_lossy(&bytes).into_owned(),
+                dropped_bytes,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(format_outputs(&files))
+}
+
+/// The last `keep` bytes `reader` yields, and how many came before them.
+fn read_tail(mut reader: impl Read, keep: usize) -> std::io::Result<(Vec<u8>, u64)> {
+    let mut tail = Vec::new();
+    let mut dropped = 0;
+    let mut chunk = [0; 64 * 1024];
+    loop {
+        let read = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        tail.extend_from_slice(&chunk[..read]);
+        // Draining only once the buffer doubles keeps the copying linear.
+        if tail.len() > keep * 2 {
+            let excess = tail.len() - keep;
+            tail.drain(..excess);
+            dropped += excess as u64;
+        }
+    }
+    let excess = tail.len().saturating_sub(keep);
+    tail.drain(..excess);
+    Ok((tail, dropped + excess as u64))
+}
+
+fn format_outputs(files: &[ContextFile]) -> String {
+    let mut output = String::new();
+    let mut remaining = MAX_TOTAL_CHARS;
+    for file in files {
+        output.push_str(&format!("--- {} ---\n", file.path));
+        let contents = file.contents.trim_end();
+        let chars = contents.chars().count();
+        let kept = chars.min(MAX_FILE_CHARS).min(remaining);
+        if chars == 0 && file.dropped_bytes == 0 {
+            output.push_str("(empty)\n");
+            continue;
+        }
+        if remaining == 0 {
+            output.push_str("[left out: the limit for build/test output was reached]\n");
+            continue;
+        }
+        if file.dropped_bytes > 0 {
+            output.push_str(&format!(
+                "[all but the last {} characters left out; the first {} bytes were not read]\n",
+                kept, file.dropped_bytes
+            ));
+        } else if kept < chars {
+            output.push_str(&format!(
+                "[first {} of {} characters left out]\n",
+                chars - kept,
+                chars
+            ));
+        }
+        output.extend(contents.chars().skip(chars - kept));
+        output.push('\n');
+        remaining -= kept;
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_outputs_keeps_the_end_and_caps_the_total() {
+        let long = format!("{}FAILED tests::it_works", "x".repeat(MAX_FILE_CHARS));
+        let files: Vec<ContextFile> = (0..4)
+            .map(|i| ContextFile {
+                path: format!("log{}.txt", i),
+                contents: long.clone(),
+                dropped_bytes: 0,
+            })
+            .collect();
+
+        let output = format_outputs(&files);
+        assert!(
+            output.starts_with("--- log0.txt ---\n[first 22 of 20022 characters left out]\nxxx")
+        );
+        assert!(output.contains("FAILED tests::it_works\n--- log1.txt ---\n"));
+        assert!(output.contains(
+            "--- log3.txt ---\n[left out: the limit for build/test output was reached]\n"
+        ));
+        let empty = ContextFile {
+            path: "empty.log".to_string(),
+            contents: "\n".to_string(),
+            dropped_bytes: 0,
+        };
+        assert_eq!(format_outputs(&[empty]), "--- empty.log ---\n(empty)\n");
+    }
+
+    #[test]
+    fn build_output_decompresses_gzipped_logs() {
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let dir = tempfile::tempdir().unwrap();
+        let gzipped = dir.path().join("test.log.gz");
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"FAILED tests::it_works\n").unwrap();
+        std::fs::write(&gzipped, encoder.finish().unwrap()).unwrap();
+        let corrupt = dir.path().join("corrupt.gz");
+        std::fs::write(&corrupt, [0x1f, 0x8b, 0x00]).unwrap();
+
+        let path = gzipped.to_string_lossy().to_string();
+        assert_eq!(
+            build_output(std::slice::from_ref(&path)).unwrap(),
+            format!("--- {} ---\nFAILED tests::it_works\n", path)
+        );
+        let err = build_output(&[corrupt.to_string_lossy().to_string()]).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Failed to decompress context file")
+        );
+    }
+
+    #[test]
+    fn build_output_reads_only_the_end_of_large_gzipped_logs() {
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let dir = tempfile::tempdir().unwrap();
+        let gzipped = dir.path().join("huge.log.gz");
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        for _ in 0..1000 {
+            encoder.write_all(&[b'x'; 1024]).unwrap();
+        }
+        encoder.write_all(b"\nFAILED tests::it_works\n").unwrap();
+        std::fs::write(&gzipped, encoder.finish().unwrap()).unwrap();
+
+        let output = build_output(&[gzipped.to_string_lossy().to_string()]).unwrap();
+        let total = 1000 * 1024 + "\nFAILED tests::it_works\n".len();
+        assert!(output.contains(&format!(
+            "[all but the last {} characters left out; the first {} bytes were not read]\nxxx",
+            MAX_FILE_CHARS,
+            total - MAX_FILE_BYTES
+        )));
+        assert!(output.ends_with("xxx\nFAILED tests::it_works\n"));
+    }
+
+    #[test]
+    fn read_tail_keeps_the_last_bytes() {
+        let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        assert_eq!(
+            read_tail(&data[..], 10).unwrap(),
+            (data[990..].to_vec(), 990)
+        );
+        assert_eq!(read_tail(&data[..], 2000).unwrap(), (data.clone(), 0));
+    }
+}
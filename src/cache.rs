@@ -0,0 +1,161 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+
+/// Prefix of every entry's file name. `prune` only touches files named like
+/// an entry, since the directory may hold other files (e.g. `--cache-dir .`).
+const ENTRY_PREFIX: &str = "blart-";
+
+/// Final model responses saved by `--cache-dir`, one JSON file per key.
+pub struct ReviewCache {
+    dir: PathBuf,
+    /// Entries older than this many seconds are ignored and pruned.
+    max_age_secs: u64,
+}
+
+/// One cached review: the model's final message plus what produced it.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct CacheEntry {
+    /// Unix timestamp, in seconds, of when the review was stored.
+    pub created_at: i64,
+    pub model: String,
+    pub base_hash: String,
+    pub head_hash: String,
+    pub content: String,
+}
+
+/// A hex SHA-256 over `parts`. Each part is length-prefixed so that moving
+/// text from one part to the next changes the key.
+pub fn cache_key(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part.as_bytes());
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+impl ReviewCache {
+    pub fn new(dir: impl Into<PathBuf>, max_age_secs: u64) -> Self {
+        Self {
+            dir: dir.into(),
+            max_age_secs,
+        }
+    }
+
+    /// The unexpired entry for `key`. Missing and unreadable entries are
+    /// misses, so a corrupt cache never fails a review.
+    pub fn get(&self, key: &str, now: i64) -> Option<CacheEntry> {
+        let contents = fs::read_to_string(self.path(key)).ok()?;
+        let entry: CacheEntry = serde_json::from_str(&contents).ok()?;
+        (!self.is_expired(&entry, now)).then_some(entry)
+    }
+
+    pub fn put(&self, key: &str, entry: &CacheEntry) -> Result<()> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create cache directory {}", self.dir.display()))?;
+        let path = self.path(key);
+        fs::write(&path, serde_json::to_string_pretty(entry)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Deletes expired and unreadable entries, leaving other files alone.
+    pub fn prune(&self, now: i64) {
+        let Ok(read_dir) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        for path in read_dir.filter_map(Result::ok).map(|entry| entry.path()) {
+            if !path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(is_entry_file_name)
+            {
+                continue;
+            }
+            let expired = fs::read_to_string(&path)
+                .ok()
+                .and_then(|contents| serde_json::from_str::<CacheEntry>(&contents).ok())
+                .is_none_or(|entry| self.is_expired(&entry, now));
+            if expired {
+                // Another run may have pruned it first.
+                let _ = fs::remove_file(&path);
+            }
+        }
+    }
+
+    fn is_expired(&self, entry: &CacheEntry, now: i64) -> bool {
+        now.saturating_sub(entry.created_at) > self.max_age_secs as i64
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}{}.json", ENTRY_PREFIX, key))
+    }
+}
+
+/// Whether `name` is `blart-<key>.json` for a `cache_key` key.
+fn is_entry_file_name(name: &str) -> bool {
+    name.strip_prefix(ENTRY_PREFIX)
+        .and_then(|rest| rest.strip_suffix(".json"))
+        .is_some_and(|key| key.len() == 64 && key.bytes().all(|byte| byte.is_ascii_hexdigit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(created_at: i64) -> CacheEntry {
+        CacheEntry {
+            created_at,
+            model: "m".to_string(),
+            base_hash: "abc".to_string(),
+            head_hash: "def".to_string(),
+            content: "review".to_string(),
+        }
+    }
+
+    #[test]
+    fn cache_key_separates_parts() {
+        assert_eq!(cache_key(&["ab", "c"]).len(), 64);
+        assert_eq!(cache_key(&["ab", "c"]), cache_key(&["ab", "c"]));
+        assert_ne!(cache_key(&["ab", "c"]), cache_key(&["a", "bc"]));
+    }
+
+    #[test]
+    fn get_returns_fresh_entries_and_prune_drops_old_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ReviewCache::new(dir.path().join("cache"), 100);
+        let fresh = cache_key(&["fresh"]);
+        let stale = cache_key(&["stale"]);
+        assert_eq!(cache.get(&fresh, 1000), None);
+
+        cache.put(&fresh, &entry(950)).unwrap();
+        cache.put(&stale, &entry(800)).unwrap();
+        let corrupt = format!("cache/blart-{}.json", cache_key(&["corrupt"]));
+        fs::write(dir.path().join(corrupt), "{").unwrap();
+        fs::write(dir.path().join("cache/package.json"), "{").unwrap();
+        fs::write(dir.path().join("cache/blart-notes.json"), "{").unwrap();
+        assert_eq!(cache.get(&fresh, 1000), Some(entry(950)));
+        assert_eq!(cache.get(&stale, 1000), None);
+
+        cache.prune(1000);
+        let mut remaining: Vec<String> = fs::read_dir(dir.path().join("cache"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        remaining.sort();
+        assert_eq!(
+            remaining,
+            vec![
+                format!("blart-{}.json", fresh),
+                "blart-notes.json".to_string(),
+                "package.json".to_string()
+            ]
+        );
+    }
+}
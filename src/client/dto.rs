@@ -1,4 +1,4 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
 #[derive(Debug, Serialize)]
 #[allow(dead_code)]
@@ -16,7 +16,23 @@ pub struct ChatRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_tokens: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub reasoning_effort: Option<String>,
+    pub reasoning_effort: Option<ReasoningEffort>,
+    /// OpenAI routes requests with the same key to the same cache, which helps
+    /// the long, unchanging system prompt hit the prompt cache.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_cache_key: Option<String>,
+    /// Sequences at which the model stops generating.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+}
+
+/// OpenAI takes a bare effort level; some backends instead take an object
+/// that can also cap the tokens spent on reasoning.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum ReasoningEffort {
+    Level(String),
+    Config { effort: String, max_tokens: u32 },
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -63,7 +79,7 @@ pub struct ToolFunctionDef {
     pub parameters: serde_json::Value,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Clone)]
 #[allow(dead_code)]
 pub struct ResponseFormat {
     #[serde(rename = "type")]
@@ -72,7 +88,7 @@ pub struct ResponseFormat {
     pub json_schema: Option<JsonSchema>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Clone)]
 #[allow(dead_code)]
 pub struct JsonSchema {
     pub name: String,
@@ -85,10 +101,15 @@ pub struct JsonSchema {
 #[allow(dead_code)]
 pub struct ChatResponse {
     pub id: String,
+    // Some OpenAI-compatible gateways omit `object`, send `created` as a string,
+    // or leave out `usage`; none of these are needed to run a review.
+    #[serde(default)]
     pub object: String,
+    #[serde(default, deserialize_with = "deserialize_created")]
     pub created: u64,
     pub model: String,
     pub choices: Vec<Choice>,
+    #[serde(default)]
     pub usage: Usage,
 }
 
@@ -100,10 +121,42 @@ pub struct Choice {
     pub finish_reason: String,
 }
 
-#[derive(Debug, Deserialize)]
-#[allow(dead_code)]
+#[derive(Debug, Deserialize, Default)]
 pub struct Usage {
     pub prompt_tokens: u32,
     pub completion_tokens: u32,
     pub total_tokens: u32,
 }
+
+/// Response of `GET /models`.
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+pub struct ModelList {
+    pub data: Vec<ModelInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+pub struct ModelInfo {
+    pub id: String,
+}
+
+/// Accepts `created` as a number or as a numeric string.
+fn deserialize_created<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Created {
+        Number(u64),
+        Text(String),
+    }
+
+    match Created::deserialize(deserializer)? {
+        Created::Number(value) => Ok(value),
+        Created::Text(text) => text.trim().parse().map_err(|_| {
+            serde::de::Error::custom(format!("invalid `created` timestamp: {}", text))
+        }),
+    }
+}
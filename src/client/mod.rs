@@ -1,14 +1,31 @@
 pub mod dto;
 
 use anyhow::{Context, Result};
-use dto::{ChatRequest, ChatResponse};
+use dto::{ChatRequest, ChatResponse, ModelList};
 #[cfg(test)]
-use dto::{JsonSchema, Message, ResponseFormat};
+use dto::{JsonSchema, Message, ReasoningEffort, ResponseFormat};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use std::time::Duration;
+
+/// How many times a well-formed response with no choices is re-requested
+/// before giving up. Some gateways return one transiently under load.
+const EMPTY_CHOICES_RETRIES: u32 = 2;
 
 pub struct OpenAIClient {
     api_key: String,
     base_url: String,
+    extra_headers: HeaderMap,
     client: reqwest::Client,
+    /// Delay before the first empty-choices retry; doubled for each further one.
+    empty_choices_backoff: Duration,
+    /// Every request fails before it is sent (`--offline`).
+    offline: bool,
+}
+
+/// Outcome of one chat completion request.
+enum ChatAttempt {
+    Response(ChatResponse),
+    EmptyChoices,
 }
 
 impl OpenAIClient {
@@ -16,16 +33,97 @@ impl OpenAIClient {
         Self {
             api_key,
             base_url: "https://api.openai.com/v1".to_string(),
+            extra_headers: HeaderMap::new(),
             client: reqwest::Client::new(),
+            empty_choices_backoff: Duration::from_secs(1),
+            offline: false,
         }
     }
 
+    /// Makes every request fail without touching the network.
+    pub fn offline(mut self) -> Self {
+        self.offline = true;
+        self
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+
+    fn ensure_online(&self) -> Result<()> {
+        if self.offline {
+            anyhow::bail!(
+                "Refusing to contact the API: network access is disabled (--offline or BLART_OFFLINE)"
+            );
+        }
+        Ok(())
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
     pub fn with_base_url(mut self, base_url: String) -> Self {
         self.base_url = base_url;
         self
     }
 
+    /// Adds a header sent with every request, e.g. `HTTP-Referer` for OpenRouter.
+    /// A later header with the same name replaces an earlier one.
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.extra_headers.insert(name, value);
+        self
+    }
+
+    pub async fn list_models(&self) -> Result<ModelList> {
+        self.ensure_online()?;
+        let url = format!("{}/models", self.base_url);
+
+        let response = self
+            .client
+            .get(&url)
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .headers(self.extra_headers.clone())
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await?;
+            anyhow::bail!("OpenAI API error ({}): {}", status, error_text);
+        }
+
+        let body = response.text().await?;
+        serde_json::from_str::<ModelList>(&body)
+            .with_context(|| format!("Failed to parse model list: {}", body))
+    }
+
     pub async fn chat(&self, request: ChatRequest) -> Result<ChatResponse> {
+        let mut delay = self.empty_choices_backoff;
+        for retry in 1..=EMPTY_CHOICES_RETRIES {
+            match self.send_chat(&request).await? {
+                ChatAttempt::Response(response) => return Ok(response),
+                ChatAttempt::EmptyChoices => {
+                    eprintln!(
+                        "API returned no choices; retrying in {:?} ({}/{})",
+                        delay, retry, EMPTY_CHOICES_RETRIES
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+            }
+        }
+        match self.send_chat(&request).await? {
+            ChatAttempt::Response(response) => Ok(response),
+            ChatAttempt::EmptyChoices => anyhow::bail!(
+                "OpenAI API error: empty choices array (after {} retries)",
+                EMPTY_CHOICES_RETRIES
+            ),
+        }
+    }
+
+    async fn send_chat(&self, request: &ChatRequest) -> Result<ChatAttempt> {
+        self.ensure_online()?;
         let url = format!("{}/chat/completions", self.base_url);
 
         let response = self
@@ -33,7 +131,8 @@ impl OpenAIClient {
             .post(&url)
             .header("Authorization", format!("Bearer {}", self.api_key))
             .header("Content-Type", "application/json")
-            .json(&request)
+            .headers(self.extra_headers.clone())
+            .json(request)
             .send()
             .await?;
 
@@ -62,33 +161,33 @@ impl OpenAIClient {
             anyhow::bail!("OpenAI API error: {}", error);
         }
 
-        if let Some(choices) = value.get("choices").and_then(|c| c.as_array()) {
-            if let Some(choice) = choices.first() {
-                let finish_reason = choice.get("finish_reason").and_then(|v| v.as_str());
-                let content = choice.get("message").and_then(|m| m.get("content"));
-                let tool_calls = choice.get("message").and_then(|m| m.get("tool_calls"));
-                let has_content = content.is_some_and(|v| !v.is_null());
-                let has_tool_calls = tool_calls.is_some_and(|v| !v.is_null());
-
-                if finish_reason == Some("error") || (!has_content && !has_tool_calls) {
-                    if let Some(choice_error) = choice.get("error").or_else(|| {
-                        choice
-                            .get("message")
-                            .and_then(|message| message.get("error"))
-                    }) {
-                        if let Ok(formatted) = serde_json::to_string_pretty(choice_error) {
-                            anyhow::bail!("OpenAI API error: {}", formatted);
-                        }
-                        anyhow::bail!("OpenAI API error: {}", choice_error);
+        if let Some(choices) = value.get("choices").and_then(|c| c.as_array())
+            && let Some(choice) = choices.first()
+        {
+            let finish_reason = choice.get("finish_reason").and_then(|v| v.as_str());
+            let content = choice.get("message").and_then(|m| m.get("content"));
+            let tool_calls = choice.get("message").and_then(|m| m.get("tool_calls"));
+            let has_content = content.is_some_and(|v| !v.is_null());
+            let has_tool_calls = tool_calls.is_some_and(|v| !v.is_null());
+
+            if finish_reason == Some("error") || (!has_content && !has_tool_calls) {
+                if let Some(choice_error) = choice.get("error").or_else(|| {
+                    choice
+                        .get("message")
+                        .and_then(|message| message.get("error"))
+                }) {
+                    if let Ok(formatted) = serde_json::to_string_pretty(choice_error) {
+                        anyhow::bail!("OpenAI API error: {}", formatted);
                     }
+                    anyhow::bail!("OpenAI API error: {}", choice_error);
+                }
 
-                    if let Ok(formatted) = serde_json::to_string_pretty(choice) {
-                        anyhow::bail!(
-                            "OpenAI API error: finish_reason={} response={}",
-                            finish_reason.unwrap_or("unknown"),
-                            formatted
-                        );
-                    }
+                if let Ok(formatted) = serde_json::to_string_pretty(choice) {
+                    anyhow::bail!(
+                        "OpenAI API error: finish_reason={} response={}",
+                        finish_reason.unwrap_or("unknown"),
+                        formatted
+                    );
                 }
             }
         }
@@ -97,10 +196,10 @@ impl OpenAIClient {
             .with_context(|| format!("Failed to parse chat response: {}", body))?;
 
         if chat_response.choices.is_empty() {
-            anyhow::bail!("OpenAI API error: empty choices array");
+            return Ok(ChatAttempt::EmptyChoices);
         }
 
-        Ok(chat_response)
+        Ok(ChatAttempt::Response(chat_response))
     }
 }
 
@@ -161,6 +260,8 @@ mod tests {
             temperature: None,
             max_tokens: None,
             reasoning_effort: None,
+            prompt_cache_key: None,
+            stop: None,
         };
 
         // Send the request
@@ -178,6 +279,257 @@ mod tests {
         assert_eq!(response.usage.total_tokens, 21);
     }
 
+    #[tokio::test]
+    async fn test_custom_headers_are_sent() {
+        let mock_server = MockServer::start().await;
+
+        let mock_response = serde_json::json!({
+            "id": "chatcmpl-789",
+            "object": "chat.completion",
+            "created": 1677652288,
+            "model": "gpt-4",
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": "ok"
+                },
+                "finish_reason": "stop"
+            }],
+            "usage": {
+                "prompt_tokens": 1,
+                "completion_tokens": 1,
+                "total_tokens": 2
+            }
+        });
+
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(matchers::header("x-title", "blart"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(mock_response))
+            .mount(&mock_server)
+            .await;
+
+        let client = OpenAIClient::new("test-api-key".to_string())
+            .with_base_url(mock_server.uri())
+            .with_header(
+                HeaderName::from_static("x-title"),
+                HeaderValue::from_static("blart"),
+            );
+
+        let request = ChatRequest {
+            model: "gpt-4".to_string(),
+            messages: vec![Message {
+                role: "user".to_string(),
+                content: Some("Hello!".to_string()),
+                tool_calls: None,
+                tool_call_id: None,
+            }],
+            response_format: None,
+            tools: None,
+            tool_choice: None,
+            temperature: None,
+            max_tokens: None,
+            reasoning_effort: None,
+            prompt_cache_key: None,
+            stop: None,
+        };
+
+        let response = client.chat(request).await.unwrap();
+        assert_eq!(response.id, "chatcmpl-789");
+    }
+
+    fn hello_request() -> ChatRequest {
+        ChatRequest {
+            model: "gpt-4".to_string(),
+            messages: vec![Message {
+                role: "user".to_string(),
+                content: Some("Hello!".to_string()),
+                tool_calls: None,
+                tool_call_id: None,
+            }],
+            response_format: None,
+            tools: None,
+            tool_choice: None,
+            temperature: None,
+            max_tokens: None,
+            reasoning_effort: None,
+            prompt_cache_key: None,
+            stop: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_empty_choices_are_retried() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "chatcmpl-empty",
+                "model": "gpt-4",
+                "choices": []
+            })))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "chatcmpl-ok",
+                "model": "gpt-4",
+                "choices": [{
+                    "index": 0,
+                    "message": { "role": "assistant", "content": "ok" },
+                    "finish_reason": "stop"
+                }]
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let mut client =
+            OpenAIClient::new("test-api-key".to_string()).with_base_url(mock_server.uri());
+        client.empty_choices_backoff = Duration::ZERO;
+
+        let response = client.chat(hello_request()).await.unwrap();
+        assert_eq!(response.id, "chatcmpl-ok");
+    }
+
+    #[tokio::test]
+    async fn test_persistent_empty_choices_fail_after_retries() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "chatcmpl-empty",
+                "model": "gpt-4",
+                "choices": []
+            })))
+            .expect(1 + EMPTY_CHOICES_RETRIES as u64)
+            .mount(&mock_server)
+            .await;
+
+        let mut client =
+            OpenAIClient::new("test-api-key".to_string()).with_base_url(mock_server.uri());
+        client.empty_choices_backoff = Duration::ZERO;
+
+        let error = client.chat(hello_request()).await.unwrap_err().to_string();
+        assert!(error.contains("empty choices array (after 2 retries)"));
+    }
+
+    #[tokio::test]
+    async fn test_prompt_cache_key_is_sent() {
+        let mock_server = MockServer::start().await;
+
+        let mock_response = serde_json::json!({
+            "id": "chatcmpl-cache",
+            "model": "gpt-4",
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": "ok"
+                },
+                "finish_reason": "stop"
+            }]
+        });
+
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(matchers::body_partial_json(
+                serde_json::json!({ "prompt_cache_key": "blart-review" }),
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(mock_response))
+            .mount(&mock_server)
+            .await;
+
+        let client = OpenAIClient::new("test-api-key".to_string()).with_base_url(mock_server.uri());
+
+        let request = ChatRequest {
+            model: "gpt-4".to_string(),
+            messages: vec![Message {
+                role: "user".to_string(),
+                content: Some("Hello!".to_string()),
+                tool_calls: None,
+                tool_call_id: None,
+            }],
+            response_format: None,
+            tools: None,
+            tool_choice: None,
+            temperature: None,
+            max_tokens: None,
+            reasoning_effort: None,
+            prompt_cache_key: Some("blart-review".to_string()),
+            stop: None,
+        };
+
+        let response = client.chat(request).await.unwrap();
+        assert_eq!(response.id, "chatcmpl-cache");
+    }
+
+    #[test]
+    fn test_stop_is_serialized_only_when_set() {
+        let request = serde_json::to_value(hello_request()).unwrap();
+        assert!(request.get("stop").is_none());
+
+        let mut request = hello_request();
+        request.stop = Some(vec!["END".to_string(), "\n\n\n".to_string()]);
+        assert_eq!(
+            serde_json::to_value(request).unwrap()["stop"],
+            serde_json::json!(["END", "\n\n\n"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reasoning_effort_serializes_as_string_or_object() {
+        let mock_server = MockServer::start().await;
+
+        let mock_response = serde_json::json!({
+            "id": "chatcmpl-reasoning",
+            "model": "gpt-4",
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": "ok" },
+                "finish_reason": "stop"
+            }]
+        });
+
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(matchers::body_partial_json(
+                serde_json::json!({ "reasoning_effort": "high" }),
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(mock_response.clone()))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(matchers::body_partial_json(serde_json::json!({
+                "reasoning_effort": { "effort": "high", "max_tokens": 2048 }
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(mock_response))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let client = OpenAIClient::new("test-api-key".to_string()).with_base_url(mock_server.uri());
+
+        let mut request = hello_request();
+        request.reasoning_effort = Some(ReasoningEffort::Level("high".to_string()));
+        client.chat(request).await.unwrap();
+
+        let mut request = hello_request();
+        request.reasoning_effort = Some(ReasoningEffort::Config {
+            effort: "high".to_string(),
+            max_tokens: 2048,
+        });
+        client.chat(request).await.unwrap();
+    }
+
     #[tokio::test]
     async fn test_structured_output() {
         // Start a mock server
@@ -247,6 +599,8 @@ mod tests {
             temperature: Some(0.7),
             max_tokens: Some(100),
             reasoning_effort: None,
+            prompt_cache_key: None,
+            stop: None,
         };
 
         // Send the request
@@ -303,6 +657,8 @@ mod tests {
             temperature: None,
             max_tokens: None,
             reasoning_effort: None,
+            prompt_cache_key: None,
+            stop: None,
         };
 
         // Send the request and expect an error
@@ -338,6 +694,8 @@ mod tests {
             temperature: None,
             max_tokens: None,
             reasoning_effort: None,
+            prompt_cache_key: None,
+            stop: None,
         };
 
         let result = client.chat(request).await;
@@ -379,6 +737,8 @@ mod tests {
             temperature: None,
             max_tokens: None,
             reasoning_effort: None,
+            prompt_cache_key: None,
+            stop: None,
         };
 
         let result = client.chat(request).await;
@@ -415,6 +775,8 @@ mod tests {
             temperature: None,
             max_tokens: None,
             reasoning_effort: None,
+            prompt_cache_key: None,
+            stop: None,
         };
 
         let result = client.chat(request).await;
@@ -422,4 +784,117 @@ mod tests {
         let error_message = result.unwrap_err().to_string();
         assert!(error_message.contains("truncated response"));
     }
+
+    #[tokio::test]
+    async fn test_off_spec_response_fields_are_tolerated() {
+        let mock_server = MockServer::start().await;
+
+        // No `object`, a string `created`, and no `usage`.
+        let mock_response = serde_json::json!({
+            "id": "gen-1",
+            "created": "1677652288",
+            "model": "gpt-4",
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": "ok"
+                },
+                "finish_reason": "stop"
+            }]
+        });
+
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(mock_response))
+            .mount(&mock_server)
+            .await;
+
+        let client = OpenAIClient::new("test-api-key".to_string()).with_base_url(mock_server.uri());
+
+        let request = ChatRequest {
+            model: "gpt-4".to_string(),
+            messages: vec![Message {
+                role: "user".to_string(),
+                content: Some("Hello!".to_string()),
+                tool_calls: None,
+                tool_call_id: None,
+            }],
+            response_format: None,
+            tools: None,
+            tool_choice: None,
+            temperature: None,
+            max_tokens: None,
+            reasoning_effort: None,
+            prompt_cache_key: None,
+            stop: None,
+        };
+
+        let response = client.chat(request).await.unwrap();
+        assert_eq!(response.created, 1677652288);
+        assert_eq!(response.object, "");
+        assert_eq!(response.usage.total_tokens, 0);
+        assert_eq!(response.choices[0].message.content.as_deref(), Some("ok"));
+    }
+
+    #[tokio::test]
+    async fn test_list_models() {
+        let mock_server = MockServer::start().await;
+
+        let mock_response = serde_json::json!({
+            "object": "list",
+            "data": [
+                {"id": "gpt-4", "object": "model", "owned_by": "openai"},
+                {"id": "gpt-4o-mini", "object": "model", "owned_by": "openai"}
+            ]
+        });
+
+        Mock::given(method("GET"))
+            .and(path("/models"))
+            .and(matchers::header("authorization", "Bearer test-api-key"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(mock_response))
+            .mount(&mock_server)
+            .await;
+
+        let client = OpenAIClient::new("test-api-key".to_string()).with_base_url(mock_server.uri());
+
+        let models = client.list_models().await.unwrap();
+        let ids: Vec<&str> = models.data.iter().map(|model| model.id.as_str()).collect();
+        assert_eq!(ids, vec!["gpt-4", "gpt-4o-mini"]);
+    }
+
+    #[tokio::test]
+    async fn test_list_models_reports_status_errors() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/models"))
+            .respond_with(ResponseTemplate::new(404).set_body_string("not found"))
+            .mount(&mock_server)
+            .await;
+
+        let client = OpenAIClient::new("test-api-key".to_string()).with_base_url(mock_server.uri());
+
+        let error_message = client.list_models().await.unwrap_err().to_string();
+        assert!(error_message.contains("404"));
+    }
+
+    #[tokio::test]
+    async fn test_offline_client_sends_nothing() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(matchers::any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+
+        let client = OpenAIClient::new("test-api-key".to_string())
+            .with_base_url(mock_server.uri())
+            .offline();
+
+        let error_message = client.chat(hello_request()).await.unwrap_err().to_string();
+        assert!(error_message.contains("network access is disabled"));
+        assert!(client.list_models().await.is_err());
+    }
 }
@@ -0,0 +1,736 @@
+use regex::Regex;
+use std::collections::{BTreeSet, HashMap};
+
+/// New-file line numbers (1-based) of every `+` line in a unified diff, keyed
+/// by the post-change path as git prints it (relative to the repo root).
+pub type ChangedLines = HashMap<String, BTreeSet<usize>>;
+
+/// A file git reports as moved (`rename from`/`rename to`), possibly with
+/// edits on top.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rename {
+    pub from: String,
+    pub to: String,
+}
+
+/// A change to what kind of file a path is, which the hunks do not show: its
+/// executable bit, or whether it is a symlink or a submodule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModeChange {
+    pub path: String,
+    /// What changed, e.g. "became executable (100644 -> 100755)".
+    pub description: String,
+}
+
+/// How a file changed, as `git diff` reports it in the extended header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeType {
+    Added,
+    Deleted,
+    Modified,
+    Renamed,
+    Copied,
+}
+
+/// One file's section of a unified diff: the `diff --git` header block
+/// followed by its hunks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileDiff<'a> {
+    pub header: Vec<&'a str>,
+    pub hunks: Vec<Hunk<'a>>,
+}
+
+/// A single `@@` hunk: its header line and body lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk<'a> {
+    pub header: &'a str,
+    pub lines: Vec<&'a str>,
+}
+
+impl FileDiff<'_> {
+    /// The pre-change path (`--- a/...`), or `None` for added files.
+    pub fn old_path(&self) -> Option<&str> {
+        self.header
+            .iter()
+            .find_map(|line| line.strip_prefix("--- "))
+            .and_then(|path| path.strip_prefix("a/"))
+    }
+
+    /// The post-change path (`+++ b/...`), or `None` for deleted files.
+    pub fn new_path(&self) -> Option<&str> {
+        self.header
+            .iter()
+            .find_map(|line| line.strip_prefix("+++ "))
+            .and_then(|path| path.strip_prefix("b/"))
+    }
+
+    /// The value of the extended header line starting with `prefix`, e.g.
+    /// `new mode ` or `rename to `.
+    fn header_value(&self, prefix: &str) -> Option<&str> {
+        self.header
+            .iter()
+            .find_map(|line| line.strip_prefix(prefix))
+    }
+
+    /// The file's path, also for changes without `---`/`+++` lines (mode-only
+    /// changes), where it comes from `rename to` or the `diff --git` line.
+    pub fn path(&self) -> Option<&str> {
+        self.new_path()
+            .or_else(|| self.old_path())
+            .or_else(|| self.header_value("rename to "))
+            .or_else(|| {
+                let paths = self.header_value("diff --git a/")?;
+                paths.rsplit_once(" b/").map(|(_, new)| new)
+            })
+    }
+
+    /// How the file changed. Edits to a renamed or copied file count as
+    /// part of the rename or copy; a mode-only change is a modification.
+    pub fn change_type(&self) -> ChangeType {
+        if self.header_value("new file mode ").is_some() {
+            ChangeType::Added
+        } else if self.header_value("deleted file mode ").is_some() {
+            ChangeType::Deleted
+        } else if self.header_value("rename from ").is_some() {
+            ChangeType::Renamed
+        } else if self.header_value("copy from ").is_some() {
+            ChangeType::Copied
+        } else {
+            ChangeType::Modified
+        }
+    }
+}
+
+impl Hunk<'_> {
+    /// 1-based start line of the hunk in the new file.
+    pub fn new_start(&self) -> Option<usize> {
+        let ranges = self.header.strip_prefix("@@ ")?;
+        let new_range = ranges
+            .split_whitespace()
+            .find(|part| part.starts_with('+'))?;
+        new_range[1..].split(',').next()?.parse().ok()
+    }
+}
+
+/// Splits a unified diff into per-file sections. Text before the first
+/// `diff --git` line (git never emits any) is kept as a header-only section.
+pub fn parse_diff(diff: &str) -> Vec<FileDiff<'_>> {
+    let mut files: Vec<FileDiff> = Vec::new();
+    for line in diff.lines() {
+        if line.starts_with("diff --git ") || files.is_empty() {
+            files.push(FileDiff {
+                header: Vec::new(),
+                hunks: Vec::new(),
+            });
+        }
+        let file = files.last_mut().expect("a file section was just pushed");
+        if line.starts_with("@@ ") {
+            file.hunks.push(Hunk {
+                header: line,
+                lines: Vec::new(),
+            });
+        } else if let Some(hunk) = file.hunks.last_mut() {
+            hunk.lines.push(line);
+        } else {
+            file.header.push(line);
+        }
+    }
+    files
+}
+
+/// Inverse of `parse_diff`; the output always ends with a newline.
+pub fn render_diff(files: &[FileDiff]) -> String {
+    let mut output = String::new();
+    for file in files {
+        for line in &file.header {
+            output.push_str(line);
+            output.push('\n');
+        }
+        for hunk in &file.hunks {
+            output.push_str(hunk.header);
+            output.push('\n');
+            for line in &hunk.lines {
+                output.push_str(line);
+                output.push('\n');
+            }
+        }
+    }
+    output
+}
+
+/// The renames in a diff produced with rename detection (`-M`).
+pub fn renames(diff: &str) -> Vec<Rename> {
+    parse_diff(diff)
+        .iter()
+        .filter_map(|file| {
+            Some(Rename {
+                from: file.header_value("rename from ")?.to_string(),
+                to: file.header_value("rename to ")?.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// The files git shows as `Binary files ... differ` rather than as hunks,
+/// with how each changed.
+pub fn binary_files(diff: &str) -> Vec<(String, ChangeType)> {
+    parse_diff(diff)
+        .iter()
+        .filter(|file| {
+            file.header
+                .iter()
+                .any(|line| line.starts_with("Binary files "))
+        })
+        .filter_map(|file| Some((file.path()?.to_string(), file.change_type())))
+        .collect()
+}
+
+const EXECUTABLE_MODE: &str = "100755";
+const SYMLINK_MODE: &str = "120000";
+const SUBMODULE_MODE: &str = "160000";
+
+/// The mode changes in a diff, read from its extended headers: permission
+/// changes, symlinks and submodules being added, removed or repointed, and
+/// files turning into or out of either. New and deleted regular files are
+/// left out, since the hunks show those plainly.
+pub fn mode_changes(diff: &str) -> Vec<ModeChange> {
+    parse_diff(diff)
+        .iter()
+        .filter_map(|file| {
+            let description = describe_mode_change(file)?;
+            Some(ModeChange {
+                path: file.path()?.to_string(),
+                description,
+            })
+        })
+        .collect()
+}
+
+fn describe_mode_change(file: &FileDiff) -> Option<String> {
+    if let (Some(old), Some(new)) = (
+        file.header_value("old mode "),
+        file.header_value("new mode "),
+    ) {
+        return Some(match (old, new) {
+            ("100644", EXECUTABLE_MODE) => format!("became executable ({} -> {})", old, new),
+            (EXECUTABLE_MODE, "100644") => format!("is no longer executable ({} -> {})", old, new),
+            _ => format!(
+                "changed from {} to {} ({} -> {})",
+                mode_name(old),
+                mode_name(new),
+                old,
+                new
+            ),
+        });
+    }
+    if let Some(mode) = file.header_value("new file mode ")
+        && (mode == SYMLINK_MODE || mode == SUBMODULE_MODE)
+    {
+        return Some(format!("added as {}", mode_name(mode)));
+    }
+    if let Some(mode) = file.header_value("deleted file mode ")
+        && (mode == SYMLINK_MODE || mode == SUBMODULE_MODE)
+    {
+        return Some(format!("removed ({})", mode_name(mode)));
+    }
+    // `index <old>..<new> <mode>` when the mode stays the same.
+    let (hashes, mode) = file.header_value("index ")?.split_once(' ')?;
+    match mode {
+        SYMLINK_MODE => Some("symlink target changed".to_string()),
+        SUBMODULE_MODE => {
+            let (old, new) = hashes.split_once("..")?;
+            Some(format!("submodule moved from commit {} to {}", old, new))
+        }
+        _ => None,
+    }
+}
+
+fn mode_name(mode: &str) -> &str {
+    match mode {
+        "100644" => "a regular file",
+        EXECUTABLE_MODE => "an executable file",
+        SYMLINK_MODE => "a symlink",
+        SUBMODULE_MODE => "a submodule",
+        mode => mode,
+    }
+}
+
+pub fn added_lines(diff: &str) -> ChangedLines {
+    let mut changed = ChangedLines::new();
+    for file in parse_diff(diff) {
+        let Some(path) = file.new_path() else {
+            continue;
+        };
+        for hunk in &file.hunks {
+            let mut next_line = hunk.new_start().unwrap_or(0);
+            for line in &hunk.lines {
+                match line.as_bytes().first() {
+                    Some(b'+') => {
+                        changed
+                            .entry(path.to_string())
+                            .or_default()
+                            .insert(next_line);
+                        next_line += 1;
+                    }
+                    Some(b' ') => next_line += 1,
+                    // Blank context lines can lose their leading space in some tools.
+                    None => next_line += 1,
+                    _ => {}
+                }
+            }
+        }
+    }
+    changed
+}
+
+/// Replaces the body of every hunk longer than `max_lines` with a one-line
+/// placeholder, keeping file and hunk headers so the model still sees what
+/// changed where.
+pub fn elide_large_hunks(diff: &str, max_lines: usize) -> String {
+    let parsed = parse_diff(diff);
+    let placeholders: Vec<Option<String>> = parsed
+        .iter()
+        .flat_map(|file| &file.hunks)
+        .map(|hunk| {
+            (hunk.lines.len() > max_lines).then(|| {
+                format!(
+                    "[hunk of {} lines elided; use read_file to inspect]",
+                    hunk.lines.len()
+                )
+            })
+        })
+        .collect();
+
+    let mut files = parsed;
+    let hunks = files.iter_mut().flat_map(|file| &mut file.hunks);
+    for (hunk, placeholder) in hunks.zip(&placeholders) {
+        if let Some(placeholder) = placeholder {
+            hunk.lines = vec![placeholder.as_str()];
+        }
+    }
+    render_diff(&files)
+}
+
+/// Keeps the first `max_hunks` hunks of each file and ends the last of them
+/// with a line saying how many more were dropped, so one broadly edited file
+/// cannot take up most of the diff.
+pub fn limit_hunks_per_file(diff: &str, max_hunks: usize) -> String {
+    let parsed = parse_diff(diff);
+    let markers: Vec<Option<String>> = parsed
+        .iter()
+        .map(|file| {
+            (file.hunks.len() > max_hunks).then(|| {
+                format!(
+                    "[{} more hunk(s) elided; use read_file to inspect]",
+                    file.hunks.len() - max_hunks
+                )
+            })
+        })
+        .collect();
+
+    let mut files = parsed;
+    for (file, marker) in files.iter_mut().zip(&markers) {
+        if let Some(marker) = marker {
+            file.hunks.truncate(max_hunks);
+            if let Some(last) = file.hunks.last_mut() {
+                last.lines.push(marker.as_str());
+            }
+        }
+    }
+    render_diff(&files)
+}
+
+/// Keeps only the hunks whose header (including the function context git
+/// prints after the second `@@`) or lines mention `function` as a whole word,
+/// and drops files left without hunks.
+pub fn hunks_mentioning(diff: &str, function: &str) -> String {
+    let pattern = Regex::new(&format!(r"\b{}\b", regex::escape(function)))
+        .expect("an escaped name is a valid regex");
+    let mut files = parse_diff(diff);
+    for file in &mut files {
+        file.hunks.retain(|hunk| {
+            pattern.is_match(hunk.header) || hunk.lines.iter().any(|line| pattern.is_match(line))
+        });
+    }
+    files.retain(|file| !file.hunks.is_empty());
+    render_diff(&files)
+}
+
+/// Widest `+`/`-` bar in a `diff_stat` line; larger changes are scaled down.
+const MAX_STAT_BAR: usize = 40;
+
+/// A `git diff --stat` for `diff` itself, for diffs narrowed after git
+/// produced them (`--function`): one ` path | N ++--` line per file, then
+/// the totals. Renames show as `old => new`.
+pub fn diff_stat(diff: &str) -> String {
+    let files: Vec<(String, usize, usize)> = parse_diff(diff)
+        .iter()
+        .map(|file| {
+            let name = match (file.old_path(), file.new_path()) {
+                (Some(old), Some(new)) if old != new => format!("{} => {}", old, new),
+                _ => file.path().unwrap_or_default().to_string(),
+            };
+            let count = |sign| {
+                file.hunks
+                    .iter()
+                    .flat_map(|hunk| &hunk.lines)
+                    .filter(|line| line.starts_with(sign))
+                    .count()
+            };
+            (name, count('+'), count('-'))
+        })
+        .collect();
+    if files.is_empty() {
+        return String::new();
+    }
+    let name_width = files
+        .iter()
+        .map(|(name, ..)| name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let max_changes = files
+        .iter()
+        .map(|(_, added, removed)| added + removed)
+        .max()
+        .unwrap_or(0);
+    let count_width = max_changes.to_string().len();
+    // Scaled like git's bars: rounded, but never to nothing.
+    let scale = |count: usize| match count {
+        0 => 0,
+        _ if max_changes <= MAX_STAT_BAR => count,
+        _ => ((count * MAX_STAT_BAR + max_changes / 2) / max_changes).max(1),
+    };
+    let mut output = String::new();
+    for (name, added, removed) in &files {
+        output.push_str(&format!(
+            " {:name_width$} | {:>count_width$} {}{}\n",
+            name,
+            added + removed,
+            "+".repeat(scale(*added)),
+            "-".repeat(scale(*removed)),
+        ));
+    }
+    let plural = |count: usize, one: &str, many: &str| {
+        format!("{} {}", count, if count == 1 { one } else { many })
+    };
+    let insertions: usize = files.iter().map(|(_, added, _)| added).sum();
+    let deletions: usize = files.iter().map(|(.., removed)| removed).sum();
+    let mut totals = vec![plural(files.len(), "file changed", "files changed")];
+    if insertions > 0 {
+        totals.push(plural(insertions, "insertion(+)", "insertions(+)"));
+    }
+    if deletions > 0 {
+        totals.push(plural(deletions, "deletion(-)", "deletions(-)"));
+    }
+    output.push_str(&format!(" {}", totals.join(", ")));
+    output
+}
+
+/// Checks that `patch` is a unified diff `git apply` could read: `---`/`+++`
+/// file headers followed by hunks whose bodies match the line counts in their
+/// `@@` headers. Lines before the first file header (`diff --git`, `index`)
+/// are allowed.
+pub fn validate_unified_diff(patch: &str) -> Result<(), String> {
+    let mut lines = patch.lines();
+    let mut in_file = false;
+    let mut hunks = 0;
+    while let Some(line) = lines.next() {
+        if line.starts_with("--- ") {
+            match lines.next() {
+                Some(next) if next.starts_with("+++ ") => in_file = true,
+                _ => return Err(format!("`{}` is not followed by a `+++` line", line)),
+            }
+        } else if line.starts_with("@@") {
+            if !in_file {
+                return Err("hunk before any `---`/`+++` file header".to_string());
+            }
+            let (mut old, mut new) =
+                hunk_lengths(line).ok_or_else(|| format!("malformed hunk header `{}`", line))?;
+            while old > 0 || new > 0 {
+                let body = lines
+                    .next()
+                    .ok_or_else(|| format!("hunk `{}` ends early", line))?;
+                let (old_step, new_step) = match body.as_bytes().first() {
+                    Some(b' ') | None => (1, 1),
+                    Some(b'-') => (1, 0),
+                    Some(b'+') => (0, 1),
+                    Some(b'\\') => (0, 0),
+                    _ => return Err(format!("unexpected line `{}` in hunk `{}`", body, line)),
+                };
+                old = old
+                    .checked_sub(old_step)
+                    .ok_or_else(|| format!("hunk `{}` has too many removed lines", line))?;
+                new = new
+                    .checked_sub(new_step)
+                    .ok_or_else(|| format!("hunk `{}` has too many added lines", line))?;
+            }
+            hunks += 1;
+        } else if in_file && matches!(line.as_bytes().first(), Some(b'+' | b'-' | b' ')) {
+            return Err(format!("line `{}` is outside any hunk", line));
+        }
+    }
+    if hunks == 0 {
+        return Err("no `@@` hunks".to_string());
+    }
+    Ok(())
+}
+
+/// The old and new line counts of a `@@ -a,b +c,d @@` header; a missing
+/// count means one line.
+fn hunk_lengths(header: &str) -> Option<(usize, usize)> {
+    let ranges = header.strip_prefix("@@ ")?.split(" @@").next()?;
+    let (old, new) = ranges.split_once(' ')?;
+    let length = |range: &str, sign: char| -> Option<usize> {
+        let range = range.strip_prefix(sign)?;
+        match range.split_once(',') {
+            Some((start, length)) => {
+                start.parse::<usize>().ok()?;
+                length.parse().ok()
+            }
+            None => range.parse::<usize>().ok().map(|_| 1),
+        }
+    };
+    Some((length(old, '-')?, length(new, '+')?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TWO_HUNKS: &str = "\
+diff --git a/src/lib.rs b/src/lib.rs
+index 111..222 100644
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,4 @@
+ fn a() {}
+-fn b() {}
++fn b2() {}
++fn c() {}
+ fn d() {}
+@@ -10,2 +11,3 @@ fn d() {}
+ fn e() {}
++fn f() {}
+ fn g() {}
+";
+
+    #[test]
+    fn parse_and_render_round_trip() {
+        let files = parse_diff(TWO_HUNKS);
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].hunks.len(), 2);
+        assert_eq!(files[0].new_path(), Some("src/lib.rs"));
+        assert_eq!(render_diff(&files), TWO_HUNKS);
+    }
+
+    #[test]
+    fn renames_reads_rename_headers() {
+        let diff = format!(
+            "{}{}",
+            "\
+diff --git a/src/old.rs b/src/new.rs
+similarity index 100%
+rename from src/old.rs
+rename to src/new.rs
+",
+            TWO_HUNKS
+        );
+        assert_eq!(
+            renames(&diff),
+            vec![Rename {
+                from: "src/old.rs".to_string(),
+                to: "src/new.rs".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn binary_files_finds_binary_sections_and_their_change_type() {
+        let diff = "\
+diff --git a/assets/logo.png b/assets/logo.png
+new file mode 100644
+index 0000000..1111111
+Binary files /dev/null and b/assets/logo.png differ
+diff --git a/fonts/old.woff2 b/fonts/old.woff2
+deleted file mode 100644
+index 2222222..0000000
+Binary files a/fonts/old.woff2 and /dev/null differ
+";
+        assert_eq!(
+            binary_files(&format!("{}{}", TWO_HUNKS, diff)),
+            [
+                ("assets/logo.png".to_string(), ChangeType::Added),
+                ("fonts/old.woff2".to_string(), ChangeType::Deleted),
+            ]
+        );
+    }
+
+    #[test]
+    fn mode_changes_reads_mode_symlink_and_submodule_headers() {
+        let diff = "\
+diff --git a/run.sh b/run.sh
+old mode 100644
+new mode 100755
+diff --git a/latest b/latest
+new file mode 120000
+index 0000000..e69de29
+--- /dev/null
++++ b/latest
+@@ -0,0 +1 @@
++v2
+\\ No newline at end of file
+diff --git a/vendor/lib b/vendor/lib
+index 1111111..2222222 160000
+--- a/vendor/lib
++++ b/vendor/lib
+@@ -1 +1 @@
+-Subproject commit 1111111
++Subproject commit 2222222
+diff --git a/config b/config
+old mode 120000
+new mode 100644
+";
+        let changes: Vec<(String, String)> = mode_changes(&format!("{}{}", TWO_HUNKS, diff))
+            .into_iter()
+            .map(|change| (change.path, change.description))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                ("run.sh", "became executable (100644 -> 100755)"),
+                ("latest", "added as a symlink"),
+                (
+                    "vendor/lib",
+                    "submodule moved from commit 1111111 to 2222222"
+                ),
+                (
+                    "config",
+                    "changed from a symlink to a regular file (120000 -> 100644)"
+                ),
+            ]
+            .map(|(path, description)| (path.to_string(), description.to_string()))
+        );
+    }
+
+    #[test]
+    fn added_lines_tracks_new_file_line_numbers() {
+        let changed = added_lines(TWO_HUNKS);
+        let lines: Vec<usize> = changed["src/lib.rs"].iter().copied().collect();
+        assert_eq!(lines, vec![2, 3, 12]);
+    }
+
+    #[test]
+    fn added_lines_ignores_deleted_files() {
+        let diff = "\
+diff --git a/old.rs b/old.rs
+deleted file mode 100644
+--- a/old.rs
++++ /dev/null
+@@ -1,1 +0,0 @@
+-fn gone() {}
+";
+        assert!(added_lines(diff).is_empty());
+    }
+
+    #[test]
+    fn elide_large_hunks_keeps_headers_and_small_hunks() {
+        let elided = elide_large_hunks(TWO_HUNKS, 3);
+        assert!(
+            elided
+                .contains("@@ -1,3 +1,4 @@\n[hunk of 5 lines elided; use read_file to inspect]\n")
+        );
+        assert!(elided.contains("@@ -10,2 +11,3 @@ fn d() {}\n fn e() {}\n+fn f() {}\n"));
+        assert!(elided.contains("+++ b/src/lib.rs"));
+        assert_eq!(elide_large_hunks(TWO_HUNKS, 5), TWO_HUNKS);
+    }
+
+    #[test]
+    fn limit_hunks_per_file_marks_dropped_hunks() {
+        let limited = limit_hunks_per_file(TWO_HUNKS, 1);
+        assert!(limited.ends_with(
+            "+fn c() {}\n fn d() {}\n[1 more hunk(s) elided; use read_file to inspect]\n"
+        ));
+        assert!(!limited.contains("fn f()"));
+        assert_eq!(limit_hunks_per_file(TWO_HUNKS, 2), TWO_HUNKS);
+    }
+
+    #[test]
+    fn hunks_mentioning_keeps_matching_hunks_only() {
+        let other_file = "\
+diff --git a/src/other.rs b/src/other.rs
+--- a/src/other.rs
++++ b/src/other.rs
+@@ -1,1 +1,1 @@
+-fn x() {}
++fn y() {}
+";
+        let diff = format!("{}{}", TWO_HUNKS, other_file);
+        let second_hunk = "\
+diff --git a/src/lib.rs b/src/lib.rs
+index 111..222 100644
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -10,2 +11,3 @@ fn d() {}
+ fn e() {}
++fn f() {}
+ fn g() {}
+";
+        assert_eq!(hunks_mentioning(&diff, "f"), second_hunk);
+        assert_eq!(hunks_mentioning(&diff, "b2").matches("@@ -").count(), 1);
+        assert_eq!(hunks_mentioning(&diff, "fn"), diff);
+        assert_eq!(hunks_mentioning(&diff, "missing"), "");
+    }
+
+    #[test]
+    fn diff_stat_counts_the_lines_of_the_diff_given() {
+        let diff = "\
+diff --git a/src/lib.rs b/src/lib.rs
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,2 +1,3 @@
+-fn a() {}
++fn b() {}
++fn c() {}
+ fn d() {}
+diff --git a/old.rs b/src/new.rs
+--- a/old.rs
++++ b/src/new.rs
+@@ -1 +1 @@
+-x
++y
+";
+        assert_eq!(
+            diff_stat(diff),
+            " src/lib.rs           | 3 ++-\n old.rs => src/new.rs | 2 +-\n 2 files changed, 3 insertions(+), 2 deletions(-)"
+        );
+        assert_eq!(diff_stat(""), "");
+
+        let added: String = (0..100).map(|i| format!("+line {}\n", i)).collect();
+        let large = format!(
+            "--- a/big.rs\n+++ b/big.rs\n@@ -1,0 +1,100 @@\n{}-gone\n",
+            added
+        );
+        assert_eq!(
+            diff_stat(&large),
+            format!(
+                " big.rs | 101 {}-\n 1 file changed, 100 insertions(+), 1 deletion(-)",
+                "+".repeat(40)
+            )
+        );
+    }
+
+    #[test]
+    fn validate_unified_diff_checks_headers_and_hunk_lengths() {
+        assert_eq!(validate_unified_diff(TWO_HUNKS), Ok(()));
+        let minimal = "--- a/x.rs\n+++ b/x.rs\n@@ -1 +1 @@\n-a\n+b\n\\ No newline at end of file\n";
+        assert_eq!(validate_unified_diff(minimal), Ok(()));
+
+        assert!(validate_unified_diff("before:\n  a\nafter:\n  b\n").is_err());
+        assert!(validate_unified_diff("--- a/x.rs\n@@ -1 +1 @@\n-a\n+b\n").is_err());
+        assert!(validate_unified_diff("--- a/x.rs\n+++ b/x.rs\n@@ -1,2 +1 @@\n-a\n+b\n").is_err());
+        assert!(
+            validate_unified_diff("--- a/x.rs\n+++ b/x.rs\n@@ -1 +1 @@\n-a\n+b\n+c\n").is_err()
+        );
+        assert!(validate_unified_diff("--- a/x.rs\n+++ b/x.rs\n@@ one @@\n").is_err());
+    }
+}
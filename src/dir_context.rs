@@ -0,0 +1,100 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File names checked, in order, for a directory's README.
+const README_NAMES: &[&str] = &["README.md", "README.rst", "README.txt", "README"];
+/// Characters kept from the start of each README.
+const MAX_EXCERPT_CHARS: usize = 1500;
+/// Characters of excerpts allowed in one prompt; READMEs past this are left out.
+const MAX_TOTAL_CHARS: usize = 6000;
+
+/// The `--directory-context` section: for each directory containing a changed
+/// file, an excerpt of the README in that directory or, failing that, in its
+/// nearest ancestor. Paths are relative to the current directory. Returns
+/// `None` if no README was found.
+pub fn directory_context(files_changed: &[String]) -> Option<String> {
+    let readmes: BTreeSet<PathBuf> = files_changed
+        .iter()
+        .filter_map(|file| nearest_readme(Path::new(file).parent()?))
+        .collect();
+    let excerpts: Vec<(String, String)> = readmes
+        .into_iter()
+        .filter_map(|path| {
+            let contents = fs::read_to_string(&path).ok()?;
+            Some((path.display().to_string(), contents))
+        })
+        .collect();
+    format_excerpts(&excerpts)
+}
+
+fn nearest_readme(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors().find_map(|dir| {
+        README_NAMES
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|path| path.is_file())
+    })
+}
+
+/// Renders `(path, contents)` pairs as excerpts, truncating each to
+/// `MAX_EXCERPT_CHARS` and stopping before the total passes `MAX_TOTAL_CHARS`.
+fn format_excerpts(readmes: &[(String, String)]) -> Option<String> {
+    let mut output = String::new();
+    let mut total = 0;
+    for (path, contents) in readmes {
+        let excerpt: String = contents.trim().chars().take(MAX_EXCERPT_CHARS).collect();
+        let excerpt_chars = excerpt.chars().count();
+        if excerpt.is_empty() || total + excerpt_chars > MAX_TOTAL_CHARS {
+            continue;
+        }
+        total += excerpt_chars;
+        output.push_str(&format!("--- {} ---\n{}\n", path, excerpt));
+        if excerpt_chars < contents.trim().chars().count() {
+            output.push_str("[...]\n");
+        }
+    }
+    (!output.is_empty()).then_some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_readme_walks_up_to_an_ancestor() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("src/foo/bar");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(dir.path().join("src/foo/README.md"), "foo docs").unwrap();
+
+        assert_eq!(
+            nearest_readme(&nested),
+            Some(dir.path().join("src/foo/README.md"))
+        );
+        assert_eq!(
+            nearest_readme(&dir.path().join("src/foo")),
+            Some(dir.path().join("src/foo/README.md"))
+        );
+    }
+
+    #[test]
+    fn format_excerpts_truncates_and_caps_the_total() {
+        let long = "x".repeat(MAX_EXCERPT_CHARS + 10);
+        let readmes: Vec<(String, String)> = (0..5)
+            .map(|i| (format!("dir{}/README.md", i), long.clone()))
+            .collect();
+
+        let output = format_excerpts(&readmes).unwrap();
+        assert_eq!(
+            output.matches("[...]").count(),
+            MAX_TOTAL_CHARS / MAX_EXCERPT_CHARS
+        );
+        assert!(output.contains("--- dir0/README.md ---\n"));
+        assert!(!output.contains("dir4"));
+        assert_eq!(
+            format_excerpts(&[("a".to_string(), " \n".to_string())]),
+            None
+        );
+    }
+}
@@ -0,0 +1,692 @@
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::client::dto::{JsonSchema, ResponseFormat};
+use crate::diff;
+use crate::template::Template;
+use crate::tools;
+
+/// Lines of context shown either side of a finding's line in its snippet.
+const SNIPPET_CONTEXT_LINES: usize = 2;
+
+/// Placeholders available to `--output-template`.
+pub const FINDING_TEMPLATE_FIELDS: &[&str] =
+    &["severity", "file", "line", "message", "confidence", "pass"];
+/// Used when `--output-template` is passed without a template.
+pub const DEFAULT_FINDING_TEMPLATE: &str = "[{{severity}}] {{file}}:{{line}}: {{message}}";
+
+/// A review as returned in `--structured` mode: the usual three keys from
+/// the system prompt plus one entry per raised issue.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Review {
+    pub reasoning: String,
+    #[serde(rename = "substantiveComments")]
+    pub substantive_comments: bool,
+    pub summary: String,
+    pub findings: Vec<Finding>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Finding {
+    pub file: String,
+    pub line: Option<u32>,
+    pub severity: Severity,
+    pub message: String,
+    /// How sure the model is that this is a real problem, in `0.0..=1.0`.
+    pub confidence: f64,
+    /// A unified diff fixing the issue, for `--write-patches`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub patch: Option<String>,
+    /// The `--passes` pass that raised the finding; never set by the model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pass: Option<String>,
+}
+
+/// Ordered from least to most severe.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Minor,
+    Major,
+    Critical,
+}
+
+impl Severity {
+    /// The name used in the JSON schema, e.g. `major`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Minor => "minor",
+            Severity::Major => "major",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+/// The `response_format` that makes the model answer with a `Review`. Only
+/// `--write-patches` asks for each finding's `patch`.
+pub fn response_format(with_patches: bool) -> ResponseFormat {
+    let mut finding = json!({
+        "type": "object",
+        "properties": {
+            "file": { "type": "string" },
+            "line": { "type": ["integer", "null"] },
+            "severity": { "type": "string", "enum": ["minor", "major", "critical"] },
+            "message": { "type": "string" },
+            "confidence": { "type": "number", "minimum": 0, "maximum": 1 }
+        },
+        "required": ["file", "line", "severity", "message", "confidence"],
+        "additionalProperties": false
+    });
+    if with_patches {
+        finding["properties"]["patch"] = json!({ "type": ["string", "null"] });
+        finding["required"]
+            .as_array_mut()
+            .expect("required is an array")
+            .push(json!("patch"));
+    }
+    ResponseFormat {
+        format_type: "json_schema".to_string(),
+        json_schema: Some(JsonSchema {
+            name: "review".to_string(),
+            schema: json!({
+                "type": "object",
+                "properties": {
+                    "reasoning": { "type": "string" },
+                    "substantiveComments": { "type": "boolean" },
+                    "summary": { "type": "string" },
+                    "findings": {
+                        "type": "array",
+                        "items": finding
+                    }
+                },
+                "required": ["reasoning", "substantiveComments", "summary", "findings"],
+                "additionalProperties": false
+            }),
+            strict: Some(true),
+        }),
+    }
+}
+
+/// The types a JSON Schema `type` may name.
+const SCHEMA_TYPES: &[&str] = &[
+    "object", "array", "string", "number", "integer", "boolean", "null",
+];
+
+/// The `response_format` for a `--json-schema-file`. The schema must describe
+/// an object, as structured outputs require. It is sent without `strict`,
+/// since few hand-written schemas meet strict mode's rules.
+pub fn custom_response_format(schema: &str) -> Result<ResponseFormat> {
+    let schema: serde_json::Value =
+        serde_json::from_str(schema).context("The schema is not valid JSON")?;
+    check_schema(&schema, "")?;
+    if schema.get("type") != Some(&json!("object")) {
+        bail!("the schema must have \"type\": \"object\" at the top level");
+    }
+    Ok(ResponseFormat {
+        format_type: "json_schema".to_string(),
+        json_schema: Some(JsonSchema {
+            name: "review".to_string(),
+            schema,
+            strict: None,
+        }),
+    })
+}
+
+/// Checks the keywords that shape a schema (`type`, `properties`, `required`,
+/// `items`, `enum`) have the right form, in `schema` and every subschema, so
+/// mistakes are reported here rather than as an API error. `path` locates
+/// `schema` in the output, like `findings[].file`; it is empty at the top.
+fn check_schema(schema: &serde_json::Value, path: &str) -> Result<()> {
+    let at = if path.is_empty() {
+        "the schema".to_string()
+    } else {
+        format!("`{}`", path)
+    };
+    let Some(schema) = schema.as_object() else {
+        bail!("{} must be a JSON object", at);
+    };
+    if let Some(schema_type) = schema.get("type") {
+        let names: Vec<&serde_json::Value> = match schema_type {
+            serde_json::Value::Array(names) => names.iter().collect(),
+            name => vec![name],
+        };
+        for name in names {
+            if !name
+                .as_str()
+                .is_some_and(|name| SCHEMA_TYPES.contains(&name))
+            {
+                bail!(
+                    "{} has type {}; use one of {}",
+                    at,
+                    name,
+                    SCHEMA_TYPES.join(", ")
+                );
+            }
+        }
+    }
+    let properties = match schema.get("properties") {
+        Some(properties) => Some(
+            properties
+                .as_object()
+                .with_context(|| format!("\"properties\" of {} must be an object", at))?,
+        ),
+        None => None,
+    };
+    if let Some(required) = schema.get("required") {
+        let required = required
+            .as_array()
+            .with_context(|| format!("\"required\" of {} must be an array", at))?;
+        for name in required {
+            let known = name.as_str().is_some_and(|name| {
+                properties.is_some_and(|properties| properties.contains_key(name))
+            });
+            if !known {
+                bail!(
+                    "{} requires {}, which is not one of its properties",
+                    at,
+                    name
+                );
+            }
+        }
+    }
+    if let Some(values) = schema.get("enum")
+        && !values.is_array()
+    {
+        bail!("\"enum\" of {} must be an array", at);
+    }
+    for (name, property) in properties.into_iter().flatten() {
+        let property_path = if path.is_empty() {
+            name.clone()
+        } else {
+            format!("{}.{}", path, name)
+        };
+        check_schema(property, &property_path)?;
+    }
+    if let Some(items) = schema.get("items") {
+        check_schema(items, &format!("{}[]", path))?;
+    }
+    Ok(())
+}
+
+/// Parses the model's final message. Not every backend enforces the schema,
+/// so out-of-range confidences are rejected here rather than filtered silently.
+pub fn parse_review(content: &str) -> Result<Review> {
+    let review: Review = serde_json::from_str(content.trim())
+        .context("Model response does not match the structured review schema")?;
+    for finding in &review.findings {
+        if !(0.0..=1.0).contains(&finding.confidence) {
+            bail!(
+                "Finding in {} has confidence {}, outside 0..=1",
+                finding.file,
+                finding.confidence
+            );
+        }
+    }
+    Ok(review)
+}
+
+impl Review {
+    /// Drops findings the model is less than `min_confidence` sure about.
+    pub fn retain_confident(&mut self, min_confidence: f64) {
+        self.findings
+            .retain(|finding| finding.confidence >= min_confidence);
+    }
+
+    /// Sorts findings by severity, then confidence, most important first,
+    /// and keeps the first `max`. Returns how many were dropped. Findings
+    /// already within the limit keep the model's order.
+    pub fn keep_most_important(&mut self, max: usize) -> usize {
+        if self.findings.len() <= max {
+            return 0;
+        }
+        self.findings.sort_by(|a, b| {
+            b.severity
+                .cmp(&a.severity)
+                .then(b.confidence.total_cmp(&a.confidence))
+        });
+        let dropped = self.findings.len().saturating_sub(max);
+        self.findings.truncate(max);
+        dropped
+    }
+}
+
+/// Combines the reviews of several `--passes` into one, labelling each
+/// finding with its pass and giving each pass its own part of the summary.
+pub fn merge_passes(reviews: Vec<(String, Review)>) -> Review {
+    let mut merged = Review {
+        reasoning: String::new(),
+        substantive_comments: false,
+        summary: String::new(),
+        findings: Vec::new(),
+    };
+    for (pass, review) in reviews {
+        if !merged.summary.is_empty() {
+            merged.reasoning.push_str("\n\n");
+            merged.summary.push_str("\n\n");
+        }
+        merged
+            .reasoning
+            .push_str(&format!("[{}] {}", pass, review.reasoning.trim()));
+        merged
+            .summary
+            .push_str(&format!("### {}\n{}", pass, review.summary.trim()));
+        merged.substantive_comments |= review.substantive_comments;
+        merged
+            .findings
+            .extend(review.findings.into_iter().map(|finding| Finding {
+                pass: Some(pass.clone()),
+                ..finding
+            }));
+    }
+    merged
+}
+
+/// Renders each finding as a GitHub Actions workflow command, which GitHub
+/// shows as an annotation on the pull request diff.
+pub fn github_actions_commands(review: &Review) -> String {
+    let mut output = String::new();
+    for finding in &review.findings {
+        let command = match finding.severity {
+            Severity::Critical => "error",
+            Severity::Major => "warning",
+            Severity::Minor => "notice",
+        };
+        let mut properties = format!("file={}", escape_workflow_property(&finding.file));
+        if let Some(line) = finding.line {
+            properties.push_str(&format!(",line={}", line));
+        }
+        let title = format!(
+            "blart: {:?} (confidence {:.2})",
+            finding.severity, finding.confidence
+        );
+        properties.push_str(&format!(",title={}", escape_workflow_property(&title)));
+        output.push_str(&format!(
+            "::{} {}::{}\n",
+            command,
+            properties,
+            escape_workflow_data(&finding.message)
+        ));
+    }
+    output
+}
+
+/// A "Snippets" section for text output showing the lines around each
+/// finding, so the model does not have to quote code itself. `read` returns
+/// a finding's file as the review saw it: from the working tree, or from the
+/// stash under review. Findings without a line, or whose file or line no
+/// longer exists, are skipped; the result is empty when no finding has a
+/// snippet.
+pub fn format_snippets(review: &Review, read: impl Fn(&str) -> Option<String>) -> String {
+    let mut output = String::new();
+    for finding in &review.findings {
+        let Some(line) = finding.line else {
+            continue;
+        };
+        let Some(snippet) = read(&finding.file).and_then(|contents| {
+            tools::format_snippet(&contents, line as usize, SNIPPET_CONTEXT_LINES)
+        }) else {
+            continue;
+        };
+        output.push_str(&format!(
+            "\n{}:{} ({:?}): {}\n```\n{}```\n",
+            finding.file, line, finding.severity, finding.message, snippet
+        ));
+    }
+    if output.is_empty() {
+        return output;
+    }
+    format!("## Snippets\n{}", output)
+}
+
+/// Renders each finding through an `--output-template`, one per line. `line`
+/// is empty for findings that do not point at a line, and `pass` outside
+/// `--passes`.
+pub fn render_findings(review: &Review, template: &Template) -> String {
+    if review.findings.is_empty() {
+        return "No findings.\n".to_string();
+    }
+    let mut output = String::new();
+    for finding in &review.findings {
+        let line = finding
+            .line
+            .map(|line| line.to_string())
+            .unwrap_or_default();
+        let confidence = format!("{:.2}", finding.confidence);
+        output.push_str(&template.render(&[
+            ("severity", finding.severity.as_str()),
+            ("file", &finding.file),
+            ("line", &line),
+            ("message", &finding.message),
+            ("confidence", &confidence),
+            ("pass", finding.pass.as_deref().unwrap_or("")),
+        ]));
+        output.push('\n');
+    }
+    output
+}
+
+/// Writes each finding's patch to `dir` as `<n>-<file>.patch`, numbered by
+/// the finding's position so names never collide. Patches that are not valid
+/// unified diffs are skipped with a warning. Returns the files written.
+pub fn write_patches(review: &Review, dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut written = Vec::new();
+    for (index, finding) in review.findings.iter().enumerate() {
+        let Some(patch) = &finding.patch else {
+            continue;
+        };
+        if let Err(err) = diff::validate_unified_diff(patch) {
+            eprintln!(
+                "Warning: skipping the patch for finding {} ({}): {}",
+                index + 1,
+                finding.file,
+                err
+            );
+            continue;
+        }
+        if written.is_empty() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let path = dir.join(format!(
+            "{:03}-{}.patch",
+            index + 1,
+            finding.file.replace(['/', '\\'], "-")
+        ));
+        let mut contents = patch.clone();
+        if !contents.ends_with('\n') {
+            contents.push('\n');
+        }
+        fs::write(&path, contents)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Escapes the message part of a workflow command.
+pub fn escape_workflow_data(value: &str) -> String {
+    value
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Escapes a `key=value` property of a workflow command, which additionally
+/// cannot contain the `:` and `,` delimiters.
+fn escape_workflow_property(value: &str) -> String {
+    escape_workflow_data(value)
+        .replace(':', "%3A")
+        .replace(',', "%2C")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESPONSE: &str = r#"{
+        "reasoning": "r",
+        "substantiveComments": true,
+        "summary": "* two issues",
+        "findings": [
+            { "file": "src/a.rs", "line": 3, "severity": "major", "message": "off by one", "confidence": 0.9 },
+            { "file": "src/b.rs", "line": null, "severity": "minor", "message": "naming", "confidence": 0.3 }
+        ]
+    }"#;
+
+    #[test]
+    fn parse_review_reads_findings() {
+        let review = parse_review(RESPONSE).unwrap();
+        assert_eq!(review.findings.len(), 2);
+        assert_eq!(review.findings[0].severity, Severity::Major);
+        assert_eq!(review.findings[1].line, None);
+    }
+
+    #[test]
+    fn response_format_asks_for_patches_only_with_write_patches() {
+        let finding_schema = |with_patches| {
+            let format = response_format(with_patches);
+            format.json_schema.unwrap().schema["properties"]["findings"]["items"].clone()
+        };
+        let without = finding_schema(false);
+        assert!(without["properties"].get("patch").is_none());
+        assert!(
+            !without["required"]
+                .as_array()
+                .unwrap()
+                .contains(&json!("patch"))
+        );
+        let with = finding_schema(true);
+        assert_eq!(
+            with["properties"]["patch"],
+            json!({ "type": ["string", "null"] })
+        );
+        assert!(
+            with["required"]
+                .as_array()
+                .unwrap()
+                .contains(&json!("patch"))
+        );
+    }
+
+    #[test]
+    fn custom_response_format_checks_the_schema() {
+        let schema = r#"{
+            "type": "object",
+            "properties": {
+                "verdict": { "type": "string", "enum": ["approve", "reject"] },
+                "issues": { "type": "array", "items": { "type": "object", "properties": { "line": { "type": ["integer", "null"] } } } }
+            },
+            "required": ["verdict"]
+        }"#;
+        let format = custom_response_format(schema).unwrap();
+        let json_schema = format.json_schema.unwrap();
+        assert_eq!(json_schema.schema["required"], json!(["verdict"]));
+        assert_eq!(json_schema.strict, None);
+
+        let error = |schema: &str| format!("{:#}", custom_response_format(schema).unwrap_err());
+        assert!(error("{").starts_with("The schema is not valid JSON"));
+        assert_eq!(
+            error(r#"{ "type": "array" }"#),
+            "the schema must have \"type\": \"object\" at the top level"
+        );
+        assert_eq!(
+            error(r#"{ "type": "object", "required": ["verdict"], "properties": {} }"#),
+            "the schema requires \"verdict\", which is not one of its properties"
+        );
+        assert!(
+            error(r#"{ "type": "object", "properties": { "issues": { "items": { "type": "int" } } } }"#)
+                .starts_with("`issues[]` has type \"int\"; use one of object,")
+        );
+    }
+
+    #[test]
+    fn retain_confident_drops_low_confidence_findings() {
+        let mut review = parse_review(RESPONSE).unwrap();
+        review.retain_confident(0.5);
+        assert_eq!(review.findings.len(), 1);
+        assert_eq!(review.findings[0].file, "src/a.rs");
+
+        let mut review = parse_review(RESPONSE).unwrap();
+        review.retain_confident(0.0);
+        assert_eq!(review.findings.len(), 2);
+    }
+
+    #[test]
+    fn keep_most_important_ranks_by_severity_then_confidence() {
+        let finding = |file: &str, severity, confidence| Finding {
+            file: file.to_string(),
+            line: None,
+            severity,
+            message: String::new(),
+            confidence,
+            patch: None,
+            pass: None,
+        };
+        let mut review = parse_review(RESPONSE).unwrap();
+        review.findings = vec![
+            finding("a", Severity::Minor, 0.9),
+            finding("b", Severity::Critical, 0.6),
+            finding("c", Severity::Major, 0.5),
+            finding("d", Severity::Critical, 0.8),
+        ];
+
+        assert_eq!(review.keep_most_important(3), 1);
+        let files: Vec<&str> = review.findings.iter().map(|f| f.file.as_str()).collect();
+        assert_eq!(files, ["d", "b", "c"]);
+
+        review.findings.reverse();
+        assert_eq!(review.keep_most_important(3), 0);
+        let files: Vec<&str> = review.findings.iter().map(|f| f.file.as_str()).collect();
+        assert_eq!(files, ["c", "b", "d"]);
+    }
+
+    #[test]
+    fn github_actions_commands_map_severity_and_escape() {
+        let review = Review {
+            reasoning: String::new(),
+            substantive_comments: true,
+            summary: String::new(),
+            findings: vec![
+                Finding {
+                    file: "src/a,b.rs".to_string(),
+                    line: Some(7),
+                    severity: Severity::Critical,
+                    message: "100% wrong:\nsee here".to_string(),
+                    confidence: 0.9,
+                    patch: None,
+                    pass: None,
+                },
+                Finding {
+                    file: "src/c.rs".to_string(),
+                    line: None,
+                    severity: Severity::Minor,
+                    message: "nit".to_string(),
+                    confidence: 0.25,
+                    patch: None,
+                    pass: None,
+                },
+            ],
+        };
+        let output = github_actions_commands(&review);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines[0],
+            "::error file=src/a%2Cb.rs,line=7,title=blart%3A Critical (confidence 0.90)::100%25 wrong:%0Asee here"
+        );
+        assert_eq!(
+            lines[1],
+            "::notice file=src/c.rs,title=blart%3A Minor (confidence 0.25)::nit"
+        );
+    }
+
+    #[test]
+    fn format_snippets_quotes_lines_around_each_finding() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.rs");
+        std::fs::write(&path, "fn a() {}\nfn b() {}\nfn c() {}\nfn d() {}\n").unwrap();
+        let finding = |file: &str, line: Option<u32>| Finding {
+            file: file.to_string(),
+            line,
+            severity: Severity::Major,
+            message: "bad".to_string(),
+            confidence: 0.9,
+            patch: None,
+            pass: None,
+        };
+        let file = path.to_string_lossy();
+        let mut review = parse_review(RESPONSE).unwrap();
+        review.findings = vec![
+            finding(&file, Some(1)),
+            finding(&file, None),
+            finding(&file, Some(40)),
+        ];
+
+        let read = |path: &str| std::fs::read_to_string(path).ok();
+        let snippets = format_snippets(&review, read);
+        assert_eq!(
+            snippets,
+            format!(
+                "## Snippets\n\n{}:1 (Major): bad\n```\n> L1: fn a() {{}}\n  L2: fn b() {{}}\n  L3: fn c() {{}}\n```\n",
+                file
+            )
+        );
+
+        review.findings.truncate(0);
+        assert_eq!(format_snippets(&review, read), "");
+    }
+
+    #[test]
+    fn render_findings_applies_the_template_to_each_finding() {
+        let review = parse_review(RESPONSE).unwrap();
+        let template = Template::parse(DEFAULT_FINDING_TEMPLATE, FINDING_TEMPLATE_FIELDS).unwrap();
+        assert_eq!(
+            render_findings(&review, &template),
+            "[major] src/a.rs:3: off by one\n[minor] src/b.rs:: naming\n"
+        );
+
+        let template =
+            Template::parse("{{confidence}} {{ file }}", FINDING_TEMPLATE_FIELDS).unwrap();
+        assert_eq!(
+            render_findings(&review, &template),
+            "0.90 src/a.rs\n0.30 src/b.rs\n"
+        );
+    }
+
+    #[test]
+    fn parse_review_rejects_out_of_range_confidence() {
+        let response = RESPONSE.replace("0.9", "1.5");
+        let error = parse_review(&response).unwrap_err().to_string();
+        assert!(error.contains("outside 0..=1"));
+    }
+
+    #[test]
+    fn write_patches_skips_malformed_patches() {
+        let dir = tempfile::tempdir().unwrap();
+        let patch = "--- a/src/a.rs\n+++ b/src/a.rs\n@@ -3 +3 @@\n-let x = 1;\n+let x = 0;";
+        let mut review = parse_review(RESPONSE).unwrap();
+        review.findings[0].patch = Some(patch.to_string());
+        review.findings[1].patch = Some("replace `naming` with `name`".to_string());
+
+        let written = write_patches(&review, &dir.path().join("patches")).unwrap();
+        assert_eq!(written, vec![dir.path().join("patches/001-src-a.rs.patch")]);
+        assert_eq!(
+            fs::read_to_string(&written[0]).unwrap(),
+            format!("{}\n", patch)
+        );
+    }
+
+    #[test]
+    fn merge_passes_labels_findings_by_pass() {
+        let security = parse_review(RESPONSE).unwrap();
+        let mut style = parse_review(RESPONSE).unwrap();
+        style.substantive_comments = false;
+        style.findings.truncate(1);
+
+        let merged = merge_passes(vec![
+            ("security".to_string(), security),
+            ("style".to_string(), style),
+        ]);
+        assert!(merged.substantive_comments);
+        assert_eq!(
+            merged.summary,
+            "### security\n* two issues\n\n### style\n* two issues"
+        );
+        let passes: Vec<_> = merged
+            .findings
+            .iter()
+            .map(|finding| finding.pass.as_deref())
+            .collect();
+        assert_eq!(
+            passes,
+            vec![Some("security"), Some("security"), Some("style")]
+        );
+        assert!(
+            serde_json::to_string(&merged)
+                .unwrap()
+                .contains("\"pass\":\"style\"")
+        );
+    }
+}
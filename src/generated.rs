@@ -0,0 +1,88 @@
+use std::io::{BufRead, BufReader, Read};
+
+/// Markers that code generators conventionally put near the top of a file.
+pub const BUILTIN_MARKERS: &[&str] = &["@generated", "DO NOT EDIT", "<auto-generated"];
+/// How many lines from the top of a file are searched for a marker.
+const HEADER_LINES: usize = 10;
+
+/// The markers to look for: the built-ins plus one per non-blank line of
+/// `extra`; lines starting with `#` are comments.
+pub fn markers(extra: &str) -> Vec<String> {
+    BUILTIN_MARKERS
+        .iter()
+        .map(|marker| marker.to_string())
+        .chain(
+            extra
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_string),
+        )
+        .collect()
+}
+
+/// The files in `files` whose first `HEADER_LINES` lines, as `open` reads
+/// them, contain one of `markers`. Files `open` cannot read (e.g. deleted
+/// ones) are not generated.
+pub fn generated_files<R: Read>(
+    files: &[String],
+    markers: &[String],
+    open: impl Fn(&str) -> Option<R>,
+) -> Vec<String> {
+    files
+        .iter()
+        .filter(|file| {
+            let Some(handle) = open(file) else {
+                return false;
+            };
+            let header: Vec<String> = BufReader::new(handle)
+                .lines()
+                .take(HEADER_LINES)
+                .map_while(Result::ok)
+                .collect();
+            has_marker(&header, markers)
+        })
+        .cloned()
+        .collect()
+}
+
+fn has_marker(header: &[String], markers: &[String]) -> bool {
+    header
+        .iter()
+        .any(|line| markers.iter().any(|marker| line.contains(marker.as_str())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_files_checks_only_the_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+        std::fs::write(path("gen.rs"), "// @generated by build.rs\nfn a() {}\n").unwrap();
+        let late_marker = format!("{}// DO NOT EDIT\n", "fn a() {}\n".repeat(HEADER_LINES));
+        std::fs::write(path("late.rs"), late_marker).unwrap();
+        std::fs::write(path("custom.rs"), "# produced by protoc\n").unwrap();
+
+        let files = vec![
+            path("gen.rs"),
+            path("late.rs"),
+            path("custom.rs"),
+            path("missing.rs"),
+        ];
+        let open = |path: &str| std::fs::File::open(path).ok();
+        assert_eq!(
+            generated_files(&files, &markers(""), open),
+            vec![path("gen.rs")]
+        );
+        assert_eq!(
+            generated_files(
+                &files,
+                &markers("# extra markers\nproduced by protoc\n"),
+                open
+            ),
+            vec![path("gen.rs"), path("custom.rs")]
+        );
+    }
+}
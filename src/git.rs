@@ -1,78 +1,440 @@
 use anyhow::{anyhow, Context, Result};
+use std::fs::File;
+use std::io::Read;
 use std::path::Path;
-use std::process::{Command, Stdio};
+use std::process::{Command, Output, Stdio};
+
+use crate::binary::{self, BinaryFile};
+use crate::diff::{self, ModeChange, Rename};
+
+/// What the working tree is compared against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffBase {
+    /// The merge base of HEAD and the named branch.
+    MergeBase(String),
+    /// A commit that must be an ancestor of HEAD, e.g. the HEAD of a previous review.
+    SinceCommit(String),
+    /// HEAD itself, so the diff is exactly the staged and unstaged changes.
+    WorkingTree,
+    /// The branch's configured upstream (`@{upstream}`), through its merge
+    /// base with HEAD unless the mode is two-dot.
+    Upstream(DiffMode),
+    /// The commit `stash@{n}` was created on, diffed against the stash itself
+    /// rather than the working tree.
+    Stash(u32),
+    /// Any tag, branch or commit, used as is rather than through a merge
+    /// base, e.g. the last release.
+    Ref(String),
+}
+
+/// How a branch is compared against, as in `git diff A..B` and `git diff A...B`.
+/// Once the branches have diverged these differ: two-dot also shows, reversed,
+/// whatever landed on the branch since, while three-dot shows only the
+/// changes made on this side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DiffMode {
+    /// Against the branch's tip (`A..B`).
+    TwoDot,
+    /// Against the merge base of the branch and HEAD (`A...B`).
+    ThreeDot,
+}
+
+/// Restrictions applied consistently to every `git diff` blart runs, so the
+/// diff, the stat and the changed-file list always describe the same files.
+#[derive(Debug, Clone, Default)]
+pub struct DiffOptions {
+    pub diff_filter: Option<DiffFilter>,
+    pub test_files: TestFiles,
+    /// Paths, relative to the repository root, left out of the diff.
+    pub excluded_paths: Vec<String>,
+    /// Diff with no context lines (`--unified=0`) instead of five.
+    pub compact: bool,
+    /// Leave deleted files out of the diff (`--diff-filter=d`), listing them
+    /// in `GitData::deleted_files` instead.
+    pub skip_deletions: bool,
+}
+
+impl DiffOptions {
+    fn git_args(&self) -> Vec<String> {
+        let filter = self.diff_filter.as_ref().map_or("", DiffFilter::as_str);
+        let deletions = if self.skip_deletions { "d" } else { "" };
+        if filter.is_empty() && deletions.is_empty() {
+            return Vec::new();
+        }
+        vec![format!("--diff-filter={}{}", filter, deletions)]
+    }
+
+    /// Pathspecs to pass after `--`, if any.
+    fn pathspecs(&self) -> Vec<String> {
+        let magic = match self.test_files {
+            TestFiles::Include => None,
+            TestFiles::Exclude => Some(":(glob,exclude)"),
+            TestFiles::Only => Some(":(glob)"),
+        };
+        let test_files = magic.into_iter().flat_map(|magic| {
+            TEST_FILE_GLOBS
+                .iter()
+                .map(move |glob| format!("{}{}", magic, glob))
+        });
+        let excluded = self
+            .excluded_paths
+            .iter()
+            .map(|path| format!(":(top,literal,exclude){}", path));
+        test_files.chain(excluded).collect()
+    }
+
+    /// The flags that narrowed the diff, e.g. `--diff-filter=A --tests-only`,
+    /// for explaining an empty one.
+    pub fn describe_restrictions(&self) -> Option<String> {
+        let mut restrictions = Vec::new();
+        if let Some(filter) = &self.diff_filter {
+            restrictions.push(format!("--diff-filter={}", filter.as_str()));
+        }
+        match self.test_files {
+            TestFiles::Include => {}
+            TestFiles::Exclude => restrictions.push("--exclude-test-files".to_string()),
+            TestFiles::Only => restrictions.push("--tests-only".to_string()),
+        }
+        if self.skip_deletions {
+            restrictions.push("--skip-deletions".to_string());
+        }
+        (!restrictions.is_empty()).then(|| restrictions.join(" "))
+    }
+}
+
+/// Paths treated as tests by `--exclude-test-files` and `--tests-only`, as
+/// git glob pathspecs.
+const TEST_FILE_GLOBS: &[&str] = &[
+    "**/tests/**",
+    "**/*_test.*",
+    "**/*.test.*",
+    "**/test_*.py",
+    "**/*_spec.rb",
+];
+
+/// Whether the diff covers test files (see `TEST_FILE_GLOBS`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TestFiles {
+    #[default]
+    Include,
+    Exclude,
+    Only,
+}
+
+/// A validated `git diff --diff-filter` value, e.g. `AM` or `d`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffFilter(String);
+
+impl DiffFilter {
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        if raw.is_empty() {
+            return Err("diff filter must not be empty".to_string());
+        }
+        if let Some(invalid) = raw
+            .chars()
+            .find(|c| !"ACDMRTUXB*".contains(c.to_ascii_uppercase()))
+        {
+            return Err(format!(
+                "`{}` is not a diff filter letter; use A, C, D, M, R, T, U, X, B (lowercase excludes)",
+                invalid
+            ));
+        }
+        Ok(Self(raw.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct GitData {
     pub diff: String,
+    pub diff_stat: String,
     pub files_changed: Vec<String>,
+    /// Renamed files; `files_changed` lists them under their new path only.
+    pub renames: Vec<Rename>,
+    /// Permission, symlink and submodule changes; see `diff::mode_changes`.
+    pub mode_changes: Vec<ModeChange>,
+    /// Files deleted by the change but left out of the diff and
+    /// `files_changed` by `DiffOptions::skip_deletions`.
+    pub deleted_files: Vec<String>,
+    /// Files the diff shows only as `Binary files ... differ`.
+    pub binary_files: Vec<BinaryFile>,
     pub head_hash: String,
-    pub merge_base_hash: String,
+    pub base_hash: String,
     pub branch_name: Option<String>,
-    pub repo_name: String,
+    /// The remote identifying the canonical repository; see
+    /// `canonical_remote`.
+    pub remote_name: Option<String>,
     pub remote_url: Option<String>,
 }
 
-impl GitData {
-    pub fn new(
-        diff: String,
-        files_changed: Vec<String>,
-        head_hash: String,
-        merge_base_hash: String,
-        branch_name: Option<String>,
-        repo_name: String,
-        remote_url: Option<String>,
-    ) -> Self {
-        Self {
-            diff,
-            files_changed,
-            head_hash,
-            merge_base_hash,
-            branch_name,
-            repo_name,
-            remote_url,
+/// The `a/` and `b/` path prefixes `diff::parse_diff` expects, passed to
+/// every diff it reads so that `diff.noprefix` or `diff.mnemonicPrefix` in
+/// the user's config cannot change them.
+const DIFF_PREFIX_ARGS: [&str; 2] = ["--src-prefix=a/", "--dst-prefix=b/"];
+
+/// Runs git. With `debug` set (`--debug-git`), every command is echoed to
+/// stderr before it runs, quoted so it can be pasted into a shell, followed
+/// by its exit status.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Git {
+    pub debug: bool,
+}
+
+impl Git {
+    /// Every git invocation goes through here, so that `debug` sees them all.
+    fn output(&self, args: &[&str], stderr: Stdio) -> std::io::Result<Output> {
+        if self.debug {
+            eprintln!("[git] {}", format_command(args));
+        }
+        let output = Command::new("git").args(args).stderr(stderr).output();
+        if self.debug {
+            match &output {
+                Ok(output) => eprintln!("[git] {}", output.status),
+                Err(err) => eprintln!("[git] failed to start: {}", err),
+            }
+        }
+        output
+    }
+
+    /// The first `len` bytes git writes to stdout. git is killed once they
+    /// are read, so its status says nothing; check for failure beforehand.
+    fn output_prefix(&self, args: &[&str], len: usize) -> std::io::Result<Vec<u8>> {
+        if self.debug {
+            eprintln!("[git] {}", format_command(args));
+        }
+        let mut child = Command::new("git")
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let mut prefix = Vec::new();
+        let read = child
+            .stdout
+            .take()
+            .expect("stdout is piped")
+            .take(len as u64)
+            .read_to_end(&mut prefix);
+        // It may have exited already.
+        let _ = child.kill();
+        let status = child.wait();
+        if self.debug {
+            match &status {
+                Ok(status) => eprintln!("[git] {} (stopped after {} bytes)", status, len),
+                Err(err) => eprintln!("[git] failed to wait: {}", err),
+            }
+        }
+        read?;
+        Ok(prefix)
+    }
+
+    /// Runs git, capturing stderr for the error.
+    fn capture(&self, args: &[&str]) -> Result<Output> {
+        self.output(args, Stdio::piped())
+            .with_context(|| format!("Failed to execute git {}", args.join(" ")))
+    }
+
+    fn run(&self, args: &[&str]) -> Result<String> {
+        let output = self.capture(args)?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(anyhow!("git {} failed: {}", args.join(" "), stderr));
+        }
+
+        String::from_utf8(output.stdout)
+            .context("Failed to parse git output as UTF-8")
+            .map(|s| s.trim().to_string())
+    }
+
+    /// Runs git for information that may legitimately be missing (unset
+    /// config, no remotes); any failure or empty output is `None`.
+    fn optional(&self, args: &[&str]) -> Option<String> {
+        let output = self.output(args, Stdio::null()).ok()?;
+        if !output.status.success() {
+            return None;
         }
+        let stdout = String::from_utf8(output.stdout).ok()?;
+        let trimmed = stdout.trim();
+        (!trimmed.is_empty()).then(|| trimmed.to_string())
     }
 }
 
-fn run_git(args: &[&str]) -> Result<String> {
-    let output = Command::new("git")
-        .args(args)
-        .output()
-        .with_context(|| format!("Failed to execute git {}", args.join(" ")))?;
+/// `git` and `args` as a shell command line, single-quoting arguments that
+/// the shell would otherwise split or expand.
+fn format_command(args: &[&str]) -> String {
+    std::iter::once("git".to_string())
+        .chain(args.iter().map(|arg| {
+            let plain = !arg.is_empty()
+                && arg
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_./=:@^+,%".contains(c));
+            if plain {
+                arg.to_string()
+            } else {
+                format!("'{}'", arg.replace('\'', "'\\''"))
+            }
+        }))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Checks that `git` is installed and the current directory is inside a work
+/// tree, so later git failures are not the first thing a new user sees.
+pub fn ensure_git_repository(git: Git) -> Result<()> {
+    match git.output(&["--version"], Stdio::piped()) {
+        Ok(output) if output.status.success() => {}
+        Ok(_) => return Err(anyhow!("git is installed but `git --version` failed")),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Err(anyhow!("git not found on PATH; install git to use blart"));
+        }
+        Err(err) => return Err(err).context("Failed to execute git --version"),
+    }
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(anyhow!("git {} failed: {}", args.join(" "), stderr));
+    let inside = git
+        .output(&["rev-parse", "--is-inside-work-tree"], Stdio::null())
+        .context("Failed to execute git rev-parse --is-inside-work-tree")?;
+    if !inside.status.success() || String::from_utf8_lossy(&inside.stdout).trim() != "true" {
+        return Err(anyhow!(
+            "not a git repository; run blart from inside a git working tree"
+        ));
     }
 
-    String::from_utf8(output.stdout)
-        .context("Failed to parse git output as UTF-8")
-        .map(|s| s.trim().to_string())
+    Ok(())
 }
 
-pub fn get_git_data(default_branch: &str) -> Result<GitData> {
-    let head_hash = run_git(&["rev-parse", "HEAD"])?;
+/// The commits a diff runs between; without a `target` it runs from `base`
+/// to the working tree.
+#[derive(Debug, Clone)]
+pub struct DiffRange {
+    pub base: String,
+    pub target: Option<String>,
+    /// The branch `DiffBase::Upstream` resolved to, e.g. `origin/main`.
+    pub upstream: Option<String>,
+}
 
-    let merge_base_hash = run_git(&["merge-base", "HEAD", default_branch])?;
+/// Resolves `base` to the commits to diff, after checking that this is a
+/// git repository at all.
+pub fn resolve_range(git: Git, base: &DiffBase) -> Result<DiffRange> {
+    ensure_git_repository(git)?;
+    let to_working_tree = |base| DiffRange {
+        base,
+        target: None,
+        upstream: None,
+    };
+    match base {
+        DiffBase::MergeBase(branch) => git
+            .run(&["merge-base", "HEAD", branch])
+            .map(to_working_tree),
+        DiffBase::SinceCommit(commit) => {
+            let hash = git
+                .run(&["rev-parse", "--verify", &format!("{}^{{commit}}", commit)])
+                .map_err(|_| anyhow!("`{}` does not name a commit", commit))?;
+            let is_ancestor = git.capture(&["merge-base", "--is-ancestor", &hash, "HEAD"])?;
+            if !is_ancestor.status.success() {
+                return Err(anyhow!(
+                    "commit `{}` is not an ancestor of HEAD (was the branch rebased?)",
+                    commit
+                ));
+            }
+            Ok(to_working_tree(hash))
+        }
+        DiffBase::WorkingTree => git.run(&["rev-parse", "HEAD"]).map(to_working_tree),
+        DiffBase::Upstream(mode) => {
+            let upstream = git
+                .optional(&["rev-parse", "--abbrev-ref", "@{upstream}"])
+                .context(
+                    "no upstream is configured for the current branch; set one with \
+                 `git branch --set-upstream-to <remote>/<branch>` or use --default-branch",
+                )?;
+            let base = match mode {
+                DiffMode::TwoDot => git.run(&["rev-parse", &upstream])?,
+                DiffMode::ThreeDot => git.run(&["merge-base", "HEAD", &upstream])?,
+            };
+            Ok(DiffRange {
+                base,
+                target: None,
+                upstream: Some(upstream),
+            })
+        }
+        DiffBase::Stash(index) => {
+            let stash = format!("stash@{{{}}}", index);
+            let target = git
+                .optional(&["rev-parse", "--verify", "--quiet", &stash])
+                .with_context(|| format!("`{}` does not exist; see `git stash list`", stash))?;
+            let base = git.run(&["rev-parse", &format!("{}^1", target)])?;
+            Ok(DiffRange {
+                base,
+                target: Some(target),
+                upstream: None,
+            })
+        }
+        DiffBase::Ref(reference) => git
+            .run(&[
+                "rev-parse",
+                "--verify",
+                &format!("{}^{{commit}}", reference),
+            ])
+            .map(to_working_tree)
+            .map_err(|_| {
+                anyhow!(
+                    "`{}` does not name a tag, branch or commit (is it fetched?)",
+                    reference
+                )
+            }),
+    }
+}
+
+/// Collects the diff over `range`, and what describes it, as restricted
+/// by `options`.
+pub fn get_git_data(git: Git, range: &DiffRange, options: &DiffOptions) -> Result<GitData> {
+    let base_hash = range.base.clone();
+    let target = range.target.clone();
+    // A stash is reviewed as if it were checked out.
+    let head_hash = match &target {
+        Some(target) => target.clone(),
+        None => git.run(&["rev-parse", "HEAD"])?,
+    };
 
-    let branch_name = run_git(&["branch", "--show-current"])?;
+    let branch_name = git.run(&["branch", "--show-current"])?;
     let branch_name = if branch_name.is_empty() {
         None
     } else {
         Some(branch_name)
     };
 
-    let diff_output = Command::new("git")
-        .args([
-            "diff",
-            "--no-ext-diff",
-            "--unified=5",
-            "--no-color",
-            &merge_base_hash,
-        ])
-        .output()
-        .context("Failed to execute git diff")?;
+    let option_args = options.git_args();
+    let pathspecs = options.pathspecs();
+    let diff_args_with = |flags: &[&str], option_args: &[String]| -> Vec<String> {
+        // `-M` so renames show up as such even where diff.renames is off.
+        let mut args: Vec<String> = ["diff", "--no-ext-diff", "-M"]
+            .iter()
+            .chain(&DIFF_PREFIX_ARGS)
+            .chain(flags)
+            .map(|arg| arg.to_string())
+            .chain(option_args.iter().cloned())
+            .chain([base_hash.clone()])
+            .chain(target.clone())
+            .collect();
+        if !pathspecs.is_empty() {
+            args.push("--".to_string());
+            args.extend(pathspecs.iter().cloned());
+        }
+        args
+    };
+    let diff_args = |flags: &[&str]| diff_args_with(flags, &option_args);
+
+    let patch_args = diff_args(&[
+        if options.compact {
+            "--unified=0"
+        } else {
+            "--unified=5"
+        },
+        "--no-color",
+    ]);
+    let diff_output = git.capture(&patch_args.iter().map(String::as_str).collect::<Vec<_>>())?;
 
     if !diff_output.status.success() {
         let stderr = String::from_utf8_lossy(&diff_output.stderr);
@@ -81,79 +443,447 @@ pub fn get_git_data(default_branch: &str) -> Result<GitData> {
 
     let diff = String::from_utf8(diff_output.stdout).context("Failed to parse diff as UTF-8")?;
 
-    let files_output = Command::new("git")
-        .args(["diff", "--no-ext-diff", "--name-only", &merge_base_hash])
-        .output()
-        .context("Failed to execute git diff --name-only")?;
+    let stat_args = diff_args(&["--stat", "--no-color"]);
+    let renames = diff::renames(&diff);
+    let mode_changes = diff::mode_changes(&diff);
+
+    let diff_stat = git.run(&stat_args.iter().map(String::as_str).collect::<Vec<_>>())?;
+
+    let files_args = diff_args(&["--name-only"]);
+    let files_output = git.capture(&files_args.iter().map(String::as_str).collect::<Vec<_>>())?;
 
     if !files_output.status.success() {
         let stderr = String::from_utf8_lossy(&files_output.stderr);
         return Err(anyhow!("git diff --name-only failed: {}", stderr));
     }
 
-    let files_changed = String::from_utf8(files_output.stdout)
+    let files_changed: Vec<String> = String::from_utf8(files_output.stdout)
         .context("Failed to parse changed files as UTF-8")?
         .lines()
         .map(|s| s.to_string())
         .collect();
 
-    let repo_path = run_git(&["rev-parse", "--show-toplevel"])?;
-    let repo_name = Path::new(&repo_path)
-        .file_name()
-        .context("Failed to extract repo name from path")?
-        .to_str()
-        .context("Repo name is not valid UTF-8")?
-        .to_string();
-
-    let remote_url = if let Some(ref branch) = branch_name {
-        let remote_result = Command::new("git")
-            .args(["config", "--get", &format!("branch.{}.remote", branch)])
-            .stderr(Stdio::null())
-            .output();
-
-        if let Ok(remote_output) = remote_result {
-            if remote_output.status.success() {
-                if let Ok(remote_name) = String::from_utf8(remote_output.stdout) {
-                    let remote_name = remote_name.trim().to_string();
-                    if remote_name.is_empty() {
-                        None
-                    } else {
-                        let url_result = Command::new("git")
-                            .args(["remote", "get-url", &remote_name])
-                            .stderr(Stdio::null())
-                            .output();
-                        if let Ok(url_output) = url_result {
-                            if url_output.status.success() {
-                                String::from_utf8(url_output.stdout)
-                                    .ok()
-                                    .map(|s| s.trim().to_string())
-                            } else {
-                                None
-                            }
-                        } else {
-                            None
-                        }
-                    }
-                } else {
-                    None
-                }
-            } else {
-                None
-            }
-        } else {
-            None
-        }
+    // The other restrictions do not apply: they would be combined with the
+    // `D` filter here.
+    let deleted_files = if options.skip_deletions {
+        let deleted_args = diff_args_with(&["--name-only", "--diff-filter=D"], &[]);
+        git.run(&deleted_args.iter().map(String::as_str).collect::<Vec<_>>())?
+            .lines()
+            .map(str::to_string)
+            .collect()
     } else {
-        None
+        Vec::new()
     };
 
-    Ok(GitData::new(
+    let repo_path = git.run(&["rev-parse", "--show-toplevel"])?;
+
+    let binary_files = diff::binary_files(&diff)
+        .into_iter()
+        .map(|(path, change_type)| {
+            // What a deleted file used to be; otherwise what it is now.
+            let revision = if change_type == diff::ChangeType::Deleted {
+                Some(base_hash.as_str())
+            } else {
+                target.as_deref()
+            };
+            let probe = probe_contents(git, revision, &repo_path, &path);
+            let probe = probe.as_ref().map(|(size, head)| (*size, head.as_slice()));
+            BinaryFile::new(path, change_type, probe)
+        })
+        .collect();
+
+    let tracking_remote = branch_name.as_deref().and_then(|branch| {
+        git.optional(&["config", "--get", &format!("branch.{}.remote", branch)])
+    });
+    let remotes: Vec<String> = git
+        .optional(&["remote"])
+        .map(|output| output.lines().map(str::to_string).collect())
+        .unwrap_or_default();
+    let remote_name = canonical_remote(&remotes, tracking_remote.as_deref());
+    let remote_url = remote_name
+        .as_deref()
+        .and_then(|remote| git.optional(&["remote", "get-url", remote]));
+
+    Ok(GitData {
         diff,
+        diff_stat,
         files_changed,
+        renames,
+        mode_changes,
+        deleted_files,
+        binary_files,
         head_hash,
-        merge_base_hash,
+        base_hash,
         branch_name,
-        repo_name,
+        remote_name,
         remote_url,
-    ))
+    })
+}
+
+/// The size of `path` at `revision` (or in the working tree under
+/// `repo_path` when there is none) and its first `binary::SNIFF_BYTES`
+/// bytes, read without loading the rest of what may be a very large file.
+fn probe_contents(
+    git: Git,
+    revision: Option<&str>,
+    repo_path: &str,
+    path: &str,
+) -> Option<(u64, Vec<u8>)> {
+    let mut head = Vec::new();
+    match revision {
+        Some(revision) => {
+            let object = format!("{}:{}", revision, path);
+            let size = git.optional(&["cat-file", "-s", &object])?.parse().ok()?;
+            if size > 0 {
+                head = git
+                    .output_prefix(&["cat-file", "blob", &object], binary::SNIFF_BYTES)
+                    .ok()?;
+            }
+            Some((size, head))
+        }
+        None => {
+            let file = File::open(Path::new(repo_path).join(path)).ok()?;
+            let size = file.metadata().ok()?.len();
+            file.take(binary::SNIFF_BYTES as u64)
+                .read_to_end(&mut head)
+                .ok()?;
+            Some((size, head))
+        }
+    }
+}
+
+/// The contents of `path`, relative to the repository root, at `revision`.
+pub fn read_file_at(git: Git, revision: &str, path: &str) -> Option<Vec<u8>> {
+    let object = format!("{}:{}", revision, path);
+    let output = git
+        .output(&["cat-file", "blob", &object], Stdio::null())
+        .ok()?;
+    output.status.success().then_some(output.stdout)
+}
+
+/// `git show` for one commit: its message, a stat and its diff. `reference`
+/// comes from the model, so it is resolved to a commit hash first and may
+/// not be taken for an option.
+pub fn show_commit(git: Git, reference: &str) -> Result<String> {
+    if reference.starts_with('-') {
+        return Err(anyhow!("`{}` is not a commit reference", reference));
+    }
+    let hash = git
+        .run(&[
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("{}^{{commit}}", reference),
+        ])
+        .map_err(|_| anyhow!("`{}` does not name a commit", reference))?;
+    git.run(&[
+        "show",
+        "--no-ext-diff",
+        DIFF_PREFIX_ARGS[0],
+        DIFF_PREFIX_ARGS[1],
+        "--no-color",
+        "--stat",
+        "--patch",
+        &hash,
+    ])
+}
+
+/// The subjects of the last `count` commits reachable from `base` that
+/// touch `path`, newest first. Empty for paths with no history there, such as
+/// files the diff adds.
+pub fn recent_subjects(git: Git, base: &str, path: &str, count: usize) -> Vec<String> {
+    git.optional(&[
+        "log",
+        &format!("--max-count={}", count),
+        "--format=%s",
+        base,
+        "--",
+        &format!(":(top,literal){}", path),
+    ])
+    .map(|output| output.lines().map(str::to_string).collect())
+    .unwrap_or_default()
+}
+
+/// Whether `branch` names a commit, e.g. a local or remote-tracking branch.
+pub fn branch_exists(git: Git, branch: &str) -> bool {
+    git.optional(&[
+        "rev-parse",
+        "--verify",
+        "--quiet",
+        &format!("{}^{{commit}}", branch),
+    ])
+    .is_some()
+}
+
+/// Picks the remote that identifies the canonical repository. In fork
+/// workflows the branch tracks the fork while PRs target `upstream`, so
+/// `upstream` wins, then `origin`, then whatever the branch tracks.
+fn canonical_remote(remotes: &[String], tracking: Option<&str>) -> Option<String> {
+    ["upstream", "origin"]
+        .into_iter()
+        .chain(tracking)
+        .find(|candidate| remotes.iter().any(|remote| remote == candidate))
+        .map(str::to_string)
+}
+
+/// Explains why the diff against the base is empty, using only the refs
+/// already collected, so the user knows what to do next.
+pub fn explain_empty_diff(git_data: &GitData, base: &DiffBase) -> String {
+    let default_branch = match base {
+        DiffBase::MergeBase(branch) => branch,
+        DiffBase::SinceCommit(commit) => {
+            return format!(
+                "No changes detected: no new commits or uncommitted changes since `{}`.",
+                commit
+            );
+        }
+        DiffBase::Upstream(DiffMode::ThreeDot) => {
+            return "No changes detected: nothing has changed since the branch diverged from its \
+                    upstream."
+                .to_string();
+        }
+        DiffBase::Upstream(DiffMode::TwoDot) => {
+            return "No changes detected: the working tree matches the branch's upstream."
+                .to_string();
+        }
+        DiffBase::WorkingTree => {
+            return "No changes detected: the working tree has no staged or unstaged changes \
+                    to tracked files relative to HEAD."
+                .to_string();
+        }
+        DiffBase::Stash(index) => {
+            return format!(
+                "No changes detected: stash@{{{}}} has no changes to tracked files.",
+                index
+            );
+        }
+        DiffBase::Ref(reference) => {
+            return format!(
+                "No changes detected: nothing has changed since `{}`.",
+                reference
+            );
+        }
+    };
+    match git_data.branch_name.as_deref() {
+        Some(branch) if branch == default_branch => format!(
+            "No changes detected: you're on `{}`, the branch blart compares against. \
+             Switch to a feature branch or pass --default-branch.",
+            branch
+        ),
+        branch if git_data.head_hash == git_data.base_hash => format!(
+            "No changes detected: {} has no commits ahead of `{}` and no uncommitted changes.",
+            describe_branch(branch),
+            default_branch
+        ),
+        branch => format!(
+            "No changes detected: {} has commits ahead of `{}`, but they introduce no net changes \
+             (for example, a merge-only branch).",
+            describe_branch(branch),
+            default_branch
+        ),
+    }
+}
+
+fn describe_branch(branch: Option<&str>) -> String {
+    match branch {
+        Some(name) => format!("branch `{}`", name),
+        None => "detached HEAD".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_prefix_args_override_prefix_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let git = |args: &[&str]| {
+            let output = Command::new("git")
+                .arg("-C")
+                .arg(dir.path())
+                .args(args)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "git {:?} failed", args);
+            String::from_utf8(output.stdout).unwrap()
+        };
+        git(&["init", "-q"]);
+        git(&["config", "user.email", "blart@example.com"]);
+        git(&["config", "user.name", "blart"]);
+        std::fs::write(dir.path().join("lib.rs"), "fn a() {}\n").unwrap();
+        git(&["add", "lib.rs"]);
+        git(&["commit", "-q", "-m", "init"]);
+        std::fs::write(dir.path().join("lib.rs"), "fn b() {}\n").unwrap();
+
+        for config in ["diff.noprefix", "diff.mnemonicPrefix"] {
+            git(&["config", config, "true"]);
+            let mut args = vec!["diff", "--no-ext-diff"];
+            args.extend(DIFF_PREFIX_ARGS);
+            args.push("HEAD");
+            let diff = git(&args);
+            let files = diff::parse_diff(&diff);
+            assert_eq!(files[0].old_path(), Some("lib.rs"), "with {}", config);
+            assert_eq!(files[0].new_path(), Some("lib.rs"), "with {}", config);
+            git(&["config", "--unset", config]);
+        }
+    }
+
+    fn git_data(branch: Option<&str>, head: &str, base: &str) -> GitData {
+        GitData {
+            diff: String::new(),
+            diff_stat: String::new(),
+            files_changed: Vec::new(),
+            renames: Vec::new(),
+            mode_changes: Vec::new(),
+            deleted_files: Vec::new(),
+            binary_files: Vec::new(),
+            head_hash: head.to_string(),
+            base_hash: base.to_string(),
+            branch_name: branch.map(str::to_string),
+            remote_name: None,
+            remote_url: None,
+        }
+    }
+
+    fn remotes(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn diff_filter_accepts_git_letters_only() {
+        assert_eq!(DiffFilter::parse("AMd").unwrap().0, "AMd");
+        assert!(DiffFilter::parse("").is_err());
+        assert!(DiffFilter::parse("AZ").unwrap_err().contains("`Z`"));
+    }
+
+    #[test]
+    fn diff_options_select_test_files_with_pathspecs() {
+        let options = |test_files| DiffOptions {
+            test_files,
+            ..DiffOptions::default()
+        };
+        assert!(options(TestFiles::Include).pathspecs().is_empty());
+        assert_eq!(options(TestFiles::Include).describe_restrictions(), None);
+
+        let exclude = options(TestFiles::Exclude).pathspecs();
+        assert_eq!(exclude.len(), TEST_FILE_GLOBS.len());
+        assert_eq!(exclude[0], ":(glob,exclude)**/tests/**");
+        assert_eq!(
+            options(TestFiles::Only).pathspecs()[4],
+            ":(glob)**/*_spec.rb"
+        );
+
+        let filtered = DiffOptions {
+            diff_filter: Some(DiffFilter::parse("A").unwrap()),
+            test_files: TestFiles::Only,
+            excluded_paths: vec!["gen/api.rs".to_string()],
+            compact: false,
+            skip_deletions: true,
+        };
+        assert_eq!(
+            filtered.pathspecs().last().map(String::as_str),
+            Some(":(top,literal,exclude)gen/api.rs")
+        );
+        assert_eq!(
+            filtered.describe_restrictions().as_deref(),
+            Some("--diff-filter=A --tests-only --skip-deletions")
+        );
+        assert_eq!(filtered.git_args(), vec!["--diff-filter=Ad"]);
+    }
+
+    #[test]
+    fn format_command_quotes_what_the_shell_would_split() {
+        assert_eq!(
+            format_command(&["merge-base", "HEAD", "origin/main"]),
+            "git merge-base HEAD origin/main"
+        );
+        assert_eq!(
+            format_command(&["diff", "--", ":(glob,exclude)**/tests/**", "it's", ""]),
+            "git diff -- ':(glob,exclude)**/tests/**' 'it'\\''s' ''"
+        );
+    }
+
+    #[test]
+    fn canonical_remote_prefers_upstream_then_origin_then_tracking() {
+        let all = remotes(&["fork", "origin", "upstream"]);
+        assert_eq!(
+            canonical_remote(&all, Some("fork")).as_deref(),
+            Some("upstream")
+        );
+        let no_upstream = remotes(&["fork", "origin"]);
+        assert_eq!(
+            canonical_remote(&no_upstream, Some("fork")).as_deref(),
+            Some("origin")
+        );
+        let fork_only = remotes(&["fork"]);
+        assert_eq!(
+            canonical_remote(&fork_only, Some("fork")).as_deref(),
+            Some("fork")
+        );
+        // `.` is what git records for branches tracking a local branch.
+        assert_eq!(canonical_remote(&fork_only, Some(".")), None);
+        assert_eq!(canonical_remote(&[], None), None);
+    }
+
+    fn main() -> DiffBase {
+        DiffBase::MergeBase("main".to_string())
+    }
+
+    #[test]
+    fn explain_empty_diff_for_clean_working_tree() {
+        let message = explain_empty_diff(
+            &git_data(Some("feature"), "abc", "abc"),
+            &DiffBase::WorkingTree,
+        );
+        assert!(message.contains("no staged or unstaged changes"));
+    }
+
+    #[test]
+    fn explain_empty_diff_on_default_branch() {
+        let message = explain_empty_diff(&git_data(Some("main"), "abc", "abc"), &main());
+        assert!(message.contains("you're on `main`"));
+    }
+
+    #[test]
+    fn explain_empty_diff_with_no_commits_ahead() {
+        let message = explain_empty_diff(&git_data(Some("feature"), "abc", "abc"), &main());
+        assert!(message.contains("branch `feature` has no commits ahead of `main`"));
+    }
+
+    #[test]
+    fn explain_empty_diff_with_merge_only_commits() {
+        let message = explain_empty_diff(&git_data(None, "abc", "def"), &main());
+        assert!(message.contains("detached HEAD has commits ahead of `main`"));
+        assert!(message.contains("no net changes"));
+    }
+
+    #[test]
+    fn explain_empty_diff_since_commit() {
+        let base = DiffBase::SinceCommit("abc123".to_string());
+        let message = explain_empty_diff(&git_data(Some("feature"), "abc", "abc"), &base);
+        assert!(message.contains("since `abc123`"));
+    }
+
+    #[test]
+    fn explain_empty_diff_for_stash() {
+        let message = explain_empty_diff(&git_data(None, "abc", "def"), &DiffBase::Stash(2));
+        assert!(message.contains("stash@{2} has no changes"));
+    }
+
+    #[test]
+    fn explain_empty_diff_against_upstream() {
+        let data = git_data(Some("feature"), "abc", "abc");
+        let message = explain_empty_diff(&data, &DiffBase::Upstream(DiffMode::ThreeDot));
+        assert!(message.contains("since the branch diverged"));
+        let message = explain_empty_diff(&data, &DiffBase::Upstream(DiffMode::TwoDot));
+        assert!(message.contains("matches the branch's upstream"));
+    }
+
+    #[test]
+    fn explain_empty_diff_since_ref() {
+        let base = DiffBase::Ref("v2.3.0".to_string());
+        let message = explain_empty_diff(&git_data(Some("feature"), "abc", "abc"), &base);
+        assert!(message.contains("nothing has changed since `v2.3.0`"));
+    }
 }
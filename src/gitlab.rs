@@ -0,0 +1,154 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+/// Posts reviews to GitLab merge requests through the REST API (v4).
+pub struct GitLabClient {
+    /// `scheme://host[:port]` of the instance, e.g. `https://gitlab.com`.
+    base_url: String,
+    token: String,
+    client: reqwest::Client,
+}
+
+/// The fields of a merge request that blart uses.
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct MergeRequest {
+    /// The number shown as `!iid` in the project.
+    pub iid: u64,
+    pub web_url: String,
+}
+
+impl GitLabClient {
+    pub fn new(base_url: &str, token: String) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// The open merge request from `source_branch` in `project` (a path such
+    /// as `group/project`), if there is one. With several, GitLab lists the
+    /// newest first.
+    pub async fn open_merge_request(
+        &self,
+        project: &str,
+        source_branch: &str,
+    ) -> Result<Option<MergeRequest>> {
+        let response = self
+            .client
+            .get(format!("{}/merge_requests", self.project_url(project)))
+            .header("PRIVATE-TOKEN", &self.token)
+            .query(&[("source_branch", source_branch), ("state", "opened")])
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await?;
+            anyhow::bail!("GitLab API error ({}): {}", status, error_text);
+        }
+
+        let body = response.text().await?;
+        let merge_requests = serde_json::from_str::<Vec<MergeRequest>>(&body)
+            .with_context(|| format!("Failed to parse merge request list: {}", body))?;
+        Ok(merge_requests.into_iter().next())
+    }
+
+    /// Adds `body` as a comment (a "note") on merge request `iid`.
+    pub async fn post_note(&self, project: &str, iid: u64, body: &str) -> Result<()> {
+        let response = self
+            .client
+            .post(format!(
+                "{}/merge_requests/{}/notes",
+                self.project_url(project),
+                iid
+            ))
+            .header("PRIVATE-TOKEN", &self.token)
+            .json(&serde_json::json!({ "body": body }))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await?;
+            anyhow::bail!("GitLab API error ({}): {}", status, error_text);
+        }
+        Ok(())
+    }
+
+    /// The API URL of `project`, which GitLab takes with its slashes encoded.
+    fn project_url(&self, project: &str) -> String {
+        format!(
+            "{}/api/v4/projects/{}",
+            self.base_url,
+            project.replace('/', "%2F")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{body_json, header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn finds_the_merge_request_and_posts_a_note() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v4/projects/group%2Fapp/merge_requests"))
+            .and(query_param("source_branch", "feature"))
+            .and(query_param("state", "opened"))
+            .and(header("PRIVATE-TOKEN", "secret"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                { "iid": 7, "web_url": "https://gitlab.example/group/app/-/merge_requests/7", "title": "x" }
+            ])))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v4/projects/group%2Fapp/merge_requests/7/notes"))
+            .and(header("PRIVATE-TOKEN", "secret"))
+            .and(body_json(serde_json::json!({ "body": "Looks good." })))
+            .respond_with(ResponseTemplate::new(201).set_body_json(serde_json::json!({ "id": 1 })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let client = GitLabClient::new(&format!("{}/", mock_server.uri()), "secret".to_string());
+        let merge_request = client
+            .open_merge_request("group/app", "feature")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(merge_request.iid, 7);
+        client
+            .post_note("group/app", merge_request.iid, "Looks good.")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn reports_missing_merge_requests_and_api_errors() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v4/projects/group%2Fapp/merge_requests"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(401).set_body_string("401 Unauthorized"))
+            .mount(&mock_server)
+            .await;
+
+        let client = GitLabClient::new(&mock_server.uri(), "bad".to_string());
+        assert_eq!(
+            client
+                .open_merge_request("group/app", "feature")
+                .await
+                .unwrap(),
+            None
+        );
+        let err = client.post_note("group/app", 7, "x").await.unwrap_err();
+        assert!(err.to_string().contains("GitLab API error (401"));
+    }
+}
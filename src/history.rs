@@ -0,0 +1,77 @@
+/// Commit subjects shown for each changed file.
+pub const SUBJECTS_PER_FILE: usize = 3;
+/// Changed files whose history is looked up; one `git log` runs per file.
+pub const MAX_FILES: usize = 40;
+/// Characters of history allowed in one prompt; files past this are left out.
+const MAX_TOTAL_CHARS: usize = 4_000;
+
+/// The `--file-history` section: each file's recent commit subjects under
+/// its path. Files without history (new files) are left out, and a closing
+/// note counts the files cut by `MAX_FILES` or the size limit. `None` when
+/// no file has any history.
+pub fn format_history(histories: &[(String, Vec<String>)], total_files: usize) -> Option<String> {
+    let mut output = String::new();
+    let mut left_out = total_files - histories.len();
+    for (index, (path, subjects)) in histories.iter().enumerate() {
+        if subjects.is_empty() {
+            continue;
+        }
+        let mut entry = format!("{}:\n", path);
+        for subject in subjects {
+            entry.push_str(&format!("  - {}\n", subject));
+        }
+        if output.chars().count() + entry.chars().count() > MAX_TOTAL_CHARS {
+            left_out += histories[index..]
+                .iter()
+                .filter(|(_, subjects)| !subjects.is_empty())
+                .count();
+            break;
+        }
+        output.push_str(&entry);
+    }
+    if output.is_empty() {
+        return None;
+    }
+    if left_out > 0 {
+        output.push_str(&format!(
+            "[history of {} more file(s) left out]\n",
+            left_out
+        ));
+    }
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_history_skips_new_files_and_caps_the_size() {
+        let history = |path: &str, subjects: &[&str]| {
+            (
+                path.to_string(),
+                subjects.iter().map(|subject| subject.to_string()).collect(),
+            )
+        };
+        assert_eq!(
+            format_history(
+                &[
+                    history("src/new.rs", &[]),
+                    history("src/lib.rs", &["Fix parsing", "Add parser"]),
+                ],
+                2
+            ),
+            Some("src/lib.rs:\n  - Fix parsing\n  - Add parser\n".to_string())
+        );
+        assert_eq!(format_history(&[history("src/new.rs", &[])], 1), None);
+
+        let long = "x".repeat(MAX_TOTAL_CHARS / 2);
+        let histories: Vec<_> = (0..4)
+            .map(|i| history(&format!("f{}.rs", i), &[long.as_str()]))
+            .collect();
+        let output = format_history(&histories, 10).unwrap();
+        assert!(output.starts_with("f0.rs:\n"));
+        assert!(!output.contains("f1.rs"));
+        assert!(output.ends_with("[history of 9 more file(s) left out]\n"));
+    }
+}
@@ -0,0 +1,360 @@
+use regex::Regex;
+use std::collections::BTreeMap;
+
+use crate::diff::{FileDiff, Hunk, parse_diff, render_diff};
+
+/// Lockfile formats whose diffs are collapsed to a dependency summary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LockfileKind {
+    Cargo,
+    Npm,
+    Yarn,
+    Poetry,
+}
+
+impl LockfileKind {
+    fn from_path(path: &str) -> Option<Self> {
+        match path.rsplit('/').next()? {
+            "Cargo.lock" => Some(Self::Cargo),
+            "package-lock.json" => Some(Self::Npm),
+            "yarn.lock" => Some(Self::Yarn),
+            "poetry.lock" => Some(Self::Poetry),
+            _ => None,
+        }
+    }
+
+    /// Patterns capturing a package name and a version from one lockfile line.
+    /// In every supported format the name line precedes its version line.
+    fn patterns(self) -> (Regex, Regex) {
+        let (name, version) = match self {
+            Self::Cargo | Self::Poetry => (r#"^name = "([^"]+)""#, r#"^version = "([^"]+)""#),
+            Self::Npm => (
+                r#"^\s*"(?:.*/)?node_modules/([^"]+)": \{"#,
+                r#"^\s*"version": "([^"]+)""#,
+            ),
+            Self::Yarn => (r#"^"?(@?[^@\s"]+)@"#, r#"^\s+version:? "?([^"\s]+)"?"#),
+        };
+        (
+            Regex::new(name).expect("lockfile name pattern is valid"),
+            Regex::new(version).expect("lockfile version pattern is valid"),
+        )
+    }
+}
+
+/// Replaces the hunks of every lockfile in `diff` with a one-line summary of
+/// the dependencies added, updated and removed. Hunks whose version changes
+/// cannot be tied to a package (as in `--compact-diff`'s context-free hunks)
+/// are kept as they are after the summary. Other files are untouched.
+pub fn collapse_lockfiles(diff: &str) -> String {
+    let mut output = String::new();
+    for file in parse_diff(diff) {
+        let kind = file_path(&file).and_then(LockfileKind::from_path);
+        match kind {
+            Some(kind) if !file.hunks.is_empty() => {
+                let header_only = FileDiff {
+                    header: file.header.clone(),
+                    hunks: Vec::new(),
+                };
+                output.push_str(&render_diff(&[header_only]));
+                let (summary, unattributed) = summarize(&file, kind);
+                output.push_str(&summary);
+                output.push('\n');
+                if !unattributed.is_empty() {
+                    output.push_str(&format!(
+                        "[{} hunk(s) change versions without naming the package; shown as they are]\n",
+                        unattributed.len()
+                    ));
+                    let hunks_only = FileDiff {
+                        header: Vec::new(),
+                        hunks: unattributed,
+                    };
+                    output.push_str(&render_diff(&[hunks_only]));
+                }
+            }
+            _ => output.push_str(&render_diff(&[file])),
+        }
+    }
+    output
+}
+
+fn file_path<'a>(file: &FileDiff<'a>) -> Option<&'a str> {
+    let first = file.header.first()?;
+    first.strip_prefix("diff --git ")?.rsplit(" b/").next()
+}
+
+/// The summary of `file`'s dependency changes, and the hunks with a changed
+/// version line that no package name precedes, which it leaves out.
+fn summarize<'a>(file: &FileDiff<'a>, kind: LockfileKind) -> (String, Vec<Hunk<'a>>) {
+    let (name_pattern, version_pattern) = kind.patterns();
+    // Counts of each (name, version) on either side. A lockfile can hold
+    // several versions of one package, so the name alone is not a key.
+    let mut old_versions = BTreeMap::new();
+    let mut new_versions = BTreeMap::new();
+    let mut unattributed = Vec::new();
+
+    for hunk in &file.hunks {
+        // Hunks are not contiguous, so a name never carries over into the
+        // next one.
+        let mut old_name: Option<String> = None;
+        let mut new_name: Option<String> = None;
+        let mut attributed = true;
+        for line in &hunk.lines {
+            let (in_old, in_new, text) = match line.as_bytes().first() {
+                Some(b'-') => (true, false, &line[1..]),
+                Some(b'+') => (false, true, &line[1..]),
+                Some(b' ') => (true, true, &line[1..]),
+                _ => continue,
+            };
+            if let Some(captures) = name_pattern.captures(text) {
+                let name = captures[1].to_string();
+                if in_old {
+                    old_name = Some(name.clone());
+                }
+                if in_new {
+                    new_name = Some(name);
+                }
+            } else if let Some(captures) = version_pattern.captures(text) {
+                let version = captures[1].to_string();
+                // Context lines appear on both sides, so an unchanged version
+                // cancels out below.
+                if in_old && let Some(name) = &old_name {
+                    *old_versions
+                        .entry((name.clone(), version.clone()))
+                        .or_insert(0) += 1;
+                }
+                if in_new && let Some(name) = &new_name {
+                    *new_versions.entry((name.clone(), version)).or_insert(0) += 1;
+                }
+                let changed = in_old != in_new;
+                if changed && (in_old && old_name.is_none() || in_new && new_name.is_none()) {
+                    attributed = false;
+                }
+            }
+        }
+        if !attributed {
+            unattributed.push(hunk.clone());
+        }
+    }
+
+    let added_versions = versions_by_name(&new_versions, &old_versions);
+    let mut removed_versions = versions_by_name(&old_versions, &new_versions);
+    let mut added = Vec::new();
+    let mut updated = Vec::new();
+    let mut removed = Vec::new();
+    for (name, new) in added_versions {
+        // Only a lone version swapped for another is an update; anything
+        // else, like a second major version alongside the first, is listed
+        // as it is.
+        match removed_versions.get(&name) {
+            Some(old) if old.len() == 1 && new.len() == 1 => {
+                updated.push(format!("{} {} -> {}", name, old[0], new[0]));
+                removed_versions.remove(&name);
+            }
+            _ => added.extend(new.iter().map(|version| format!("{} {}", name, version))),
+        }
+    }
+    for (name, old) in removed_versions {
+        removed.extend(old.iter().map(|version| format!("{} {}", name, version)));
+    }
+
+    let mut summary = format!(
+        "[lockfile diff collapsed: {} dependencies added, {} updated, {} removed; pass --full-lockfile-diffs to see it]",
+        added.len(),
+        updated.len(),
+        removed.len()
+    );
+    for (label, entries) in [("added", added), ("updated", updated), ("removed", removed)] {
+        if !entries.is_empty() {
+            summary.push_str(&format!("\n[{}: {}]", label, entries.join(", ")));
+        }
+    }
+    (summary, unattributed)
+}
+
+/// The versions in `side` that `other` does not cancel out, counting
+/// repeats, grouped by package name.
+fn versions_by_name(
+    side: &BTreeMap<(String, String), usize>,
+    other: &BTreeMap<(String, String), usize>,
+) -> BTreeMap<String, Vec<String>> {
+    let mut by_name: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (key, &count) in side {
+        let surplus = count.saturating_sub(other.get(key).copied().unwrap_or(0));
+        if surplus > 0 {
+            let (name, version) = key;
+            by_name
+                .entry(name.clone())
+                .or_default()
+                .extend(std::iter::repeat_n(version.clone(), surplus));
+        }
+    }
+    by_name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collapse_lockfiles_summarizes_cargo_lock() {
+        let diff = "\
+diff --git a/Cargo.lock b/Cargo.lock
+index 111..222 100644
+--- a/Cargo.lock
++++ b/Cargo.lock
+@@ -1,10 +1,14 @@
+ [[package]]
+ name = \"anyhow\"
+-version = \"1.0.0\"
++version = \"1.0.1\"
+ 
++[[package]]
++name = \"chrono\"
++version = \"0.4.45\"
++
+ [[package]]
+-name = \"old-dep\"
+-version = \"0.1.0\"
+-
+-[[package]]
+ name = \"serde\"
+ version = \"1.0.200\"
+diff --git a/src/lib.rs b/src/lib.rs
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,1 +1,1 @@
+-a
++b
+";
+        let collapsed = collapse_lockfiles(diff);
+        assert!(collapsed.contains(
+            "+++ b/Cargo.lock\n[lockfile diff collapsed: 1 dependencies added, 1 updated, 1 removed"
+        ));
+        assert!(collapsed.contains("[added: chrono 0.4.45]"));
+        assert!(collapsed.contains("[updated: anyhow 1.0.0 -> 1.0.1]"));
+        assert!(collapsed.contains("[removed: old-dep 0.1.0]"));
+        assert!(!collapsed.contains("serde"));
+        assert!(collapsed.ends_with("@@ -1,1 +1,1 @@\n-a\n+b\n"));
+    }
+
+    #[test]
+    fn collapse_lockfiles_does_not_carry_names_across_hunks() {
+        let diff = "\
+diff --git a/Cargo.lock b/Cargo.lock
+--- a/Cargo.lock
++++ b/Cargo.lock
+@@ -1,4 +1,4 @@
+ [[package]]
+ name = \"anyhow\"
+-version = \"1.0.0\"
++version = \"1.0.1\"
+@@ -40,3 +40,3 @@
+ source = \"registry+https://github.com/rust-lang/crates.io-index\"
+-version = \"2.0.0\"
++version = \"2.1.0\"
+";
+        let collapsed = collapse_lockfiles(diff);
+        assert!(collapsed.contains("0 dependencies added, 1 updated, 0 removed"));
+        assert!(collapsed.contains("[updated: anyhow 1.0.0 -> 1.0.1]\n"));
+        assert!(collapsed.ends_with(
+            "[1 hunk(s) change versions without naming the package; shown as they are]\n\
+             @@ -40,3 +40,3 @@\n \
+             source = \"registry+https://github.com/rust-lang/crates.io-index\"\n\
+             -version = \"2.0.0\"\n\
+             +version = \"2.1.0\"\n"
+        ));
+    }
+
+    #[test]
+    fn collapse_lockfiles_keeps_compact_hunks_it_cannot_attribute() {
+        // `--compact-diff` leaves out the `name` line above a version bump.
+        let diff = "\
+diff --git a/Cargo.lock b/Cargo.lock
+--- a/Cargo.lock
++++ b/Cargo.lock
+@@ -12 +12 @@
+-version = \"1.0.0\"
++version = \"1.0.1\"
+@@ -30,0 +31,5 @@
++
++[[package]]
++name = \"chrono\"
++version = \"0.4.45\"
++source = \"registry+https://github.com/rust-lang/crates.io-index\"
+";
+        let collapsed = collapse_lockfiles(diff);
+        assert!(collapsed.contains("1 dependencies added, 0 updated, 0 removed"));
+        assert!(collapsed.contains("[added: chrono 0.4.45]\n"));
+        assert!(collapsed.ends_with(
+            "[1 hunk(s) change versions without naming the package; shown as they are]\n\
+             @@ -12 +12 @@\n\
+             -version = \"1.0.0\"\n\
+             +version = \"1.0.1\"\n"
+        ));
+    }
+
+    #[test]
+    fn collapse_lockfiles_adds_a_second_version_beside_an_existing_one() {
+        let diff = "\
+diff --git a/Cargo.lock b/Cargo.lock
+--- a/Cargo.lock
++++ b/Cargo.lock
+@@ -20,4 +20,8 @@
+ [[package]]
+ name = \"syn\"
+ version = \"1.0.109\"
+ 
++[[package]]
++name = \"syn\"
++version = \"2.0.48\"
++
+";
+        let collapsed = collapse_lockfiles(diff);
+        assert!(collapsed.contains("1 dependencies added, 0 updated, 0 removed"));
+        assert!(collapsed.contains("[added: syn 2.0.48]\n"));
+    }
+
+    #[test]
+    fn collapse_lockfiles_removes_one_of_two_versions() {
+        let diff = "\
+diff --git a/Cargo.lock b/Cargo.lock
+--- a/Cargo.lock
++++ b/Cargo.lock
+@@ -20,8 +20,4 @@
+-[[package]]
+-name = \"syn\"
+-version = \"1.0.109\"
+-
+ [[package]]
+ name = \"syn\"
+ version = \"2.0.48\"
+ 
+";
+        let collapsed = collapse_lockfiles(diff);
+        assert!(collapsed.contains("0 dependencies added, 0 updated, 1 removed"));
+        assert!(collapsed.contains("[removed: syn 1.0.109]\n"));
+    }
+
+    #[test]
+    fn collapse_lockfiles_summarizes_package_lock() {
+        let diff = "\
+diff --git a/web/package-lock.json b/web/package-lock.json
+--- a/web/package-lock.json
++++ b/web/package-lock.json
+@@ -10,3 +10,3 @@
+     \"node_modules/left-pad\": {
+-      \"version\": \"1.1.0\",
++      \"version\": \"1.3.0\",
+       \"resolved\": \"https://registry.npmjs.org/left-pad\"
+";
+        let collapsed = collapse_lockfiles(diff);
+        assert!(collapsed.contains("0 dependencies added, 1 updated, 0 removed"));
+        assert!(collapsed.contains("[updated: left-pad 1.1.0 -> 1.3.0]"));
+    }
+
+    #[test]
+    fn collapse_lockfiles_leaves_other_files_alone() {
+        let diff = "diff --git a/src/lock.rs b/src/lock.rs\n--- a/src/lock.rs\n+++ b/src/lock.rs\n@@ -1 +1 @@\n-a\n+b\n";
+        assert_eq!(collapse_lockfiles(diff), diff);
+    }
+}
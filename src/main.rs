@@ -1,19 +1,66 @@
+mod binary;
+mod build_output;
+mod cache;
 mod client;
+mod diff;
+mod dir_context;
+mod findings;
+mod forge;
+mod generated;
 mod git;
+mod gitlab;
+mod history;
+mod lockfile;
+mod passes;
+mod profile;
 mod prompt;
+mod redact;
+mod select;
+mod template;
 mod tools;
+mod tree;
+mod watch;
 
 use anyhow::{anyhow, Context, Result};
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use reqwest::header::{HeaderName, HeaderValue};
+use serde::Serialize;
+use serde_json::json;
+use std::path::Path;
+use std::process::ExitCode;
+use std::time::Duration;
+use tokio::time::Instant;
 
-use client::dto::{ChatRequest, Message};
+use cache::{CacheEntry, ReviewCache};
 use client::OpenAIClient;
-use git::get_git_data;
-use prompt::{create_user_prompt, get_system_prompt};
-use tools::tool_definitions;
+use client::dto::{ChatRequest, Message, ReasoningEffort, ResponseFormat, Tool, Usage};
+use findings::Review;
+use git::{
+    DiffBase, DiffFilter, DiffMode, DiffOptions, Git, GitData, TestFiles, explain_empty_diff,
+    get_git_data,
+};
+use gitlab::GitLabClient;
+use passes::Pass;
+use profile::Profile;
+use prompt::{UserPromptInput, create_user_prompt, get_system_prompt};
+use redact::Redactor;
+use select::Selector;
+use template::Template;
+use tools::{Inspection, MAX_SEARCH_MATCHES_CEILING, ToolContext, tool_definitions};
 
 const DEFAULT_MODEL: &str = "openai/gpt-5.2";
 const MAX_TOOL_CALLS: usize = 8;
+/// Calls to tools that do not exist which are answered (with the list of real
+/// tools) without counting against `MAX_TOOL_CALLS`.
+const MAX_FREE_UNKNOWN_TOOL_CALLS: usize = 3;
+const REVIEW_TEMPLATE_PATH: &str = ".blart/template.md";
+const REVIEW_TEMPLATE_FIELDS: &[&str] = &["review", "title", "branch", "model", "reviewer"];
+const REDACT_PATTERNS_PATH: &str = ".blart/redact.txt";
+const GENERATED_MARKERS_PATH: &str = ".blart/generated-markers.txt";
+const SEVERITY_GUIDE_PATH: &str = ".blart/severity.md";
+/// Characters of `--previous-review` kept in the prompt. A review's summary
+/// and most serious findings come first, so the start is what is kept.
+const MAX_PREVIOUS_REVIEW_CHARS: usize = 10_000;
 
 #[derive(Parser, Debug)]
 #[command(name = "blart")]
@@ -26,22 +73,121 @@ struct Cli {
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Run a code review on the current git branch
-    Review(ReviewArgs),
+    Review(Box<ReviewArgs>),
+    /// List the model IDs the API backend offers
+    Models(ApiArgs),
+    /// Check that git, the API key and the API endpoint are set up for `review`
+    Doctor(DoctorArgs),
+    /// List the tools `review` offers the model
+    Tools(ToolsArgs),
 }
 
 #[derive(Parser, Debug)]
+struct ToolsArgs {
+    /// Print the tool definitions, with their parameter schemas, as the JSON sent to the API
+    #[arg(long)]
+    json: bool,
+
+    /// Include `get_diff`, which `review` offers only with --diff-as-tool
+    #[arg(long)]
+    diff_as_tool: bool,
+}
+
+#[derive(Parser, Debug)]
+struct DoctorArgs {
+    #[command(flatten)]
+    api: ApiArgs,
+
+    /// Branch `review` will compare against
+    #[arg(long, default_value = "main")]
+    default_branch: String,
+
+    /// Print each git command to stderr before it runs, and its exit status after
+    #[arg(long)]
+    debug_git: bool,
+}
+
+/// How to reach the OpenAI-compatible API.
+#[derive(Parser, Debug, Clone)]
+struct ApiArgs {
+    /// OpenAI API key (if not provided, will use OPENAI_API_KEY environment variable)
+    #[arg(long)]
+    api_key: Option<String>,
+
+    /// Base URL of the OpenAI-compatible API (takes precedence over OPENAI_BASE_URL)
+    #[arg(long)]
+    base_url: Option<String>,
+
+    /// Extra HTTP header to send with API requests, as `Name: value` (repeatable)
+    #[arg(long = "header", value_name = "NAME:VALUE", value_parser = parse_header)]
+    headers: Vec<(HeaderName, HeaderValue)>,
+
+    /// Never contact the API (also set by BLART_OFFLINE=1): every request fails before it is
+    /// sent, and `review` prints the requests it would make instead
+    #[arg(long)]
+    offline: bool,
+}
+
+/// What blart prints on stdout.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    /// Progress lines followed by the model's response
+    Text,
+    /// A single JSON object with a `status` field; progress goes to stderr
+    Json,
+    /// One GitHub Actions `::error`/`::warning`/`::notice` command per finding (needs
+    /// --structured); progress goes to stderr
+    GithubActions,
+}
+
+/// The role the standing instructions are sent under.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum SystemRole {
+    System,
+    /// The newer OpenAI convention, required by some reasoning models
+    Developer,
+}
+
+impl SystemRole {
+    /// `developer` for OpenAI's o-series reasoning models (with or without an
+    /// `openai/` prefix), `system` for everything else.
+    fn for_model(model: &str) -> Self {
+        let name = model.strip_prefix("openai/").unwrap_or(model);
+        let is_o_series = name
+            .strip_prefix('o')
+            .is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_digit()));
+        if is_o_series {
+            SystemRole::Developer
+        } else {
+            SystemRole::System
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            SystemRole::System => "system",
+            SystemRole::Developer => "developer",
+        }
+    }
+}
+
+#[derive(Parser, Debug, Clone)]
 struct ReviewArgs {
     /// Default branch name to compare against
     #[arg(long, default_value = "main")]
     default_branch: String,
 
+    /// Review the checkout at this path (e.g. another `git worktree`), as if blart were
+    /// started there; relative paths in other options are resolved from it too
+    #[arg(short = 'C', long, value_name = "PATH")]
+    worktree: Option<String>,
+
     /// If set, do not make any changes, just print what would be done
     #[arg(long)]
     dry_run: bool,
 
-    /// OpenAI API key (if not provided, will use OPENAI_API_KEY environment variable)
-    #[arg(long)]
-    api_key: Option<String>,
+    #[command(flatten)]
+    api: ApiArgs,
 
     /// Additional context to add to the user prompt
     #[arg(long, default_value = "")]
@@ -55,88 +201,1482 @@ struct ReviewArgs {
     )]
     reasoning_effort: String,
 
+    /// Cap on reasoning tokens; sends reasoning_effort as `{"effort", "max_tokens"}`
+    /// instead of a bare level, for backends that accept that form
+    #[arg(long)]
+    reasoning_max_tokens: Option<u32>,
+
     /// OpenAI model to use for the review
     #[arg(long, default_value = DEFAULT_MODEL)]
     model: String,
+
+    /// Include a `git diff --stat` summary in the user prompt
+    #[arg(long)]
+    diff_stat: bool,
+
+    /// Maximum number of matches search_files returns per call
+    #[arg(
+        long,
+        default_value_t = tools::DEFAULT_MAX_SEARCH_MATCHES,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new()
+            .range(1..=MAX_SEARCH_MATCHES_CEILING as u64)
+    )]
+    max_search_matches: usize,
+
+    /// Threads the search tools scan files on (default: the number of CPUs)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    concurrency: Option<u32>,
+
+    /// Replace the built-in review instructions with this file (takes precedence over
+    /// BLART_SYSTEM_PROMPT). The tool guide is still prepended when tools are on
+    #[arg(long, value_name = "PATH")]
+    system_prompt_file: Option<String>,
+
+    /// Append this text to the system prompt, e.g. "Be terse" or "Focus on security".
+    /// Combines with --system-prompt-file and BLART_SYSTEM_PROMPT
+    #[arg(long, value_name = "TEXT")]
+    system_suffix: Option<String>,
+
+    /// Role of the message carrying the system prompt [default: developer for OpenAI
+    /// o-series models, system otherwise]
+    #[arg(long, value_enum, value_name = "ROLE")]
+    system_role: Option<SystemRole>,
+
+    /// Order search_files results by relevance: files changed in the diff first, then
+    /// files with the most matches per line, so the match limit drops the least relevant
+    #[arg(long)]
+    rank_search_results: bool,
+
+    /// Write the system prompt, user prompt and tool definitions to this file and exit
+    /// without calling the API
+    #[arg(long, value_name = "PATH", conflicts_with = "dry_run")]
+    dump_prompt: Option<String>,
+
+    /// Leave the diff out of the prompt (sending the diff stat instead) and offer a
+    /// get_diff tool that returns it, whole or per file
+    #[arg(long, conflicts_with = "no_tools")]
+    diff_as_tool: bool,
+
+    /// Print how long the git commands and each model turn (request plus tool calls)
+    /// took to stderr at the end of the review
+    #[arg(long)]
+    profile: bool,
+
+    /// Print the tokens each model turn used to stderr as the review runs, with a
+    /// running total
+    #[arg(long)]
+    usage_per_turn: bool,
+
+    /// Print each git command to stderr before it runs, and its exit status after
+    #[arg(long)]
+    debug_git: bool,
+
+    /// Review only the hunks whose `@@` header (the function context git prints) or
+    /// lines mention this function name
+    #[arg(long, value_name = "NAME")]
+    function: Option<String>,
+
+    /// Send the diff without context lines, only the changed ones, and ask the model to
+    /// read_file around them; the smallest diff prompt
+    #[arg(long, conflicts_with = "no_tools")]
+    compact_diff: bool,
+
+    /// Send a single request without tools, using a system prompt without the tool guide
+    #[arg(long)]
+    no_tools: bool,
+
+    /// Abort the review if any tool call returns an error instead of passing it to the model
+    #[arg(long, conflicts_with = "no_tools")]
+    strict_tools: bool,
+
+    /// Sampling temperature (0.0-2.0). Ignored unless --reasoning-effort is `none`,
+    /// because reasoning models reject it
+    #[arg(long, value_parser = parse_temperature)]
+    temperature: Option<f32>,
+
+    /// After the review, list the files the model read and the searches it ran
+    #[arg(long)]
+    show_inspected: bool,
+
+    /// Only review changes made after this commit (e.g. the HEAD of a previous review)
+    /// instead of everything since the merge base with the default branch
+    #[arg(long, value_name = "SHA", conflicts_with = "default_branch")]
+    since_commit: Option<String>,
+
+    /// With --since-commit, include the saved output of the previous review in the prompt
+    /// so the model knows what was already raised
+    #[arg(long, value_name = "PATH", requires = "since_commit")]
+    previous_review: Option<String>,
+
+    /// Review all uncommitted changes to tracked files (staged and unstaged) against HEAD
+    #[arg(long, conflicts_with_all = ["default_branch", "since_commit"])]
+    working_tree: bool,
+
+    /// Compare against the merge base with the current branch's upstream (`@{upstream}`)
+    /// instead of the default branch
+    #[arg(long, conflicts_with_all = ["default_branch", "since_commit", "working_tree"])]
+    upstream: bool,
+
+    /// Review the changes saved in `stash@{N}` instead of the current branch. Requires
+    /// --no-tools, since the tools read the working tree rather than the stash
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "no_tools",
+        conflicts_with_all = ["default_branch", "since_commit", "working_tree", "upstream"]
+    )]
+    stash: Option<u32>,
+
+    /// Compare directly against this tag, branch or commit (e.g. the last release)
+    /// instead of the merge base with the default branch
+    #[arg(
+        long,
+        value_name = "REF",
+        conflicts_with_all = ["default_branch", "since_commit", "working_tree", "upstream", "stash"]
+    )]
+    base: Option<String>,
+
+    /// How the branch compared against is diffed: `three-dot` (`git diff A...B`) from
+    /// its merge base with HEAD, or `two-dot` (`git diff A..B`) against its tip, which
+    /// also shows, reversed, what landed there since the branches diverged. Defaults to
+    /// three-dot for the default branch and --upstream, and two-dot for --base
+    #[arg(
+        long,
+        value_enum,
+        conflicts_with_all = ["since_commit", "working_tree", "stash"]
+    )]
+    diff_mode: Option<DiffMode>,
+
+    /// Only review files with these change types, as in `git diff --diff-filter`
+    /// (e.g. `A` for added files, `d` to exclude deletions)
+    #[arg(long, value_name = "ACDMRTUXB", value_parser = DiffFilter::parse)]
+    diff_filter: Option<DiffFilter>,
+
+    /// Leave deleted files out of the diff (as `--diff-filter=d` does) and only
+    /// list their paths in the prompt
+    #[arg(long, conflicts_with = "diff_filter")]
+    skip_deletions: bool,
+
+    /// Leave test files (`tests/` directories, `*_test.*`, `*.test.*`, `test_*.py`,
+    /// `*_spec.rb`) out of the review
+    #[arg(long, conflicts_with = "tests_only")]
+    exclude_test_files: bool,
+
+    /// Only review test files (the paths --exclude-test-files leaves out)
+    #[arg(long)]
+    tests_only: bool,
+
+    /// Only review files matching one of these selectors, `TYPES:GLOB`: change types
+    /// (added, deleted, modified, renamed, copied, comma-separated, or any) and a glob
+    /// over the path, e.g. `added:*.rs`. Repeatable
+    #[arg(long, value_name = "TYPES:GLOB", value_parser = Selector::parse)]
+    select: Vec<Selector>,
+
+    /// Replace the body of any diff hunk longer than this many lines with a placeholder
+    #[arg(long, value_name = "LINES", value_parser = clap::value_parser!(u32).range(1..))]
+    max_hunk_lines: Option<u32>,
+
+    /// Show only the first N hunks of each file, with a note counting the rest
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    max_hunks_per_file: Option<u32>,
+
+    /// Sent as `prompt_cache_key` so repeated reviews share the cached system prompt
+    /// (OpenAI; other backends may reject unknown fields)
+    #[arg(long, value_name = "KEY")]
+    prompt_cache_key: Option<String>,
+
+    /// Stop generating at this sequence, sent as `stop` (repeatable)
+    #[arg(long = "stop", value_name = "SEQUENCE")]
+    stop_sequences: Vec<String>,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Exit with this status code when there is nothing to review, instead of 0
+    #[arg(long, value_name = "CODE", value_parser = clap::value_parser!(u8).range(1..))]
+    no_changes_exit_code: Option<u8>,
+
+    /// Title for the review: a heading in text mode (or `{{title}}` in the template),
+    /// a `title` field in JSON
+    #[arg(long, value_name = "TITLE")]
+    review_title: Option<String>,
+
+    /// Name identifying this reviewer among others posting to the same place: a
+    /// `Reviewer:` line in text mode (or `{{reviewer}}` in the template) and the footer,
+    /// a `reviewer` field in JSON
+    #[arg(long, value_name = "NAME")]
+    reviewer_name: Option<String>,
+
+    /// In text mode, end the output with a footer recording the blart version, model and commits
+    /// reviewed (JSON output always carries this as `metadata`)
+    #[arg(long)]
+    footer: bool,
+
+    /// Also post the text review as a note on the open GitLab merge request for the current
+    /// branch, found from the remote's URL; the access token is read from GITLAB_TOKEN
+    #[arg(long)]
+    post_gitlab: bool,
+
+    /// Base URL of the GitLab instance for --post-gitlab, e.g. https://gitlab.example.com
+    /// (default: the remote's host over HTTPS)
+    #[arg(long, value_name = "URL", requires = "post_gitlab")]
+    gitlab_url: Option<String>,
+
+    /// Send lockfile diffs (Cargo.lock, package-lock.json, yarn.lock, poetry.lock) in full
+    /// instead of collapsing them to a summary of dependency changes
+    #[arg(long)]
+    full_lockfile_diffs: bool,
+
+    /// Ask for a JSON review with a `findings` array (file, line, severity, message, confidence)
+    #[arg(long, required_if_eq("format", "github-actions"))]
+    structured: bool,
+
+    /// Make the model answer with JSON matching the JSON Schema in this file instead of the
+    /// built-in review format. The answer is printed as is, or wrapped in the envelope with
+    /// --format json
+    #[arg(long, value_name = "PATH", conflicts_with = "structured")]
+    json_schema_file: Option<String>,
+
+    /// In text mode, print each structured finding through this template instead of the JSON.
+    /// Placeholders: {{severity}}, {{file}}, {{line}}, {{message}}, {{confidence}}, {{pass}}.
+    /// Without a value, uses `[{{severity}}] {{file}}:{{line}}: {{message}}`
+    #[arg(
+        long,
+        requires = "structured",
+        value_name = "TEMPLATE",
+        num_args = 0..=1,
+        default_missing_value = findings::DEFAULT_FINDING_TEMPLATE,
+        value_parser = parse_finding_template
+    )]
+    output_template: Option<Template>,
+
+    /// Save each structured finding's suggested patch to this directory as a `.patch`
+    /// file for `git apply`; patches that are not valid unified diffs are skipped
+    #[arg(long, requires = "structured", value_name = "DIR")]
+    write_patches: Option<String>,
+
+    /// Review once per focus area and combine the findings, labelled by pass. Built in:
+    /// security, performance, style; .blart/passes/<name>.md overrides or adds one
+    #[arg(
+        long,
+        value_name = "PASS",
+        value_delimiter = ',',
+        requires = "structured"
+    )]
+    passes: Vec<String>,
+
+    /// After the review, send it back to the model with the diff to drop false positives
+    /// and add what it missed; the refined review replaces the first. Costs a second run
+    #[arg(long, conflicts_with = "passes")]
+    critique: bool,
+
+    /// Drop structured findings whose confidence is below this threshold (0.0-1.0)
+    #[arg(long, requires = "structured", value_parser = parse_confidence)]
+    min_confidence: Option<f64>,
+
+    /// Keep only the N most important structured findings (by severity, then
+    /// confidence), noting how many were left out. The model is asked to prioritize
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "structured",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    max_findings: Option<u32>,
+
+    /// Include excerpts of the README nearest to each changed directory in the prompt
+    #[arg(long)]
+    directory_context: bool,
+
+    /// Include the directory tree around the changed files in the prompt: the path to
+    /// each one and the other entries of its directory
+    #[arg(long)]
+    directory_tree: bool,
+
+    /// Include this file's contents (e.g. a failing test or build log) in the prompt as
+    /// BUILD/TEST OUTPUT for the model to relate to the diff (repeatable)
+    #[arg(long = "context-file", value_name = "PATH")]
+    context_files: Vec<String>,
+
+    /// Include the subjects of the last few commits touching each changed file (before
+    /// this change) in the prompt, so the model sees how the file has been evolving
+    #[arg(long)]
+    file_history: bool,
+
+    /// Review generated files too; by default changed files with a marker such as
+    /// `@generated` or `DO NOT EDIT` in their first lines are left out
+    #[arg(long)]
+    include_generated: bool,
+
+    /// Replace likely secrets in the diff and tool results with [REDACTED] before sending them
+    #[arg(long)]
+    redact: bool,
+
+    /// Give up after this many seconds. When three quarters of the time has passed, the
+    /// model is told to stop using tools and give its review with what it has
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    deadline_secs: Option<u64>,
+
+    /// Store each review in this directory, keyed by a hash of the prompts, model and
+    /// reasoning settings, and reuse it instead of calling the API when they match
+    #[arg(long, value_name = "DIR")]
+    cache_dir: Option<String>,
+
+    /// Ignore cached reviews; the fresh review is still stored
+    #[arg(long, requires = "cache_dir")]
+    no_cache: bool,
+
+    /// Seconds after which a cached review expires and is deleted
+    #[arg(long, value_name = "SECS", default_value_t = 7 * 24 * 60 * 60)]
+    cache_max_age_secs: u64,
+
+    /// Keep running, and review again whenever the working tree changes
+    #[arg(long, conflicts_with = "no_changes_exit_code")]
+    watch: bool,
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> Result<ExitCode> {
     let cli = Cli::parse();
 
-    match cli.command {
-        Commands::Review(args) => run_review(args).await,
+    if let Commands::Review(args) = &cli.command
+        && let Some(path) = &args.worktree
+    {
+        // Changing directory rather than passing it to each git command also
+        // points the tools, the `.blart` config files and --watch at `path`.
+        std::env::set_current_dir(path)
+            .with_context(|| format!("Failed to change to worktree {}", path))?;
+    }
+    let result = match cli.command {
+        Commands::Review(args) if args.watch => watch_reviews(*args).await,
+        Commands::Review(args) => run_review_until_cancelled(*args).await,
+        Commands::Models(api) => run_models(api).await,
+        Commands::Doctor(args) => run_doctor(args).await,
+        Commands::Tools(args) => {
+            print!(
+                "{}",
+                format_tools(&tool_definitions(args.diff_as_tool), args.json)?
+            );
+            Ok(())
+        }
+    };
+    match result {
+        Err(err) if err.is::<Cancelled>() => {
+            eprintln!("{}", err);
+            Ok(ExitCode::from(130))
+        }
+        result => result.map(|()| ExitCode::SUCCESS),
+    }
+}
+
+/// The error a review ends with when Ctrl-C interrupts it. `main` turns it
+/// into exit code 130, the shell's code for a SIGINT.
+#[derive(Debug)]
+struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Review cancelled.")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+fn build_client(api: ApiArgs) -> Result<OpenAIClient> {
+    let offline = resolve_offline(api.offline, std::env::var("BLART_OFFLINE").ok());
+    let api_key = api.api_key.or_else(|| std::env::var("OPENAI_API_KEY").ok());
+    // Offline, the key is never sent, so it need not be set.
+    let api_key = match api_key {
+        None if offline => String::new(),
+        api_key => api_key.context("OpenAI API key must be provided via --api-key argument or OPENAI_API_KEY environment variable")?,
+    };
+
+    let mut client = OpenAIClient::new(api_key);
+    if offline {
+        client = client.offline();
+    }
+    let base_url = resolve_base_url(api.base_url, std::env::var("OPENAI_BASE_URL").ok());
+    if let Some(base_url) = base_url {
+        client = client.with_base_url(base_url);
+    }
+    for (name, value) in api.headers {
+        client = client.with_header(name, value);
+    }
+    Ok(client)
+}
+
+async fn run_models(api: ApiArgs) -> Result<()> {
+    let client = build_client(api)?;
+    let mut ids: Vec<String> = client
+        .list_models()
+        .await?
+        .data
+        .into_iter()
+        .map(|model| model.id)
+        .collect();
+    ids.sort();
+    for id in ids {
+        println!("{}", id);
+    }
+    Ok(())
+}
+
+/// One line of `blart doctor` output: what was checked, and on failure a
+/// hint for fixing it.
+struct Check {
+    description: String,
+    outcome: Result<(), String>,
+}
+
+async fn run_doctor(args: DoctorArgs) -> Result<()> {
+    let mut checks = Vec::new();
+
+    let git = Git {
+        debug: args.debug_git,
+    };
+    let repository = git::ensure_git_repository(git);
+    let in_repository = repository.is_ok();
+    checks.push(Check {
+        description: "git is installed and this is a git repository".to_string(),
+        outcome: repository.map_err(|err| format!("{:#}", err)),
+    });
+    if in_repository {
+        checks.push(Check {
+            description: format!("default branch `{}` exists", args.default_branch),
+            outcome: if git::branch_exists(git, &args.default_branch) {
+                Ok(())
+            } else {
+                Err(
+                    "pass --default-branch with the branch you merge into (e.g. `master`)"
+                        .to_string(),
+                )
+            },
+        });
+    }
+
+    let has_api_key = args.api.api_key.is_some() || std::env::var("OPENAI_API_KEY").is_ok();
+    checks.push(Check {
+        description: "an API key is set".to_string(),
+        outcome: if has_api_key {
+            Ok(())
+        } else {
+            Err("set OPENAI_API_KEY or pass --api-key".to_string())
+        },
+    });
+    if has_api_key {
+        let client = build_client(args.api.clone())?;
+        let outcome = match client.list_models().await {
+            Ok(_) => Ok(()),
+            Err(err) => Err(format!(
+                "{:#}\ncheck --base-url / OPENAI_BASE_URL and that the key is valid for it",
+                err
+            )),
+        };
+        checks.push(Check {
+            description: format!("the API at {} lists models", client.base_url()),
+            outcome,
+        });
+    }
+
+    print!("{}", format_checks(&checks));
+    let failed = checks.iter().filter(|check| check.outcome.is_err()).count();
+    if failed > 0 {
+        return Err(anyhow!("{} check(s) failed", failed));
+    }
+    Ok(())
+}
+
+fn format_checks(checks: &[Check]) -> String {
+    let mut output = String::new();
+    for check in checks {
+        match &check.outcome {
+            Ok(()) => output.push_str(&format!("[ok]   {}\n", check.description)),
+            Err(hint) => {
+                output.push_str(&format!("[FAIL] {}\n", check.description));
+                for line in hint.lines() {
+                    output.push_str(&format!("       {}\n", line));
+                }
+            }
+        }
+    }
+    output
+}
+
+/// `review --watch`: one review now, then another after each change to the
+/// working tree. Changes made during a review are skipped rather than queued,
+/// so runs never overlap. A failed review is reported and watching continues.
+async fn watch_reviews(args: ReviewArgs) -> Result<()> {
+    let mut watcher = watch::ChangeWatcher::new(Path::new("."))?;
+    loop {
+        match run_review_until_cancelled(args.clone()).await {
+            Err(err) if err.is::<Cancelled>() => return Err(err),
+            Err(err) => eprintln!("Review failed: {:#}", err),
+            Ok(()) => {}
+        }
+        watcher.skip_pending();
+        eprintln!("Watching for changes (Ctrl-C to stop)...");
+        tokio::select! {
+            changed = watcher.wait_for_change() => changed?,
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        }
+    }
+}
+
+/// Runs one review, or fails with `Cancelled` on Ctrl-C. Dropping the review
+/// future aborts any in-flight request and runs its destructors as usual.
+async fn run_review_until_cancelled(args: ReviewArgs) -> Result<()> {
+    tokio::select! {
+        result = run_review(args) => result,
+        _ = tokio::signal::ctrl_c() => Err(Cancelled.into()),
     }
 }
 
 async fn run_review(args: ReviewArgs) -> Result<()> {
-    let git_data = get_git_data(&args.default_branch)?;
+    let started = Instant::now();
+    let budget = args.deadline_secs.map(Duration::from_secs);
+    let deadline = budget.map(|budget| started + budget);
+    let wrap_up_at = budget.map(|budget| started + budget * 3 / 4);
+    // --base defaults to two-dot; the default branch and upstream to three-dot.
+    let base = match &args.since_commit {
+        Some(commit) => DiffBase::SinceCommit(commit.clone()),
+        None if let Some(index) = args.stash => DiffBase::Stash(index),
+        None if let Some(reference) = &args.base => match args.diff_mode {
+            Some(DiffMode::ThreeDot) => DiffBase::MergeBase(reference.clone()),
+            _ => DiffBase::Ref(reference.clone()),
+        },
+        None if args.working_tree => DiffBase::WorkingTree,
+        None if args.upstream => DiffBase::Upstream(args.diff_mode.unwrap_or(DiffMode::ThreeDot)),
+        None => match args.diff_mode {
+            Some(DiffMode::TwoDot) => DiffBase::Ref(args.default_branch.clone()),
+            _ => DiffBase::MergeBase(args.default_branch.clone()),
+        },
+    };
+    let test_files = if args.exclude_test_files {
+        TestFiles::Exclude
+    } else if args.tests_only {
+        TestFiles::Only
+    } else {
+        TestFiles::Include
+    };
+    let mut diff_options = DiffOptions {
+        diff_filter: args.diff_filter.clone(),
+        test_files,
+        excluded_paths: Vec::new(),
+        compact: args.compact_diff,
+        skip_deletions: args.skip_deletions,
+    };
+    let git = Git {
+        debug: args.debug_git,
+    };
+    let mut profile = Profile::default();
+    // Progress goes to stderr: on large repositories the git steps can take
+    // seconds, and stdout is reserved for the review itself.
+    let git_started = Instant::now();
+    eprintln!("Resolving diff base...");
+    let range = git::resolve_range(git, &base)?;
+    if let Some(upstream) = &range.upstream {
+        eprintln!("Comparing against upstream `{}`", upstream);
+    }
+    eprintln!("Collecting diff...");
+    let mut git_data = get_git_data(git, &range, &diff_options)?;
+    eprintln!("Found {} changed file(s)", git_data.files_changed.len());
+    profile.record_git(git_started.elapsed());
+    // A stash is reviewed as if it were checked out, so files are read from
+    // it rather than from the working tree.
+    let stash_revision = args.stash.map(|_| git_data.head_hash.clone());
+    let read_source = |path: &str| match &stash_revision {
+        Some(revision) => git::read_file_at(git, revision, path)
+            .and_then(|contents| String::from_utf8(contents).ok()),
+        None => std::fs::read_to_string(path).ok(),
+    };
+    // git cannot combine a change type with a pathspec, so `--select` works
+    // like generated files: whatever it rejects is excluded by path.
+    let unselected = if args.select.is_empty() {
+        Vec::new()
+    } else {
+        select::unselected_paths(&git_data.diff, &args.select)
+    };
+    let generated_files = if args.include_generated {
+        Vec::new()
+    } else {
+        let markers = generated::markers(&read_optional_config(GENERATED_MARKERS_PATH)?);
+        let selected: Vec<String> = git_data
+            .files_changed
+            .iter()
+            .filter(|file| !unselected.contains(file))
+            .cloned()
+            .collect();
+        match &stash_revision {
+            Some(revision) => generated::generated_files(&selected, &markers, |path| {
+                git::read_file_at(git, revision, path).map(std::io::Cursor::new)
+            }),
+            None => generated::generated_files(&selected, &markers, |path| {
+                std::fs::File::open(path).ok()
+            }),
+        }
+    };
+    if !unselected.is_empty() || !generated_files.is_empty() {
+        for file in &generated_files {
+            eprintln!("Skipping generated file {}", file);
+        }
+        diff_options.excluded_paths = unselected.iter().chain(&generated_files).cloned().collect();
+        let git_started = Instant::now();
+        git_data = get_git_data(git, &range, &diff_options)?;
+        profile.record_git(git_started.elapsed());
+        if git_data.diff.trim().is_empty() && generated_files.is_empty() {
+            let selectors: Vec<&str> = args.select.iter().map(Selector::as_str).collect();
+            return report_no_changes(
+                &args,
+                &format!(
+                    "No changes detected matching --select {}.",
+                    selectors.join(" --select ")
+                ),
+            );
+        }
+        if git_data.diff.trim().is_empty() {
+            return report_no_changes(
+                &args,
+                "No changes detected: every changed file is generated; pass --include-generated \
+                 to review them.",
+            );
+        }
+    }
+    let template = load_review_template()?;
+    let redactor = if args.redact {
+        Some(load_redactor()?)
+    } else {
+        None
+    };
+    let gitlab_target = if args.post_gitlab {
+        Some(gitlab_target(&args, &git_data)?)
+    } else {
+        None
+    };
 
+    if git_data.diff.trim().is_empty() && !git_data.deleted_files.is_empty() {
+        return report_no_changes(
+            &args,
+            &format!(
+                "No changes detected besides {} deleted file(s), which --skip-deletions leaves out.",
+                git_data.deleted_files.len()
+            ),
+        );
+    }
     if git_data.diff.trim().is_empty() {
-        println!("No changes detected.");
-        return Ok(());
+        let message = match diff_options.describe_restrictions() {
+            Some(restrictions) => format!("No changes detected matching {}.", restrictions),
+            None => explain_empty_diff(&git_data, &base),
+        };
+        return report_no_changes(&args, &message);
+    }
+    if let Some(function) = &args.function {
+        git_data.diff = diff::hunks_mentioning(&git_data.diff, function);
+        if git_data.diff.is_empty() {
+            return report_no_changes(
+                &args,
+                &format!("No changed hunks mention function `{}`.", function),
+            );
+        }
+        let kept: Vec<String> = diff::parse_diff(&git_data.diff)
+            .iter()
+            .flat_map(|file| [file.old_path(), file.new_path()])
+            .flatten()
+            .map(str::to_string)
+            .collect();
+        git_data.files_changed.retain(|file| kept.contains(file));
+        git_data.diff_stat = diff::diff_stat(&git_data.diff);
+        git_data.renames.retain(|rename| kept.contains(&rename.to));
+        git_data
+            .mode_changes
+            .retain(|change| kept.contains(&change.path));
     }
     if git_data.files_changed.is_empty() {
-        println!("No changed files detected.");
-        return Ok(());
+        return report_no_changes(&args, "No changed files detected.");
     }
 
-    let system_prompt = get_system_prompt();
+    let prompt_file = match &args.system_prompt_file {
+        Some(path) => Some(
+            std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read system prompt file {}", path))?,
+        ),
+        None => None,
+    };
+    let base_prompt = resolve_base_prompt(prompt_file, std::env::var("BLART_SYSTEM_PROMPT").ok());
+    let passes = passes::resolve_passes(&args.passes, Path::new(passes::PASSES_DIR))?;
+    let base_prompt = base_prompt
+        .as_deref()
+        .unwrap_or(prompt::DEFAULT_BASE_PROMPT);
+    let system_suffix = args
+        .system_suffix
+        .as_deref()
+        .filter(|suffix| !suffix.trim().is_empty());
+    let severity_guide = if args.structured {
+        read_optional_config(SEVERITY_GUIDE_PATH)?
+    } else {
+        String::new()
+    };
+    let severity_guide = Some(severity_guide.as_str()).filter(|guide| !guide.trim().is_empty());
+    let custom_format = match &args.json_schema_file {
+        Some(path) => {
+            let schema = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path))?;
+            let format = findings::custom_response_format(&schema)
+                .with_context(|| format!("Invalid JSON schema in {}", path))?;
+            Some(format)
+        }
+        None => None,
+    };
+    // Backends that ignore `response_format` still see the schema this way.
+    let schema_instructions = match custom_format
+        .as_ref()
+        .and_then(|format| format.json_schema.as_ref())
+    {
+        Some(json_schema) => Some(format!(
+            "Instead of the JSON format described above, answer with a JSON object matching \
+             this JSON Schema, and nothing else:\n{}",
+            serde_json::to_string_pretty(&json_schema.schema)?
+        )),
+        None => None,
+    };
+    let with_patches = args.write_patches.is_some();
+    let response_format = custom_format.or_else(|| {
+        args.structured
+            .then(|| findings::response_format(with_patches))
+    });
+    let patch_instructions = with_patches.then_some(
+        "Also give each finding a \"patch\": a unified diff (with \"--- a/<file>\" and \
+         \"+++ b/<file>\" headers and correctly counted \"@@\" hunks) that fixes the issue and \
+         applies to the new version of the file, or null if you have no concrete fix.",
+    );
+    let max_findings_instructions = args.max_findings.map(|max| {
+        format!(
+            "Report at most {} findings. If you find more, keep the ones that matter most: \
+             higher severity first, then those you are most confident about.",
+            max
+        )
+    });
+    let system_prompt_for = |pass: Option<&Pass>| {
+        let parts: Vec<&str> = [
+            schema_instructions.as_deref(),
+            max_findings_instructions.as_deref(),
+            patch_instructions,
+            system_suffix,
+            pass.map(|pass| pass.prompt.as_str()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        let suffix = (!parts.is_empty()).then(|| parts.join("\n\n"));
+        get_system_prompt(
+            base_prompt,
+            !args.no_tools,
+            args.structured,
+            severity_guide,
+            suffix.as_deref(),
+        )
+    };
+    // One conversation per pass, or a single one without --passes.
+    let runs: Vec<(Option<&Pass>, String)> = if passes.is_empty() {
+        vec![(None, system_prompt_for(None))]
+    } else {
+        passes
+            .iter()
+            .map(|pass| (Some(pass), system_prompt_for(Some(pass))))
+            .collect()
+    };
     let additional_prompt = if args.additional_prompt.trim().is_empty() {
         None
     } else {
         Some(args.additional_prompt.as_str())
     };
-    let user_prompt = create_user_prompt(&git_data.diff, &git_data.files_changed, additional_prompt);
+    // Without the diff in the prompt, the stat is the model's overview of it.
+    let diff_stat = (args.diff_stat || args.diff_as_tool).then_some(git_data.diff_stat.as_str());
+    let prompt_diff = if args.full_lockfile_diffs {
+        git_data.diff.clone()
+    } else {
+        lockfile::collapse_lockfiles(&git_data.diff)
+    };
+    let prompt_diff = match args.max_hunks_per_file {
+        Some(max_hunks) => diff::limit_hunks_per_file(&prompt_diff, max_hunks as usize),
+        None => prompt_diff,
+    };
+    let prompt_diff = match args.max_hunk_lines {
+        Some(max_lines) => diff::elide_large_hunks(&prompt_diff, max_lines as usize),
+        None => prompt_diff,
+    };
+    let prompt_diff = match &redactor {
+        Some(redactor) => {
+            let (redacted, count) = redactor.redact(&prompt_diff);
+            if count > 0 {
+                eprintln!("Warning: redacted {} likely secret(s) from the diff", count);
+            }
+            redacted
+        }
+        None => prompt_diff,
+    };
+    let directory_context = if args.directory_context {
+        dir_context::directory_context(&git_data.files_changed)
+    } else {
+        None
+    };
+    let directory_context = match (directory_context, &redactor) {
+        (Some(context), Some(redactor)) => {
+            let (redacted, count) = redactor.redact(&context);
+            if count > 0 {
+                eprintln!(
+                    "Warning: redacted {} likely secret(s) from the directory context",
+                    count
+                );
+            }
+            Some(redacted)
+        }
+        (context, _) => context,
+    };
+    let directory_tree = if args.directory_tree {
+        tree::changed_tree(&git_data.files_changed)
+    } else {
+        None
+    };
+    let file_history = if args.file_history {
+        let histories: Vec<(String, Vec<String>)> = git_data
+            .files_changed
+            .iter()
+            .take(history::MAX_FILES)
+            .map(|file| {
+                // A renamed file's history is under its old path.
+                let path = git_data
+                    .renames
+                    .iter()
+                    .find(|rename| &rename.to == file)
+                    .map_or(file, |rename| &rename.from);
+                let subjects = git::recent_subjects(
+                    git,
+                    &git_data.base_hash,
+                    path,
+                    history::SUBJECTS_PER_FILE,
+                );
+                (file.clone(), subjects)
+            })
+            .collect();
+        history::format_history(&histories, git_data.files_changed.len())
+    } else {
+        None
+    };
+    let file_history = match (file_history, &redactor) {
+        (Some(history), Some(redactor)) => {
+            let (redacted, count) = redactor.redact(&history);
+            if count > 0 {
+                eprintln!(
+                    "Warning: redacted {} likely secret(s) from the file history",
+                    count
+                );
+            }
+            Some(redacted)
+        }
+        (history, _) => history,
+    };
+    let build_output = if args.context_files.is_empty() {
+        None
+    } else {
+        Some(build_output::build_output(&args.context_files)?)
+    };
+    let build_output = match (build_output, &redactor) {
+        (Some(output), Some(redactor)) => {
+            let (redacted, count) = redactor.redact(&output);
+            if count > 0 {
+                eprintln!(
+                    "Warning: redacted {} likely secret(s) from the context files",
+                    count
+                );
+            }
+            Some(redacted)
+        }
+        (output, _) => output,
+    };
+    let previous_review = args
+        .previous_review
+        .as_deref()
+        .map(read_previous_review)
+        .transpose()?;
+    let user_prompt = create_user_prompt(&UserPromptInput {
+        diff: (!args.diff_as_tool).then_some(prompt_diff.as_str()),
+        files_changed: &git_data.files_changed,
+        renames: &git_data.renames,
+        mode_changes: &git_data.mode_changes,
+        binary_files: &git_data.binary_files,
+        compact_diff: args.compact_diff,
+        additional_prompt,
+        diff_stat,
+        directory_context: directory_context.as_deref(),
+        directory_tree: directory_tree.as_deref(),
+        build_output: build_output.as_deref(),
+        file_history: file_history.as_deref(),
+        previous_review: previous_review.as_deref(),
+        generated_files: &generated_files,
+        deleted_files: &git_data.deleted_files,
+        tools_enabled: !args.no_tools,
+    });
+
+    let review_cache = args
+        .cache_dir
+        .as_ref()
+        .map(|dir| ReviewCache::new(dir, args.cache_max_age_secs));
+    let review_cache_key = |system_prompt: &str| {
+        cache::cache_key(&[
+            system_prompt,
+            &user_prompt,
+            &args.model,
+            &args.reasoning_effort,
+            &format!("{:?}", args.reasoning_max_tokens),
+            &format!("{:?}", args.temperature),
+            &format!("{:?}", args.stop_sequences),
+        ])
+    };
+    let shown_system_prompt = runs
+        .iter()
+        .map(|(pass, system_prompt)| match pass {
+            Some(pass) => format!("({} pass)\n{}", pass.name, system_prompt),
+            None => system_prompt.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    if let Some(path) = &args.dump_prompt {
+        let tools = (!args.no_tools).then(|| tool_definitions(args.diff_as_tool));
+        let dump = format_prompt_dump(&shown_system_prompt, &user_prompt, tools.as_deref())?;
+        std::fs::write(path, dump).with_context(|| format!("Failed to write {}", path))?;
+        eprintln!("Wrote the prompts and tool definitions to {}", path);
+        return Ok(());
+    }
 
     if args.dry_run {
-        println!("System prompt:\n{}", system_prompt);
+        println!("System prompt:\n{}", shown_system_prompt);
         println!("\nUser prompt:\n{}", user_prompt);
         println!("\nModel: {}", args.model);
         println!("Reasoning effort: {}", args.reasoning_effort);
+        if let Some(target) = &gitlab_target {
+            println!(
+                "Would post the review to the open merge request from `{}` in {} on {}",
+                target.branch, target.project, target.base_url
+            );
+        }
         return Ok(());
     }
 
-    let api_key = args
-        .api_key
-        .or_else(|| std::env::var("OPENAI_API_KEY").ok())
-        .context("OpenAI API key must be provided via --api-key argument or OPENAI_API_KEY environment variable")?;
+    let client = build_client(args.api.clone())?;
+    let gitlab = match gitlab_target {
+        Some(target) => {
+            let token = std::env::var("GITLAB_TOKEN")
+                .context("--post-gitlab needs a GitLab access token in GITLAB_TOKEN")?;
+            Some((GitLabClient::new(&target.base_url, token), target))
+        }
+        None => None,
+    };
 
-    let mut client = OpenAIClient::new(api_key);
-    if let Ok(base_url) = std::env::var("OPENAI_BASE_URL") {
-        client = client.with_base_url(base_url);
+    let temperature = resolve_temperature(args.temperature, &args.reasoning_effort);
+    if args.temperature.is_some() && temperature.is_none() {
+        eprintln!(
+            "Warning: ignoring --temperature because reasoning effort `{}` is active; \
+             pass --reasoning-effort none to use it.",
+            args.reasoning_effort
+        );
+    }
+
+    let tools = (!args.no_tools).then(|| tool_definitions(args.diff_as_tool));
+    let mut tool_context = ToolContext::new(args.max_search_matches)
+        .with_changed_lines(diff::added_lines(&git_data.diff))
+        .with_git(git);
+    if args.rank_search_results {
+        tool_context = tool_context.with_search_ranking(&git_data.files_changed);
+    }
+    let concurrency = args.concurrency.map_or_else(
+        || std::thread::available_parallelism().map_or(1, usize::from),
+        |concurrency| concurrency as usize,
+    );
+    if concurrency > 1 {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(concurrency)
+            .build()
+            .context("Failed to start the file scanning threads")?;
+        tool_context = tool_context.with_pool(pool);
+    }
+    if args.diff_as_tool {
+        tool_context = tool_context.with_diff(prompt_diff.clone());
+    }
+
+    let reasoning_effort = match args.reasoning_max_tokens {
+        Some(max_tokens) => ReasoningEffort::Config {
+            effort: args.reasoning_effort.clone(),
+            max_tokens,
+        },
+        None => ReasoningEffort::Level(args.reasoning_effort.clone()),
+    };
+    let conversation = Conversation {
+        args: &args,
+        client: &client,
+        tools,
+        tool_context,
+        redactor: redactor.as_ref(),
+        response_format,
+        reasoning_effort,
+        temperature,
+        deadline,
+        wrap_up_at,
+    };
+    let system_role = args
+        .system_role
+        .unwrap_or_else(|| SystemRole::for_model(&args.model));
+    if client.is_offline() {
+        for (pass, system_prompt) in &runs {
+            if let Some(pass) = pass {
+                println!("({} pass)", pass.name);
+            }
+            let messages = opening_messages(system_role, system_prompt.clone(), &user_prompt);
+            let request = conversation.request(messages, "auto");
+            println!("{}", serde_json::to_string_pretty(&request)?);
+        }
+        eprintln!("Offline: the request(s) above were not sent.");
+        return Ok(());
+    }
+    let now = chrono::Utc::now().timestamp();
+    if let Some(cache) = &review_cache {
+        cache.prune(now);
+    }
+    let mut inspected: Vec<Inspection> = Vec::new();
+    let mut results = Vec::new();
+    for (pass, system_prompt) in runs {
+        if let Some(pass) = pass {
+            progress(args.format, &format!("Running the {} pass", pass.name));
+        }
+        let key = review_cache_key(&system_prompt);
+        let cached = match &review_cache {
+            Some(cache) if !args.no_cache => cache.get(&key, now),
+            _ => None,
+        };
+        // An entry that no longer parses is a miss, like any unreadable one.
+        let cached = cached.and_then(|entry| {
+            if !args.structured {
+                return Some((entry, None));
+            }
+            match findings::parse_review(&entry.content) {
+                Ok(review) => Some((entry, Some(review))),
+                Err(err) => {
+                    eprintln!(
+                        "Warning: ignoring a cached review that does not parse: {:#}",
+                        err
+                    );
+                    None
+                }
+            }
+        });
+        let (content, parsed_review, wrapped_up) = match cached {
+            Some((entry, parsed_review)) => {
+                let stored_at = chrono::DateTime::from_timestamp(entry.created_at, 0)
+                    .map(|time| time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+                    .unwrap_or_else(|| entry.created_at.to_string());
+                progress(
+                    args.format,
+                    &format!("Using the review cached at {}", stored_at),
+                );
+                (entry.content, parsed_review, None)
+            }
+            None => {
+                let messages = opening_messages(system_role, system_prompt, &user_prompt);
+                let (content, parsed_review, wrapped_up) =
+                    converse(&conversation, messages, &mut profile, &mut inspected).await?;
+                // A review cut short is not cached, so the next run can finish it.
+                if let Some(cache) = &review_cache
+                    && wrapped_up.is_none()
+                {
+                    let entry = CacheEntry {
+                        created_at: chrono::Utc::now().timestamp(),
+                        model: args.model.clone(),
+                        base_hash: git_data.base_hash.clone(),
+                        head_hash: git_data.head_hash.clone(),
+                        content: content.clone(),
+                    };
+                    if let Err(err) = cache.put(&key, &entry) {
+                        eprintln!("Warning: could not cache the review: {:#}", err);
+                    }
+                }
+                (content, parsed_review, wrapped_up)
+            }
+        };
+        results.push((pass, content, parsed_review, wrapped_up));
+    }
+    let mut caveats: Vec<&str> = Vec::new();
+    for (_, _, _, wrapped_up) in &results {
+        if let Some(wrapped_up) = wrapped_up
+            && !caveats.contains(&wrapped_up.caveat())
+        {
+            caveats.push(wrapped_up.caveat());
+        }
+    }
+    let (content, parsed_review) = if passes.is_empty() {
+        let (_, content, parsed_review, _) = results.pop().expect("one run without --passes");
+        (content, parsed_review)
+    } else {
+        let reviews = results
+            .into_iter()
+            .filter_map(|(pass, _, review, _)| {
+                Some((
+                    pass?.name.clone(),
+                    review.expect("clap requires --structured with --passes"),
+                ))
+            })
+            .collect();
+        let merged = findings::merge_passes(reviews);
+        (serde_json::to_string(&merged)?, Some(merged))
+    };
+
+    let (content, parsed_review) = if args.critique {
+        progress(args.format, "Critiquing the review");
+        let critique_prompt = prompt::create_critique_prompt(&user_prompt, &content);
+        let messages = opening_messages(system_role, system_prompt_for(None), &critique_prompt);
+        let (content, parsed_review, wrapped_up) =
+            converse(&conversation, messages, &mut profile, &mut inspected).await?;
+        if let Some(wrapped_up) = wrapped_up
+            && !caveats.contains(&wrapped_up.caveat())
+        {
+            caveats.push(wrapped_up.caveat());
+        }
+        (content, parsed_review)
+    } else {
+        (content, parsed_review)
+    };
+
+    let metadata = ReviewMetadata::new(&args.model, &args.reasoning_effort, &git_data);
+    let mut omitted_findings = 0;
+    let structured_review = parsed_review.map(|mut review| {
+        if let Some(min_confidence) = args.min_confidence {
+            review.retain_confident(min_confidence);
+        }
+        if let Some(max) = args.max_findings {
+            omitted_findings = review.keep_most_important(max as usize);
+        }
+        review
+    });
+    let omitted_caveat = (omitted_findings > 0).then(|| {
+        format!(
+            "--max-findings kept the {} most important findings and left out {} more.",
+            args.max_findings.unwrap_or_default(),
+            omitted_findings
+        )
+    });
+    if let Some(caveat) = &omitted_caveat {
+        caveats.push(caveat);
+    }
+    if let Some(dir) = &args.write_patches
+        && let Some(review) = &structured_review
+    {
+        let written = findings::write_patches(review, Path::new(dir))?;
+        eprintln!("Wrote {} patch(es) to {}", written.len(), dir);
+    }
+    match args.format {
+        OutputFormat::Text => {
+            let body = match &structured_review {
+                Some(review) => {
+                    let findings = match &args.output_template {
+                        Some(template) => findings::render_findings(review, template),
+                        None => serde_json::to_string_pretty(&review)?,
+                    };
+                    match findings::format_snippets(review, read_source) {
+                        snippets if snippets.is_empty() => findings,
+                        snippets => format!("{}\n\n{}", findings.trim_end(), snippets),
+                    }
+                }
+                None => content.trim().to_string(),
+            };
+            let body = if caveats.is_empty() {
+                body
+            } else {
+                format!("{}\n\n{}", caveats.join("\n"), body)
+            };
+            let branch = git_data.branch_name.as_deref().unwrap_or("detached HEAD");
+            let title = args.review_title.as_deref();
+            let reviewer = args.reviewer_name.as_deref();
+            let output = match (&template, title) {
+                (Some(template), _) => template.render(&[
+                    ("review", &body),
+                    ("title", title.unwrap_or("")),
+                    ("branch", branch),
+                    ("model", &args.model),
+                    ("reviewer", reviewer.unwrap_or("")),
+                ]),
+                (None, Some(title)) => format!("# {}\n\n{}", title, body),
+                (None, None) => body,
+            };
+            let output = match (&template, reviewer) {
+                (None, Some(reviewer)) => format!("Reviewer: {}\n\n{}", reviewer, output),
+                _ => output,
+            };
+            let mut output = format!("{}\n", output.trim_end());
+            if args.footer {
+                output.push_str(&format_footer(&metadata, reviewer));
+            }
+            print!("{}", output);
+            if let Some((client, target)) = &gitlab {
+                post_to_gitlab(client, target, &output).await?;
+            }
+        }
+        OutputFormat::Json => {
+            let review = review_json(
+                structured_review.as_ref(),
+                &content,
+                args.json_schema_file.is_some(),
+            )?;
+            let mut envelope = json!({
+                "status": "reviewed",
+                "review": review,
+                "metadata": metadata,
+            });
+            if let Some(title) = &args.review_title {
+                envelope["title"] = json!(title);
+            }
+            if let Some(reviewer) = &args.reviewer_name {
+                envelope["reviewer"] = json!(reviewer);
+            }
+            if !caveats.is_empty() {
+                envelope["caveats"] = json!(caveats);
+            }
+            println!("{}", serde_json::to_string_pretty(&envelope)?);
+        }
+        OutputFormat::GithubActions => {
+            let review =
+                structured_review.expect("clap requires --structured with --format github-actions");
+            for caveat in &caveats {
+                println!("::warning::{}", caveat);
+            }
+            print!("{}", findings::github_actions_commands(&review));
+        }
     }
 
-    let tools = tool_definitions();
-    let mut messages = vec![
+    if args.show_inspected {
+        progress(args.format, format_inspected(&inspected).trim_end());
+    }
+    if args.profile {
+        eprint!("{}", profile.report(started.elapsed()));
+    }
+
+    Ok(())
+}
+
+/// Where `--post-gitlab` posts the review.
+struct GitLabTarget {
+    base_url: String,
+    project: String,
+    /// The merge request's source branch.
+    branch: String,
+}
+
+/// Works out where `--post-gitlab` posts from the remote and branch, so a
+/// review that cannot be posted fails before the model is asked for it.
+fn gitlab_target(args: &ReviewArgs, git_data: &GitData) -> Result<GitLabTarget> {
+    if args.format != OutputFormat::Text {
+        return Err(anyhow!(
+            "--post-gitlab posts the text review; use --format text"
+        ));
+    }
+    let (Some(remote_name), Some(remote_url)) = (&git_data.remote_name, &git_data.remote_url)
+    else {
+        return Err(anyhow!(
+            "--post-gitlab needs a git remote to find the GitLab project"
+        ));
+    };
+    let remote = forge::parse_remote(remote_url).with_context(|| {
+        format!(
+            "--post-gitlab cannot find a GitLab project in the URL of remote `{}` (`{}`)",
+            remote_name, remote_url
+        )
+    })?;
+    let branch = git_data
+        .branch_name
+        .clone()
+        .context("--post-gitlab needs a checked-out branch to find its merge request")?;
+    Ok(GitLabTarget {
+        base_url: args.gitlab_url.clone().unwrap_or(remote.web_url),
+        project: remote.project,
+        branch,
+    })
+}
+
+async fn post_to_gitlab(client: &GitLabClient, target: &GitLabTarget, review: &str) -> Result<()> {
+    let merge_request = client
+        .open_merge_request(&target.project, &target.branch)
+        .await?
+        .with_context(|| {
+            format!(
+                "No open merge request from `{}` in {} to post the review to",
+                target.branch, target.project
+            )
+        })?;
+    client
+        .post_note(&target.project, merge_request.iid, review)
+        .await?;
+    eprintln!("Posted the review to {}", merge_request.web_url);
+    Ok(())
+}
+
+/// What every turn of a review conversation shares; see `converse`.
+struct Conversation<'a> {
+    args: &'a ReviewArgs,
+    client: &'a OpenAIClient,
+    tools: Option<Vec<Tool>>,
+    tool_context: ToolContext,
+    redactor: Option<&'a Redactor>,
+    response_format: Option<ResponseFormat>,
+    reasoning_effort: ReasoningEffort,
+    temperature: Option<f32>,
+    deadline: Option<Instant>,
+    wrap_up_at: Option<Instant>,
+}
+
+impl Conversation<'_> {
+    /// The request for one turn, continuing from `messages`.
+    fn request(&self, messages: Vec<Message>, tool_choice: &str) -> ChatRequest {
+        ChatRequest {
+            model: self.args.model.clone(),
+            messages,
+            response_format: self.response_format.clone(),
+            tools: self.tools.clone(),
+            tool_choice: self.tools.as_ref().map(|_| tool_choice.to_string()),
+            temperature: self.temperature,
+            max_tokens: None,
+            reasoning_effort: Some(self.reasoning_effort.clone()),
+            prompt_cache_key: self.args.prompt_cache_key.clone(),
+            stop: (!self.args.stop_sequences.is_empty()).then(|| self.args.stop_sequences.clone()),
+        }
+    }
+}
+
+/// The system and user prompts that start a review conversation.
+fn opening_messages(role: SystemRole, system_prompt: String, user_prompt: &str) -> Vec<Message> {
+    vec![
         Message {
-            role: "system".to_string(),
+            role: role.as_str().to_string(),
             content: Some(system_prompt),
             tool_calls: None,
             tool_call_id: None,
         },
         Message {
             role: "user".to_string(),
-            content: Some(user_prompt),
+            content: Some(user_prompt.to_string()),
             tool_calls: None,
             tool_call_id: None,
         },
-    ];
+    ]
+}
+
+/// Why a review conversation told the model to stop calling tools before it
+/// chose to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WrapUp {
+    /// `MAX_TOOL_CALLS` were used.
+    ToolBudget,
+    /// Three quarters of `--deadline-secs` passed.
+    Deadline,
+}
+
+impl WrapUp {
+    /// Shown with the review, which may have missed something as a result.
+    fn caveat(self) -> &'static str {
+        match self {
+            WrapUp::ToolBudget => {
+                "Note: the tool call budget was reached; this review may be incomplete."
+            }
+            WrapUp::Deadline => {
+                "Note: the deadline was nearly reached; this review may be incomplete."
+            }
+        }
+    }
+}
 
+/// Runs one review conversation from `messages` (the system and user
+/// prompts) until the model answers without calling tools, and returns the
+/// answer, parsed in `--structured` mode, with why it was cut short, if it
+/// was.
+async fn converse(
+    conversation: &Conversation<'_>,
+    mut messages: Vec<Message>,
+    profile: &mut Profile,
+    inspected: &mut Vec<Inspection>,
+) -> Result<(String, Option<Review>, Option<WrapUp>)> {
+    let &Conversation {
+        args,
+        client,
+        ref tools,
+        ref tool_context,
+        redactor,
+        deadline,
+        wrap_up_at,
+        ..
+    } = conversation;
     let mut tool_calls_used = 0;
+    let mut free_unknown_tool_calls = 0;
+    let mut invalid_json_retried = false;
+    let mut budget_exhausted = false;
+    let mut wrapped_up = None;
+    let mut turn = 0;
+    let mut tokens_so_far = 0;
     loop {
-        let request = ChatRequest {
-            model: args.model.clone(),
-            messages: messages.clone(),
-            response_format: None,
-            tools: Some(tools.clone()),
-            tool_choice: Some("auto".to_string()),
-            temperature: None,
-            max_tokens: None,
-            reasoning_effort: Some(args.reasoning_effort.clone()),
-        };
+        if wrapped_up.is_none() {
+            let reason = if budget_exhausted {
+                Some(WrapUp::ToolBudget)
+            } else if wrap_up_at.is_some_and(|wrap_up_at| Instant::now() >= wrap_up_at) {
+                Some(WrapUp::Deadline)
+            } else {
+                None
+            };
+            if let Some(reason) = reason {
+                wrapped_up = Some(reason);
+                let (status, instruction) = match reason {
+                    WrapUp::ToolBudget => (
+                        "Tool call budget reached; asking the model to wrap up",
+                        "You have used all of your tool calls.",
+                    ),
+                    WrapUp::Deadline => (
+                        "Deadline approaching; asking the model to wrap up",
+                        "Time is almost up.",
+                    ),
+                };
+                progress(args.format, status);
+                messages.push(Message {
+                    role: "user".to_string(),
+                    content: Some(format!(
+                        "{} Do not call any more tools: give your final review now, \
+                         based on what you have already seen.",
+                        instruction
+                    )),
+                    tool_calls: None,
+                    tool_call_id: None,
+                });
+            }
+        }
+        // Once wrapping up, tools stay defined (the history refers to them)
+        // but the model may no longer call them.
+        let tool_choice = if wrapped_up.is_some() { "none" } else { "auto" };
+        let request = conversation.request(messages.clone(), tool_choice);
 
-        let response = client.chat(request).await?;
+        let request_started = Instant::now();
+        let response = tokio::select! {
+            response = client.chat(request) => response?,
+            _ = sleep_until_deadline(deadline) => {
+                return Err(anyhow!(
+                    "Review did not finish within --deadline-secs {}",
+                    args.deadline_secs.expect("a deadline was set")
+                ));
+            }
+        };
+        profile.record_api(request_started.elapsed());
+        if args.usage_per_turn {
+            turn += 1;
+            tokens_so_far += u64::from(response.usage.total_tokens);
+            eprintln!(
+                "{}",
+                format_turn_usage(turn, &response.usage, tokens_so_far)
+            );
+        }
         let choice = response
             .choices
             .into_iter()
@@ -148,23 +1688,95 @@ async fn run_review(args: ReviewArgs) -> Result<()> {
         messages.push(assistant_message.clone());
 
         if let Some(tool_calls) = tool_calls {
-            println!("Actioning {} tool call(s)", tool_calls.len());
+            if tools.is_none() {
+                return Err(anyhow!(
+                    "Model requested tool calls although no tools were offered (--no-tools)."
+                ));
+            }
+            // Asking again would only loop, each turn paying for the whole
+            // history.
+            if wrapped_up.is_some() {
+                return Err(anyhow!(
+                    "Model kept calling tools after being told to give its final review."
+                ));
+            }
+            progress(
+                args.format,
+                &format!("Actioning {} tool call(s)", tool_calls.len()),
+            );
             for call in tool_calls {
-                tool_calls_used += 1;
-                if tool_calls_used > MAX_TOOL_CALLS {
-                    return Err(anyhow!(
-                        "Tool call limit exceeded (max {}).", MAX_TOOL_CALLS
-                    ));
+                // Every call still needs a result for the history to be valid.
+                let free = free_unknown_tool_calls < MAX_FREE_UNKNOWN_TOOL_CALLS
+                    && !tools::is_available_tool(&call.function.name, tool_context);
+                if !free && tool_calls_used == MAX_TOOL_CALLS {
+                    budget_exhausted = true;
+                    messages.push(Message {
+                        role: "tool".to_string(),
+                        content: Some(format!(
+                            "Not run: the limit of {} tool calls was reached.",
+                            MAX_TOOL_CALLS
+                        )),
+                        tool_calls: None,
+                        tool_call_id: Some(call.id),
+                    });
+                    continue;
+                }
+                if free {
+                    free_unknown_tool_calls += 1;
+                } else {
+                    tool_calls_used += 1;
                 }
 
                 let summary = tools::summarize_tool_call(
                     &call.function.name,
                     &call.function.arguments,
                 );
-                println!("Tool call: {}", summary);
 
-                let tool_output =
-                    tools::handle_tool_call(&call.function.name, &call.function.arguments);
+                if let Some(inspection) =
+                    tools::inspection_for_call(&call.function.name, &call.function.arguments)
+                    && !inspected.contains(&inspection)
+                {
+                    inspected.push(inspection);
+                }
+
+                // Tools block this thread (file reads, the read retry
+                // delay), which is fine: nothing else runs on the runtime
+                // until the model has the result.
+                let tool_started = Instant::now();
+                let tool_output = tools::handle_tool_call(
+                    &call.function.name,
+                    &call.function.arguments,
+                    tool_context,
+                );
+                profile.record_tool_call(tool_started.elapsed());
+                let tool_output = match &redactor {
+                    Some(redactor) => {
+                        let (redacted, count) = redactor.redact(&tool_output);
+                        if count > 0 {
+                            eprintln!(
+                                "Warning: redacted {} likely secret(s) from {}",
+                                count, summary
+                            );
+                        }
+                        redacted
+                    }
+                    None => tool_output,
+                };
+                if args.strict_tools && tools::is_tool_error(&tool_output) {
+                    return Err(anyhow!(
+                        "Tool call {} failed under --strict-tools: {}",
+                        summary,
+                        tool_output.trim_end()
+                    ));
+                }
+                progress(
+                    args.format,
+                    &format!(
+                        "Tool call: {} -> {}",
+                        summary,
+                        describe_output_size(&tool_output)
+                    ),
+                );
 
                 messages.push(Message {
                     role: "tool".to_string(),
@@ -182,9 +1794,633 @@ async fn run_review(args: ReviewArgs) -> Result<()> {
                 "Model returned an empty response with no tool calls."
             ));
         }
-        println!("{}", content.trim());
-        break;
+        let parsed_review = if args.structured {
+            match findings::parse_review(&content) {
+                Ok(review) => Some(review),
+                Err(err) => {
+                    eprintln!(
+                        "Model response is not a valid structured review:\n{}",
+                        content.trim()
+                    );
+                    if invalid_json_retried {
+                        return Err(err.context("Model response was still invalid after a retry"));
+                    }
+                    invalid_json_retried = true;
+                    progress(args.format, "Asking the model to correct its response");
+                    messages.push(Message {
+                        role: "user".to_string(),
+                        content: Some(format!(
+                            "Your previous response was not valid JSON matching the required schema ({:#}). \
+                             Respond again with only the JSON object.",
+                            err
+                        )),
+                        tool_calls: None,
+                        tool_call_id: None,
+                    });
+                    continue;
+                }
+            }
+        } else {
+            None
+        };
+        return Ok((content, parsed_review, wrapped_up));
     }
+}
+
+/// The `--dump-prompt` file: both prompts, then the tool definitions exactly
+/// as they are sent.
+fn format_prompt_dump(
+    system_prompt: &str,
+    user_prompt: &str,
+    tools: Option<&[Tool]>,
+) -> Result<String> {
+    let tools = match tools {
+        Some(tools) => serde_json::to_string_pretty(tools)?,
+        None => "(none: --no-tools)".to_string(),
+    };
+    Ok(format!(
+        "System prompt:\n{}\n\nUser prompt:\n{}\n\nTools:\n{}\n",
+        system_prompt, user_prompt, tools
+    ))
+}
 
+/// The `blart tools` output: one `name: first line of the description` line
+/// per tool, or with `json`, the definitions exactly as they are sent.
+fn format_tools(tools: &[Tool], json: bool) -> Result<String> {
+    if json {
+        return Ok(format!("{}\n", serde_json::to_string_pretty(tools)?));
+    }
+    Ok(tools
+        .iter()
+        .map(|tool| {
+            let summary = tool.function.description.lines().next().unwrap_or_default();
+            format!("{}: {}\n", tool.function.name, summary)
+        })
+        .collect())
+}
+
+/// Completes at `deadline`, or never without one.
+async fn sleep_until_deadline(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Reads the optional review template from `.blart/template.md` in the
+/// current directory. It wraps the text-mode review output.
+fn load_review_template() -> Result<Option<Template>> {
+    let source = match std::fs::read_to_string(REVIEW_TEMPLATE_PATH) {
+        Ok(source) => source,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => {
+            return Err(err).with_context(|| format!("Failed to read {}", REVIEW_TEMPLATE_PATH));
+        }
+    };
+    Template::parse(&source, REVIEW_TEMPLATE_FIELDS)
+        .map(Some)
+        .map_err(|err| anyhow!("{}: {}", REVIEW_TEMPLATE_PATH, err))
+}
+
+/// Builds the `--redact` redactor: the built-in secret patterns plus any
+/// extra regexes in `.blart/redact.txt`, one per line.
+fn load_redactor() -> Result<Redactor> {
+    let extra = read_optional_config(REDACT_PATTERNS_PATH)?;
+    Redactor::new(&extra).map_err(|err| anyhow!("{}: {}", REDACT_PATTERNS_PATH, err))
+}
+
+/// Reads the `--previous-review` file, keeping its first
+/// `MAX_PREVIOUS_REVIEW_CHARS` characters.
+fn read_previous_review(path: &str) -> Result<String> {
+    let review = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read previous review {}", path))?;
+    Ok(cap_previous_review(&review))
+}
+
+fn cap_previous_review(review: &str) -> String {
+    match review.char_indices().nth(MAX_PREVIOUS_REVIEW_CHARS) {
+        Some((cut, _)) => format!(
+            "{}\n[previous review cut at {} characters]",
+            &review[..cut],
+            MAX_PREVIOUS_REVIEW_CHARS
+        ),
+        None => review.to_string(),
+    }
+}
+
+/// Reads a `.blart/` file that extends a built-in list; a missing file is
+/// the same as an empty one.
+fn read_optional_config(path: &str) -> Result<String> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Ok(contents),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(err) => Err(err).with_context(|| format!("Failed to read {}", path)),
+    }
+}
+
+/// Provenance for a saved review.
+#[derive(Debug, Serialize)]
+struct ReviewMetadata {
+    timestamp: String,
+    blart_version: &'static str,
+    model: String,
+    reasoning_effort: String,
+    base_hash: String,
+    head_hash: String,
+}
+
+impl ReviewMetadata {
+    fn new(model: &str, reasoning_effort: &str, git_data: &GitData) -> Self {
+        Self {
+            timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            blart_version: env!("CARGO_PKG_VERSION"),
+            model: model.to_string(),
+            reasoning_effort: reasoning_effort.to_string(),
+            base_hash: git_data.base_hash.clone(),
+            head_hash: git_data.head_hash.clone(),
+        }
+    }
+}
+
+/// The `--footer` text. A `--reviewer-name` goes before the blart version, so
+/// the footer still says which tool and model produced the review.
+fn format_footer(metadata: &ReviewMetadata, reviewer: Option<&str>) -> String {
+    let reviewed_by = match reviewer {
+        Some(reviewer) => format!("{} (blart {})", reviewer, metadata.blart_version),
+        None => format!("blart {}", metadata.blart_version),
+    };
+    format!(
+        "\n---\nReviewed by {} at {}\nModel: {} (reasoning effort: {})\nBase: {}\nHEAD: {}\n",
+        reviewed_by,
+        metadata.timestamp,
+        metadata.model,
+        metadata.reasoning_effort,
+        metadata.base_hash,
+        metadata.head_hash
+    )
+}
+
+/// Prints a progress or diagnostic line. In JSON mode it goes to stderr so
+/// stdout stays a single JSON document.
+fn progress(format: OutputFormat, line: &str) {
+    match format {
+        OutputFormat::Text => println!("{}", line),
+        OutputFormat::Json | OutputFormat::GithubActions => eprintln!("{}", line),
+    }
+}
+
+/// One `--usage-per-turn` line. Gateways that leave `usage` out report zero.
+fn format_turn_usage(turn: usize, usage: &Usage, tokens_so_far: u64) -> String {
+    if usage.total_tokens == 0 {
+        return format!("Turn {}: usage not reported", turn);
+    }
+    format!(
+        "Turn {}: {} prompt + {} completion = {} tokens ({} so far)",
+        turn, usage.prompt_tokens, usage.completion_tokens, usage.total_tokens, tokens_so_far
+    )
+}
+
+fn report_no_changes(args: &ReviewArgs, message: &str) -> Result<()> {
+    match args.format {
+        OutputFormat::Text => println!("{}", message),
+        OutputFormat::Json => println!("{}", json!({ "status": "no_changes", "message": message })),
+        OutputFormat::GithubActions => {
+            println!("::notice::{}", findings::escape_workflow_data(message))
+        }
+    }
+    if let Some(code) = args.no_changes_exit_code {
+        std::process::exit(code.into());
+    }
     Ok(())
 }
+
+/// The `review` field of the `--format json` envelope: the structured
+/// review, the answer to a `--json-schema-file` (which must be JSON), or
+/// otherwise the model's free-text review as a string.
+fn review_json(
+    structured_review: Option<&Review>,
+    content: &str,
+    custom_schema: bool,
+) -> Result<serde_json::Value> {
+    match structured_review {
+        Some(review) => Ok(serde_json::to_value(review)?),
+        None if custom_schema => serde_json::from_str(content.trim())
+            .context("Model response is not valid JSON, so it cannot be wrapped for --format json"),
+        None => Ok(json!(content.trim())),
+    }
+}
+
+/// Human-readable size of a tool result, e.g. `200 lines, 8.1KB`.
+fn describe_output_size(output: &str) -> String {
+    let lines = output.lines().count();
+    let bytes = output.len();
+    let size = if bytes < 1024 {
+        format!("{}B", bytes)
+    } else {
+        format!("{:.1}KB", bytes as f64 / 1024.0)
+    };
+    let noun = if lines == 1 { "line" } else { "lines" };
+    format!("{} {}, {}", lines, noun, size)
+}
+
+fn format_inspected(inspected: &[Inspection]) -> String {
+    let mut output = String::from("\nInspected files:\n");
+    let files: Vec<&str> = inspected
+        .iter()
+        .filter_map(|item| match item {
+            Inspection::File(path) => Some(path.as_str()),
+            Inspection::Search { .. } => None,
+        })
+        .collect();
+    if files.is_empty() {
+        output.push_str("  (none)\n");
+    }
+    for path in files {
+        output.push_str(&format!("  {}\n", path));
+    }
+
+    output.push_str("Searches:\n");
+    let searches: Vec<(&str, &str)> = inspected
+        .iter()
+        .filter_map(|item| match item {
+            Inspection::Search { path, regex } => Some((path.as_str(), regex.as_str())),
+            Inspection::File(_) => None,
+        })
+        .collect();
+    if searches.is_empty() {
+        output.push_str("  (none)\n");
+    }
+    for (path, regex) in searches {
+        output.push_str(&format!("  {} in {}\n", regex, path));
+    }
+    output
+}
+
+/// Picks the API base URL: the `--base-url` flag wins over `OPENAI_BASE_URL`.
+/// `None` means the client keeps its built-in default.
+fn resolve_base_url(flag: Option<String>, env: Option<String>) -> Option<String> {
+    flag.or(env)
+}
+
+/// Whether `--offline` or `BLART_OFFLINE` (any value but empty, `0` or
+/// `false`) disables network access.
+fn resolve_offline(flag: bool, env: Option<String>) -> bool {
+    flag || env.is_some_and(|value| !matches!(value.trim(), "" | "0" | "false"))
+}
+
+/// Picks the review instructions: `--system-prompt-file` wins over a non-empty
+/// `BLART_SYSTEM_PROMPT`. `None` means the embedded default.
+fn resolve_base_prompt(file: Option<String>, env: Option<String>) -> Option<String> {
+    file.or(env.filter(|value| !value.trim().is_empty()))
+}
+
+fn parse_temperature(raw: &str) -> Result<f32, String> {
+    let value: f32 = raw
+        .parse()
+        .map_err(|_| format!("`{}` is not a number", raw))?;
+    if !(0.0..=2.0).contains(&value) {
+        return Err(format!("{} is not in 0.0..=2.0", value));
+    }
+    Ok(value)
+}
+
+fn parse_confidence(raw: &str) -> Result<f64, String> {
+    let value: f64 = raw
+        .parse()
+        .map_err(|_| format!("`{}` is not a number", raw))?;
+    if !(0.0..=1.0).contains(&value) {
+        return Err(format!("{} is not in 0.0..=1.0", value));
+    }
+    Ok(value)
+}
+
+fn parse_finding_template(raw: &str) -> Result<Template, String> {
+    Template::parse(raw, findings::FINDING_TEMPLATE_FIELDS)
+}
+
+/// Reasoning models reject `temperature`, so it is only sent when reasoning
+/// is switched off.
+fn resolve_temperature(requested: Option<f32>, reasoning_effort: &str) -> Option<f32> {
+    if reasoning_effort == "none" {
+        requested
+    } else {
+        None
+    }
+}
+
+/// Parses a `--header` argument of the form `Name: value`.
+fn parse_header(raw: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, value) = raw
+        .split_once(':')
+        .ok_or_else(|| format!("expected `Name: value`, got `{}`", raw))?;
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .map_err(|_| format!("invalid header name `{}`", name.trim()))?;
+    let value = HeaderValue::from_str(value.trim())
+        .map_err(|_| format!("invalid value for header `{}`", name))?;
+    Ok((name, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn converse_stops_when_the_model_keeps_calling_tools_after_wrap_up() {
+        let mock_server = MockServer::start().await;
+        let tool_call_response = serde_json::json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 1677652288,
+            "model": "gpt-4",
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": {
+                            "name": "read_file",
+                            "arguments": "{\"path\": \"Cargo.toml\"}"
+                        }
+                    }]
+                },
+                "finish_reason": "tool_calls"
+            }],
+            "usage": {
+                "prompt_tokens": 1,
+                "completion_tokens": 1,
+                "total_tokens": 2
+            }
+        });
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(tool_call_response))
+            .mount(&mock_server)
+            .await;
+
+        let args = ReviewArgs::parse_from(["blart"]);
+        let client = OpenAIClient::new("test-api-key".to_string()).with_base_url(mock_server.uri());
+        let conversation = Conversation {
+            args: &args,
+            client: &client,
+            tools: Some(tool_definitions(false)),
+            tool_context: ToolContext::new(args.max_search_matches),
+            redactor: None,
+            response_format: None,
+            reasoning_effort: ReasoningEffort::Level(args.reasoning_effort.clone()),
+            temperature: None,
+            deadline: None,
+            wrap_up_at: None,
+        };
+        let messages = opening_messages(SystemRole::System, "system".to_string(), "user");
+        let err = converse(
+            &conversation,
+            messages,
+            &mut Profile::default(),
+            &mut Vec::new(),
+        )
+        .await
+        .unwrap_err();
+
+        assert!(
+            err.to_string().contains("kept calling tools"),
+            "unexpected error: {}",
+            err
+        );
+        // MAX_TOOL_CALLS turns of one call each, one turn whose call is
+        // refused, then the one wrap-up turn.
+        let requests = mock_server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), MAX_TOOL_CALLS + 2);
+    }
+
+    #[test]
+    fn review_json_wraps_free_text_and_parses_custom_schema_answers() {
+        assert_eq!(
+            review_json(None, "  Looks good.\n* one nit\n", false).unwrap(),
+            json!("Looks good.\n* one nit")
+        );
+        assert_eq!(
+            review_json(None, r#"{"verdict": "ok"}"#, true).unwrap(),
+            json!({ "verdict": "ok" })
+        );
+        assert!(review_json(None, "Looks good.", true).is_err());
+    }
+
+    #[test]
+    fn format_turn_usage_shows_the_split_and_running_total() {
+        let usage = Usage {
+            prompt_tokens: 12_000,
+            completion_tokens: 500,
+            total_tokens: 12_500,
+        };
+        assert_eq!(
+            format_turn_usage(3, &usage, 30_000),
+            "Turn 3: 12000 prompt + 500 completion = 12500 tokens (30000 so far)"
+        );
+        assert_eq!(
+            format_turn_usage(1, &Usage::default(), 0),
+            "Turn 1: usage not reported"
+        );
+    }
+
+    #[test]
+    fn base_url_flag_takes_precedence_over_env() {
+        let resolved = resolve_base_url(
+            Some("http://flag".to_string()),
+            Some("http://env".to_string()),
+        );
+        assert_eq!(resolved.as_deref(), Some("http://flag"));
+    }
+
+    #[test]
+    fn offline_is_set_by_the_flag_or_a_truthy_env_var() {
+        assert!(resolve_offline(true, None));
+        assert!(resolve_offline(false, Some("1".to_string())));
+        assert!(!resolve_offline(false, Some("0".to_string())));
+        assert!(!resolve_offline(false, Some(String::new())));
+        assert!(!resolve_offline(false, None));
+    }
+
+    #[test]
+    fn base_url_falls_back_to_env_then_default() {
+        let resolved = resolve_base_url(None, Some("http://env".to_string()));
+        assert_eq!(resolved.as_deref(), Some("http://env"));
+        assert_eq!(resolve_base_url(None, None), None);
+    }
+
+    #[test]
+    fn base_prompt_prefers_file_then_non_empty_env() {
+        let resolved = resolve_base_prompt(Some("file".to_string()), Some("env".to_string()));
+        assert_eq!(resolved.as_deref(), Some("file"));
+        let resolved = resolve_base_prompt(None, Some("env".to_string()));
+        assert_eq!(resolved.as_deref(), Some("env"));
+        assert_eq!(resolve_base_prompt(None, Some("  ".to_string())), None);
+        assert_eq!(resolve_base_prompt(None, None), None);
+    }
+
+    #[test]
+    fn parse_header_accepts_name_and_value() {
+        let (name, value) = parse_header("HTTP-Referer: https://example.com").unwrap();
+        assert_eq!(name.as_str(), "http-referer");
+        assert_eq!(value.to_str().unwrap(), "https://example.com");
+    }
+
+    #[test]
+    fn parse_header_rejects_malformed_input() {
+        assert!(
+            parse_header("no-colon")
+                .unwrap_err()
+                .contains("expected `Name: value`")
+        );
+        assert!(
+            parse_header("bad name: x")
+                .unwrap_err()
+                .contains("invalid header name")
+        );
+        assert!(
+            parse_header("X-Title: bad\nvalue")
+                .unwrap_err()
+                .contains("invalid value")
+        );
+    }
+
+    #[test]
+    fn parse_temperature_enforces_range() {
+        assert_eq!(parse_temperature("0"), Ok(0.0));
+        assert_eq!(parse_temperature("2.0"), Ok(2.0));
+        assert!(parse_temperature("2.5").is_err());
+        assert!(parse_temperature("-0.1").is_err());
+        assert!(parse_temperature("warm").is_err());
+    }
+
+    #[test]
+    fn format_checks_indents_failure_hints() {
+        let checks = [
+            Check {
+                description: "git works".to_string(),
+                outcome: Ok(()),
+            },
+            Check {
+                description: "an API key is set".to_string(),
+                outcome: Err("set OPENAI_API_KEY\nor pass --api-key".to_string()),
+            },
+        ];
+        assert_eq!(
+            format_checks(&checks),
+            "[ok]   git works\n[FAIL] an API key is set\n       set OPENAI_API_KEY\n       or pass --api-key\n"
+        );
+    }
+
+    #[test]
+    fn format_prompt_dump_includes_tool_definitions() {
+        let dump = format_prompt_dump("SYS", "USER", Some(&tool_definitions(false))).unwrap();
+        assert!(dump.starts_with("System prompt:\nSYS\n\nUser prompt:\nUSER\n\nTools:\n["));
+        assert!(dump.contains("\"name\": \"search_files\""));
+
+        let dump = format_prompt_dump("SYS", "USER", None).unwrap();
+        assert!(dump.ends_with("Tools:\n(none: --no-tools)\n"));
+    }
+
+    #[test]
+    fn format_tools_lists_names_or_the_json_definitions() {
+        let tools = tool_definitions(true);
+        let listing = format_tools(&tools, false).unwrap();
+        assert_eq!(listing.lines().count(), tools.len());
+        assert!(listing.contains("\nget_diff: "));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&format_tools(&tools, true).unwrap()).unwrap();
+        let names: Vec<&str> = json
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|tool| tool["function"]["name"].as_str().unwrap())
+            .collect();
+        assert!(names.contains(&"read_file"));
+        assert_eq!(names.len(), tools.len());
+    }
+
+    #[test]
+    fn parse_confidence_enforces_range() {
+        assert_eq!(parse_confidence("0.7"), Ok(0.7));
+        assert!(parse_confidence("1.1").is_err());
+        assert!(parse_confidence("high").is_err());
+    }
+
+    #[test]
+    fn temperature_is_dropped_while_reasoning_is_active() {
+        assert_eq!(resolve_temperature(Some(0.0), "none"), Some(0.0));
+        assert_eq!(resolve_temperature(Some(0.0), "high"), None);
+        assert_eq!(resolve_temperature(None, "none"), None);
+    }
+
+    #[test]
+    fn format_inspected_lists_files_and_searches() {
+        let inspected = vec![
+            Inspection::File("src/a.rs".to_string()),
+            Inspection::Search {
+                path: "src".to_string(),
+                regex: "foo".to_string(),
+            },
+        ];
+        let output = format_inspected(&inspected);
+        assert!(output.contains("Inspected files:\n  src/a.rs\n"));
+        assert!(output.contains("Searches:\n  foo in src\n"));
+        assert!(format_inspected(&[]).contains("Searches:\n  (none)"));
+    }
+
+    #[test]
+    fn format_footer_records_provenance() {
+        let metadata = ReviewMetadata {
+            timestamp: "2026-01-02T03:04:05Z".to_string(),
+            blart_version: "1.2.3",
+            model: "openai/gpt-5.2".to_string(),
+            reasoning_effort: "high".to_string(),
+            base_hash: "abc".to_string(),
+            head_hash: "def".to_string(),
+        };
+        let footer = format_footer(&metadata, None);
+        assert!(footer.contains("Reviewed by blart 1.2.3 at 2026-01-02T03:04:05Z\n"));
+        assert!(footer.contains("Model: openai/gpt-5.2 (reasoning effort: high)\n"));
+        assert!(footer.contains("Base: abc\nHEAD: def\n"));
+        assert!(
+            format_footer(&metadata, Some("security-bot"))
+                .contains("Reviewed by security-bot (blart 1.2.3) at 2026-01-02T03:04:05Z\n")
+        );
+    }
+
+    #[test]
+    fn describe_output_size_reports_lines_and_bytes() {
+        assert_eq!(describe_output_size("one\n"), "1 line, 4B");
+        let big = "x".repeat(2047) + "\n";
+        assert_eq!(describe_output_size(&big), "1 line, 2.0KB");
+        assert_eq!(describe_output_size("a\nb\n"), "2 lines, 4B");
+    }
+
+    #[test]
+    fn system_role_defaults_to_developer_for_o_series_models() {
+        assert_eq!(SystemRole::for_model("o1"), SystemRole::Developer);
+        assert_eq!(
+            SystemRole::for_model("openai/o3-mini"),
+            SystemRole::Developer
+        );
+        assert_eq!(SystemRole::for_model("openai/gpt-5.2"), SystemRole::System);
+        assert_eq!(SystemRole::for_model("ollama/llama3"), SystemRole::System);
+    }
+
+    #[test]
+    fn cap_previous_review_keeps_the_start() {
+        assert_eq!(cap_previous_review("short review"), "short review");
+        let long = "é".repeat(MAX_PREVIOUS_REVIEW_CHARS + 5);
+        let capped = cap_previous_review(&long);
+        assert!(capped.starts_with(&"é".repeat(MAX_PREVIOUS_REVIEW_CHARS)));
+        assert!(capped.ends_with(&format!(
+            "é\n[previous review cut at {} characters]",
+            MAX_PREVIOUS_REVIEW_CHARS
+        )));
+    }
+}
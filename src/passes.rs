@@ -0,0 +1,103 @@
+use anyhow::{Context, Result, bail};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// Directory of `<name>.md` files that override a built-in `--passes` prompt
+/// or define a new pass.
+pub const PASSES_DIR: &str = ".blart/passes";
+
+/// The passes `--passes` knows without configuration, with the instructions
+/// appended to the system prompt for each.
+const BUILTIN_PASSES: &[(&str, &str)] = &[
+    (
+        "security",
+        "This pass focuses on security only: injection, unsafe handling of untrusted input, \
+         authentication and authorization mistakes, secrets in code, unsafe deserialization and \
+         similar vulnerabilities. Leave other kinds of issue to the other passes.",
+    ),
+    (
+        "performance",
+        "This pass focuses on performance only: needless allocation or copying, accidental \
+         quadratic work, blocking calls on async paths, missing batching and similar costs that \
+         grow with input size. Leave other kinds of issue to the other passes.",
+    ),
+    (
+        "style",
+        "This pass focuses on readability and style only: naming, misleading comments, dead \
+         code, duplication and departures from the conventions of the surrounding code. Leave \
+         other kinds of issue to the other passes.",
+    ),
+];
+
+/// One `--passes` review: a name for labelling findings and the instructions
+/// for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pass {
+    pub name: String,
+    pub prompt: String,
+}
+
+/// Resolves pass names to their prompts, preferring `dir/<name>.md` over the
+/// built-in prompt. A name with neither is an error.
+pub fn resolve_passes(names: &[String], dir: &Path) -> Result<Vec<Pass>> {
+    names
+        .iter()
+        .map(|name| {
+            let path = dir.join(format!("{}.md", name));
+            let prompt = match fs::read_to_string(&path) {
+                Ok(prompt) => prompt.trim().to_string(),
+                Err(err) if err.kind() == ErrorKind::NotFound => {
+                    match BUILTIN_PASSES.iter().find(|(builtin, _)| builtin == name) {
+                        Some((_, prompt)) => prompt.to_string(),
+                        None => bail!(
+                            "unknown pass `{}`: add {} or use one of {}",
+                            name,
+                            path.display(),
+                            builtin_names()
+                        ),
+                    }
+                }
+                Err(err) => {
+                    return Err(err).with_context(|| format!("Failed to read {}", path.display()));
+                }
+            };
+            Ok(Pass {
+                name: name.clone(),
+                prompt,
+            })
+        })
+        .collect()
+}
+
+fn builtin_names() -> String {
+    BUILTIN_PASSES
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_passes_prefers_configured_prompts() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("style.md"), "Only check naming.\n").unwrap();
+        fs::write(dir.path().join("docs.md"), "Check the docs.").unwrap();
+
+        let names = ["security", "style", "docs"].map(str::to_string);
+        let passes = resolve_passes(&names, dir.path()).unwrap();
+        assert!(passes[0].prompt.contains("security only"));
+        assert_eq!(passes[1].prompt, "Only check naming.");
+        assert_eq!(passes[2].name, "docs");
+
+        let err = resolve_passes(&["typos".to_string()], dir.path()).unwrap_err();
+        assert!(
+            err.to_string()
+                .ends_with("use one of security, performance, style")
+        );
+    }
+}
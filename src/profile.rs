@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+/// Wall-clock timings of one review, reported on stderr by `--profile`.
+#[derive(Debug, Default)]
+pub struct Profile {
+    git: Duration,
+    turns: Vec<Turn>,
+}
+
+/// One request to the model and the tool calls it asked for.
+#[derive(Debug, Default)]
+struct Turn {
+    api: Duration,
+    tools: Duration,
+    tool_calls: usize,
+}
+
+impl Profile {
+    pub fn record_git(&mut self, duration: Duration) {
+        self.git += duration;
+    }
+
+    /// Starts a new turn with the time its API request took.
+    pub fn record_api(&mut self, duration: Duration) {
+        self.turns.push(Turn {
+            api: duration,
+            ..Turn::default()
+        });
+    }
+
+    /// Adds a tool call to the latest turn.
+    pub fn record_tool_call(&mut self, duration: Duration) {
+        let turn = self
+            .turns
+            .last_mut()
+            .expect("tool calls follow the API request that made them");
+        turn.tools += duration;
+        turn.tool_calls += 1;
+    }
+
+    pub fn report(&self, total: Duration) -> String {
+        let mut output = format!("Profile:\n  git: {}ms\n", self.git.as_millis());
+        for (index, turn) in self.turns.iter().enumerate() {
+            output.push_str(&format!(
+                "  turn {}: {}ms (API {}ms",
+                index + 1,
+                (turn.api + turn.tools).as_millis(),
+                turn.api.as_millis()
+            ));
+            if turn.tool_calls > 0 {
+                output.push_str(&format!(
+                    ", {} tool call(s) {}ms",
+                    turn.tool_calls,
+                    turn.tools.as_millis()
+                ));
+            }
+            output.push_str(")\n");
+        }
+        output.push_str(&format!("  total: {}ms\n", total.as_millis()));
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_breaks_turns_into_api_and_tool_time() {
+        let mut profile = Profile::default();
+        profile.record_git(Duration::from_millis(40));
+        profile.record_api(Duration::from_millis(900));
+        profile.record_tool_call(Duration::from_millis(5));
+        profile.record_tool_call(Duration::from_millis(15));
+        profile.record_api(Duration::from_millis(1200));
+
+        assert_eq!(
+            profile.report(Duration::from_millis(2200)),
+            "Profile:\n  git: 40ms\n  turn 1: 920ms (API 900ms, 2 tool call(s) 20ms)\n  \
+             turn 2: 1200ms (API 1200ms)\n  total: 2200ms\n"
+        );
+    }
+}
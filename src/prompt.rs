@@ -1,33 +1,190 @@
-pub fn get_system_prompt() -> String {
-    let base = include_str!("../prompt.txt");
-    let tools = include_str!("../prompt_tools.txt");
-    format!("{}\n\n{}", tools, base)
-}
+use crate::binary::{BinaryFile, format_binary_files};
+use crate::diff::{ModeChange, Rename};
+
+/// The review instructions used unless `--system-prompt-file` or
+/// `BLART_SYSTEM_PROMPT` replaces them.
+pub const DEFAULT_BASE_PROMPT: &str = include_str!("../prompt.txt");
 
-pub fn create_user_prompt(
-    diff: &str,
-    files_changed: &[String],
-    additional_prompt: Option<&str>,
+/// Builds the system prompt around `base`. The tool guide is only prepended
+/// when the model will actually be offered tools, and the findings
+/// instructions are only appended in `--structured` mode. `suffix`
+/// (`--system-suffix`) goes last, so it applies to a custom base as well.
+pub fn get_system_prompt(
+    base: &str,
+    include_tools: bool,
+    structured: bool,
+    severity_guide: Option<&str>,
+    suffix: Option<&str>,
 ) -> String {
-    let mut user_prompt = String::from(
-        "Below is a git diff and the list of touched files. Use search_files and read_file if you need more context.\n",
-    );
+    let mut prompt = base.to_string();
+    if include_tools {
+        let tools = include_str!("../prompt_tools.txt");
+        prompt = format!("{}\n\n{}", tools, prompt);
+    }
+    if structured {
+        prompt.push_str("\n\n");
+        prompt.push_str(include_str!("../prompt_findings.txt"));
+        let severity_guide = severity_guide.unwrap_or(include_str!("../prompt_severity.txt"));
+        prompt.push_str(&format!(
+            "\n<severityCalibration>\n{}\n</severityCalibration>\n",
+            severity_guide.trim()
+        ));
+    }
+    if let Some(suffix) = suffix {
+        prompt = format!("{}\n\n{}", prompt.trim_end(), suffix);
+    }
+    prompt
+}
 
-    if let Some(additional) = additional_prompt {
-        if !additional.trim().is_empty() {
-            user_prompt.push_str(additional);
-            user_prompt.push('\n');
-        }
+/// Everything that goes into the user prompt for one review.
+pub struct UserPromptInput<'a> {
+    /// `None` when the model reads the diff through the `get_diff` tool.
+    pub diff: Option<&'a str>,
+    pub files_changed: &'a [String],
+    pub renames: &'a [Rename],
+    pub mode_changes: &'a [ModeChange],
+    pub binary_files: &'a [BinaryFile],
+    /// The diff has no context lines (`--compact-diff`).
+    pub compact_diff: bool,
+    pub additional_prompt: Option<&'a str>,
+    pub diff_stat: Option<&'a str>,
+    pub directory_context: Option<&'a str>,
+    pub directory_tree: Option<&'a str>,
+    /// Build or test logs from `--context-file`.
+    pub build_output: Option<&'a str>,
+    /// Recent commit subjects per changed file, from `--file-history`.
+    pub file_history: Option<&'a str>,
+    /// The review of the commits before `--since-commit`, from `--previous-review`.
+    pub previous_review: Option<&'a str>,
+    /// Changed files left out of the diff because they are generated.
+    pub generated_files: &'a [String],
+    /// Files deleted by the change but left out of the diff (`--skip-deletions`).
+    pub deleted_files: &'a [String],
+    pub tools_enabled: bool,
+}
+
+pub fn create_user_prompt(input: &UserPromptInput) -> String {
+    let mut user_prompt = if input.diff.is_none() {
+        String::from(
+            "Below is a summary of a git diff and the list of touched files. Call get_diff to read the diff itself, for one file or all of them. Use search_files and read_file if you need more context.\n",
+        )
+    } else if input.tools_enabled {
+        String::from(
+            "Below is a git diff and the list of touched files. Use search_files and read_file if you need more context.\n",
+        )
+    } else {
+        String::from(
+            "Below is a git diff and the list of touched files. No tools are available; review the diff as given.\n",
+        )
+    };
+
+    if input.compact_diff {
+        user_prompt.push_str(
+            "The diff has no context lines, only the changed ones. Use read_file to see the code around a change before judging it.\n",
+        );
+    }
+
+    if let Some(additional) = input.additional_prompt
+        && !additional.trim().is_empty()
+    {
+        user_prompt.push_str(additional);
+        user_prompt.push('\n');
+    }
+
+    if let Some(diff_stat) = input.diff_stat {
+        user_prompt.push_str("\nDIFF STAT:\n");
+        user_prompt.push_str(diff_stat.trim_end());
+        user_prompt.push('\n');
+    }
+
+    if let Some(directory_tree) = input.directory_tree {
+        user_prompt.push_str("\nDIRECTORY TREE (around the changed files):\n");
+        user_prompt.push_str(directory_tree.trim_end());
+        user_prompt.push('\n');
+    }
+
+    if let Some(directory_context) = input.directory_context {
+        user_prompt.push_str("\nDIRECTORY CONTEXT:\n");
+        user_prompt.push_str(directory_context.trim_end());
+        user_prompt.push('\n');
+    }
+
+    if let Some(file_history) = input.file_history {
+        user_prompt.push_str(
+            "\nRECENT HISTORY (subjects of the last commits touching each file before this change):\n",
+        );
+        user_prompt.push_str(file_history.trim_end());
+        user_prompt.push('\n');
     }
 
-    user_prompt.push_str("\nDIFF BEGINS:\n");
-    user_prompt.push_str(diff);
-    user_prompt.push_str("\nDIFF ENDS\n\nTOUCHED FILES:\n");
+    if let Some(build_output) = input.build_output {
+        user_prompt.push_str(
+            "\nBUILD/TEST OUTPUT (from a build or test run of this change; relate any failures to the diff):\n",
+        );
+        user_prompt.push_str(build_output.trim_end());
+        user_prompt.push('\n');
+    }
 
-    if files_changed.is_empty() {
+    if let Some(previous_review) = input.previous_review {
+        user_prompt.push_str(
+            "\nPREVIOUS REVIEW (of the commits before this diff; do not repeat its findings unless this diff leaves them unaddressed or makes them worse):\n",
+        );
+        user_prompt.push_str(previous_review.trim_end());
+        user_prompt.push('\n');
+    }
+
+    if let Some(diff) = input.diff {
+        user_prompt.push_str("\nDIFF BEGINS:\n");
+        user_prompt.push_str(diff);
+        user_prompt.push_str("\nDIFF ENDS\n");
+    }
+    user_prompt.push_str("\nTOUCHED FILES:\n");
+
+    if input.files_changed.is_empty() {
         user_prompt.push_str("(none)\n");
     } else {
-        for file in files_changed {
+        for file in input.files_changed {
+            user_prompt.push_str(file);
+            user_prompt.push('\n');
+        }
+    }
+
+    if !input.renames.is_empty() {
+        user_prompt.push_str("\nRENAMES (old path -> new path; the old path no longer exists):\n");
+        for rename in input.renames {
+            user_prompt.push_str(&format!("{} -> {}\n", rename.from, rename.to));
+        }
+    }
+
+    if !input.mode_changes.is_empty() {
+        user_prompt.push_str(
+            "\nMODE CHANGES (not visible in the diff hunks; check each is intended, e.g. a file becoming executable or a submodule moving):\n",
+        );
+        for change in input.mode_changes {
+            user_prompt.push_str(&format!("{}: {}\n", change.path, change.description));
+        }
+    }
+
+    if !input.binary_files.is_empty() {
+        user_prompt.push_str(
+            "\nBINARY FILES (contents not shown in the diff; removed files are described as they were):\n",
+        );
+        user_prompt.push_str(&format_binary_files(input.binary_files));
+    }
+
+    if !input.generated_files.is_empty() {
+        user_prompt.push_str("\nGENERATED FILES (also changed, but left out of the diff):\n");
+        for file in input.generated_files {
+            user_prompt.push_str(file);
+            user_prompt.push('\n');
+        }
+    }
+
+    if !input.deleted_files.is_empty() {
+        user_prompt.push_str(
+            "\nDELETED FILES (removed by this change; their removed lines are not shown):\n",
+        );
+        for file in input.deleted_files {
             user_prompt.push_str(file);
             user_prompt.push('\n');
         }
@@ -36,20 +193,318 @@ pub fn create_user_prompt(
     user_prompt
 }
 
+/// The user prompt for the `--critique` pass: the original prompt, so the
+/// model sees the same diff and context, followed by the first review.
+pub fn create_critique_prompt(user_prompt: &str, review: &str) -> String {
+    format!(
+        "{}\nFIRST REVIEW (written for this diff; check it before anything else):\n{}\n\n\
+         Critique the first review above. Check every finding against the diff and the code, \
+         and drop the ones that are wrong, already handled elsewhere or too speculative to act \
+         on. Then look for real problems it missed. Answer with the refined review in the same \
+         format as a first review, as it will replace the first one: do not describe your \
+         changes to it.\n",
+        user_prompt.trim_end(),
+        review.trim()
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::diff::ChangeType;
+
+    fn input<'a>(diff: &'a str, files_changed: &'a [String]) -> UserPromptInput<'a> {
+        UserPromptInput {
+            diff: Some(diff),
+            files_changed,
+            renames: &[],
+            mode_changes: &[],
+            compact_diff: false,
+            additional_prompt: None,
+            diff_stat: None,
+            directory_context: None,
+            directory_tree: None,
+            build_output: None,
+            file_history: None,
+            previous_review: None,
+            generated_files: &[],
+            deleted_files: &[],
+            binary_files: &[],
+            tools_enabled: true,
+        }
+    }
 
     #[test]
     fn create_user_prompt_includes_diff_and_files() {
         let diff = "diff --git a/a b/a\n+hi\n";
         let files = vec!["src/main.rs".to_string()];
-        let prompt = create_user_prompt(diff, &files, Some("Extra context"));
+        let prompt = create_user_prompt(&UserPromptInput {
+            additional_prompt: Some("Extra context"),
+            ..input(diff, &files)
+        });
 
         assert!(prompt.contains("DIFF BEGINS"));
         assert!(prompt.contains(diff));
         assert!(prompt.contains("TOUCHED FILES"));
         assert!(prompt.contains("src/main.rs"));
         assert!(prompt.contains("Extra context"));
+        assert!(!prompt.contains("DIFF STAT"));
+        assert!(!prompt.contains("DIRECTORY CONTEXT"));
+        assert!(!prompt.contains("BUILD/TEST OUTPUT"));
+        assert!(!prompt.contains("PREVIOUS REVIEW"));
+        assert!(!prompt.contains("GENERATED FILES"));
+        assert!(!prompt.contains("DELETED FILES"));
+        assert!(!prompt.contains("RENAMES"));
+        assert!(!prompt.contains("MODE CHANGES"));
+        assert!(!prompt.contains("no context lines"));
+    }
+
+    #[test]
+    fn create_user_prompt_points_compact_diffs_at_read_file() {
+        let files = vec!["a".to_string()];
+        let prompt = create_user_prompt(&UserPromptInput {
+            compact_diff: true,
+            ..input("+hi\n", &files)
+        });
+
+        let note = prompt.find("no context lines").expect("compact diff note");
+        assert!(note < prompt.find("DIFF BEGINS").unwrap());
+        assert!(prompt.contains("Use read_file"));
+    }
+
+    #[test]
+    fn create_user_prompt_maps_renamed_paths() {
+        let files = vec!["src/new.rs".to_string()];
+        let renames = [Rename {
+            from: "src/old.rs".to_string(),
+            to: "src/new.rs".to_string(),
+        }];
+        let prompt = create_user_prompt(&UserPromptInput {
+            renames: &renames,
+            ..input("+hi\n", &files)
+        });
+
+        assert!(prompt.contains("\nRENAMES (old path -> new path; the old path no longer exists):\nsrc/old.rs -> src/new.rs\n"));
+    }
+
+    #[test]
+    fn create_user_prompt_lists_mode_changes() {
+        let files = vec!["run.sh".to_string()];
+        let mode_changes = [ModeChange {
+            path: "run.sh".to_string(),
+            description: "became executable (100644 -> 100755)".to_string(),
+        }];
+        let prompt = create_user_prompt(&UserPromptInput {
+            mode_changes: &mode_changes,
+            ..input("", &files)
+        });
+
+        assert!(
+            prompt.contains("a submodule moving):\nrun.sh: became executable (100644 -> 100755)\n")
+        );
+    }
+
+    #[test]
+    fn create_user_prompt_lists_binary_files() {
+        let files = vec!["logo.png".to_string()];
+        let binary_files = [BinaryFile::new(
+            "logo.png".to_string(),
+            ChangeType::Added,
+            Some((8, b"\x89PNG\r\n\x1a\n")),
+        )];
+        let prompt = create_user_prompt(&UserPromptInput {
+            binary_files: &binary_files,
+            ..input("", &files)
+        });
+
+        assert!(prompt.contains("as they were):\nlogo.png: added, 8B, image/png\n"));
+    }
+
+    #[test]
+    fn create_user_prompt_includes_diff_stat_before_diff() {
+        let diff = "diff --git a/a b/a\n+hi\n";
+        let stat = " a | 1 +\n 1 file changed, 1 insertion(+)\n";
+        let files = vec!["a".to_string()];
+        let prompt = create_user_prompt(&UserPromptInput {
+            diff_stat: Some(stat),
+            ..input(diff, &files)
+        });
+
+        let stat_index = prompt.find("DIFF STAT:").expect("diff stat section");
+        let diff_index = prompt.find("DIFF BEGINS").expect("diff section");
+        assert!(stat_index < diff_index);
+        assert!(prompt.contains("1 file changed, 1 insertion(+)"));
+    }
+
+    #[test]
+    fn create_user_prompt_includes_directory_context_before_diff() {
+        let files = vec!["src/foo/a.rs".to_string()];
+        let prompt = create_user_prompt(&UserPromptInput {
+            directory_context: Some("--- src/foo/README.md ---\nFoo parses bars.\n"),
+            ..input("+hi\n", &files)
+        });
+
+        let context_index = prompt
+            .find("DIRECTORY CONTEXT:")
+            .expect("directory context section");
+        let diff_index = prompt.find("DIFF BEGINS").expect("diff section");
+        assert!(context_index < diff_index);
+        assert!(prompt.contains("Foo parses bars."));
+    }
+
+    #[test]
+    fn create_user_prompt_includes_directory_tree_before_diff() {
+        let files = vec!["src/a.rs".to_string()];
+        let prompt = create_user_prompt(&UserPromptInput {
+            directory_tree: Some("src/\n  a.rs (changed)\n  b.rs\n"),
+            ..input("+hi\n", &files)
+        });
+
+        let tree_index = prompt
+            .find("DIRECTORY TREE (around the changed files):\nsrc/\n")
+            .expect("directory tree section");
+        assert!(tree_index < prompt.find("DIFF BEGINS").expect("diff section"));
+    }
+
+    #[test]
+    fn create_user_prompt_includes_build_output_before_diff() {
+        let files = vec!["src/a.rs".to_string()];
+        let prompt = create_user_prompt(&UserPromptInput {
+            build_output: Some("--- test.log ---\nFAILED a::works\n"),
+            ..input("+hi\n", &files)
+        });
+
+        let output_index = prompt
+            .find("relate any failures to the diff):\n--- test.log ---\nFAILED a::works\n")
+            .expect("build output section");
+        assert!(output_index < prompt.find("DIFF BEGINS").expect("diff section"));
+    }
+
+    #[test]
+    fn create_user_prompt_includes_previous_review_before_diff() {
+        let files = vec!["src/a.rs".to_string()];
+        let prompt = create_user_prompt(&UserPromptInput {
+            previous_review: Some("The parser drops the last line.\n"),
+            ..input("+hi\n", &files)
+        });
+
+        let review_index = prompt
+            .find("makes them worse):\nThe parser drops the last line.\n")
+            .expect("previous review section");
+        assert!(review_index < prompt.find("DIFF BEGINS").expect("diff section"));
+    }
+
+    #[test]
+    fn create_user_prompt_lists_generated_files_after_touched_files() {
+        let files = vec!["src/a.rs".to_string()];
+        let generated = vec!["src/schema.rs".to_string()];
+        let prompt = create_user_prompt(&UserPromptInput {
+            generated_files: &generated,
+            ..input("+hi\n", &files)
+        });
+
+        let touched_index = prompt.find("TOUCHED FILES").expect("touched files section");
+        let generated_index = prompt
+            .find("GENERATED FILES")
+            .expect("generated files section");
+        assert!(touched_index < generated_index);
+        assert!(prompt.ends_with("src/schema.rs\n"));
+    }
+
+    #[test]
+    fn create_user_prompt_lists_deleted_files_without_their_contents() {
+        let files = vec!["src/a.rs".to_string()];
+        let deleted = vec!["src/old.rs".to_string()];
+        let prompt = create_user_prompt(&UserPromptInput {
+            deleted_files: &deleted,
+            ..input("+hi\n", &files)
+        });
+
+        assert!(prompt.ends_with(
+            "\nDELETED FILES (removed by this change; their removed lines are not shown):\nsrc/old.rs\n"
+        ));
+    }
+
+    #[test]
+    fn create_user_prompt_without_diff_points_to_get_diff() {
+        let files = vec!["a".to_string()];
+        let prompt = create_user_prompt(&UserPromptInput {
+            diff: None,
+            diff_stat: Some(" a | 1 +\n"),
+            ..input("", &files)
+        });
+        assert!(prompt.contains("get_diff"));
+        assert!(prompt.contains("DIFF STAT:\n a | 1 +\n"));
+        assert!(!prompt.contains("DIFF BEGINS"));
+        assert!(prompt.contains("\nTOUCHED FILES:\na\n"));
+    }
+
+    #[test]
+    fn prompts_without_tools_do_not_mention_them() {
+        let files = vec!["a".to_string()];
+        let prompt = create_user_prompt(&UserPromptInput {
+            tools_enabled: false,
+            ..input("+hi\n", &files)
+        });
+        assert!(!prompt.contains("read_file"));
+
+        let system_prompt = get_system_prompt(DEFAULT_BASE_PROMPT, false, false, None, None);
+        assert!(!system_prompt.contains("search_files"));
+        assert!(
+            get_system_prompt(DEFAULT_BASE_PROMPT, true, false, None, None)
+                .contains("search_files")
+        );
+    }
+
+    #[test]
+    fn system_prompt_keeps_tool_guide_around_a_custom_base() {
+        let prompt = get_system_prompt("Review for security only.", true, false, None, None);
+        assert!(prompt.starts_with(include_str!("../prompt_tools.txt")));
+        assert!(prompt.ends_with("Review for security only."));
+        assert!(!prompt.contains("substantiveComments"));
+    }
+
+    #[test]
+    fn system_prompt_describes_findings_only_when_structured() {
+        assert!(
+            !get_system_prompt(DEFAULT_BASE_PROMPT, true, false, None, None)
+                .contains("\"confidence\"")
+        );
+        assert!(
+            get_system_prompt(DEFAULT_BASE_PROMPT, true, true, None, None)
+                .contains("\"confidence\"")
+        );
+    }
+
+    #[test]
+    fn system_prompt_calibrates_severity_with_a_project_guide_if_given() {
+        let prompt = get_system_prompt(DEFAULT_BASE_PROMPT, true, true, None, None);
+        assert!(prompt.contains(include_str!("../prompt_severity.txt").trim()));
+
+        let guide = "critical: anything touching billing.\n";
+        let prompt = get_system_prompt(DEFAULT_BASE_PROMPT, true, true, Some(guide), None);
+        assert!(prompt.ends_with(
+            "</structuredFindings>\n\n<severityCalibration>\ncritical: anything touching billing.\n</severityCalibration>\n"
+        ));
+        assert!(
+            !get_system_prompt(DEFAULT_BASE_PROMPT, true, false, Some(guide), None)
+                .contains("billing")
+        );
+    }
+
+    #[test]
+    fn system_prompt_appends_suffix_after_everything_else() {
+        let prompt = get_system_prompt("Custom base.", true, true, None, Some("Be terse."));
+        assert!(prompt.starts_with(include_str!("../prompt_tools.txt")));
+        assert!(prompt.contains("Custom base.\n\n"));
+        assert!(prompt.ends_with("</severityCalibration>\n\nBe terse."));
+    }
+
+    #[test]
+    fn critique_prompt_follows_the_review_prompt_with_the_first_review() {
+        let prompt =
+            create_critique_prompt("DIFF BEGINS:\n+x\nDIFF ENDS\n\n", "  Bug on line 1.\n");
+        assert!(prompt.starts_with("DIFF BEGINS:\n+x\nDIFF ENDS\nFIRST REVIEW"));
+        assert!(prompt.contains("check it before anything else):\nBug on line 1.\n\nCritique"));
     }
 }
@@ -0,0 +1,175 @@
+use crate::diff::{self, ChangeType, FileDiff};
+use globset::{Glob, GlobMatcher};
+
+const CHANGE_TYPES: &[(&str, ChangeType)] = &[
+    ("added", ChangeType::Added),
+    ("deleted", ChangeType::Deleted),
+    ("modified", ChangeType::Modified),
+    ("renamed", ChangeType::Renamed),
+    ("copied", ChangeType::Copied),
+];
+
+/// A `--select` predicate, `TYPES:GLOB`: files whose change type is one of
+/// the comma-separated `TYPES` (or `any`) and whose path matches `GLOB`.
+#[derive(Debug, Clone)]
+pub struct Selector {
+    raw: String,
+    /// `None` for `any`.
+    change_types: Option<Vec<ChangeType>>,
+    glob: GlobMatcher,
+}
+
+impl Selector {
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let Some((types, pattern)) = raw.split_once(':') else {
+            return Err(format!(
+                "`{}` is not a selector; use TYPES:GLOB, e.g. `added:*.rs`",
+                raw
+            ));
+        };
+        let change_types = if types == "any" {
+            None
+        } else {
+            let types = types
+                .split(',')
+                .map(|name| {
+                    CHANGE_TYPES
+                        .iter()
+                        .find(|(known, _)| *known == name)
+                        .map(|(_, change_type)| *change_type)
+                        .ok_or_else(|| {
+                            format!(
+                                "`{}` is not a change type; use any, {}",
+                                name,
+                                change_type_names()
+                            )
+                        })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Some(types)
+        };
+        if pattern.is_empty() {
+            return Err(format!("`{}` has no glob after the `:`", raw));
+        }
+        let glob = Glob::new(pattern)
+            .map_err(|e| format!("Invalid glob pattern: {}", e))?
+            .compile_matcher();
+        Ok(Self {
+            raw: raw.to_string(),
+            change_types,
+            glob,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.raw
+    }
+
+    fn matches(&self, file: &FileDiff, path: &str) -> bool {
+        self.change_types
+            .as_ref()
+            .is_none_or(|types| types.contains(&file.change_type()))
+            && self.glob.is_match(path)
+    }
+}
+
+fn change_type_names() -> String {
+    CHANGE_TYPES
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The paths in `diff` that no selector matches, to leave out of the review.
+/// Renamed files contribute both paths, so that leaving them out does not
+/// turn the rename into a deletion or an addition.
+pub fn unselected_paths(diff: &str, selectors: &[Selector]) -> Vec<String> {
+    let mut paths = Vec::new();
+    for file in diff::parse_diff(diff) {
+        let Some(path) = file.path() else {
+            continue;
+        };
+        if selectors
+            .iter()
+            .any(|selector| selector.matches(&file, path))
+        {
+            continue;
+        }
+        if file.change_type() == ChangeType::Renamed
+            && let Some(old_path) = file.old_path()
+        {
+            paths.push(old_path.to_string());
+        }
+        paths.push(path.to_string());
+    }
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIFF: &str = "\
+diff --git a/src/new.rs b/src/new.rs
+new file mode 100644
+--- /dev/null
++++ b/src/new.rs
+@@ -0,0 +1 @@
++fn new() {}
+diff --git a/src/lib.rs b/src/lib.rs
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1 +1 @@
+-fn old() {}
++fn lib() {}
+diff --git a/docs/guide.md b/docs/guide.md
+new file mode 100644
+--- /dev/null
++++ b/docs/guide.md
+@@ -0,0 +1 @@
++# Guide
+diff --git a/src/a.rs b/src/b.rs
+similarity index 90%
+rename from src/a.rs
+rename to src/b.rs
+--- a/src/a.rs
++++ b/src/b.rs
+@@ -1 +1 @@
+-a
++b
+";
+
+    fn select(raw: &[&str]) -> Vec<String> {
+        let selectors: Vec<Selector> = raw
+            .iter()
+            .map(|raw| Selector::parse(raw).unwrap())
+            .collect();
+        unselected_paths(DIFF, &selectors)
+    }
+
+    #[test]
+    fn unselected_paths_intersects_change_type_and_glob() {
+        assert_eq!(
+            select(&["added:*.rs"]),
+            vec!["src/lib.rs", "docs/guide.md", "src/a.rs", "src/b.rs"]
+        );
+        assert_eq!(
+            select(&["added,renamed:src/**", "any:docs/*"]),
+            vec!["src/lib.rs"]
+        );
+        assert!(select(&["any:**"]).is_empty());
+    }
+
+    #[test]
+    fn parse_rejects_malformed_selectors() {
+        assert!(Selector::parse("*.rs").unwrap_err().contains("TYPES:GLOB"));
+        assert!(
+            Selector::parse("new:*.rs")
+                .unwrap_err()
+                .contains("`new` is not a change type")
+        );
+        assert!(Selector::parse("added:").is_err());
+        assert!(Selector::parse("added:[").is_err());
+    }
+}
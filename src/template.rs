@@ -0,0 +1,92 @@
+/// A text template with `{{field}}` placeholders, parsed up front so an
+/// unknown placeholder is reported before any API call is made.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Text(String),
+    Field(String),
+}
+
+impl Template {
+    /// Parses `source`, accepting only placeholders named in `fields`.
+    pub fn parse(source: &str, fields: &[&str]) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("{{") {
+            let after_open = &rest[start + 2..];
+            let end = after_open
+                .find("}}")
+                .ok_or_else(|| format!("unclosed `{{{{` in template: `{}`", &rest[start..]))?;
+            let name = after_open[..end].trim();
+            if !fields.contains(&name) {
+                return Err(format!(
+                    "unknown template placeholder `{{{{{}}}}}`; expected one of: {}",
+                    name,
+                    fields.join(", ")
+                ));
+            }
+            if start > 0 {
+                segments.push(Segment::Text(rest[..start].to_string()));
+            }
+            segments.push(Segment::Field(name.to_string()));
+            rest = &after_open[end + 2..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest.to_string()));
+        }
+        Ok(Self { segments })
+    }
+
+    /// Substitutes `values` for the placeholders. Every field accepted by
+    /// `parse` must have a value.
+    pub fn render(&self, values: &[(&str, &str)]) -> String {
+        let mut output = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => output.push_str(text),
+                Segment::Field(name) => {
+                    let value = values
+                        .iter()
+                        .find(|(field, _)| field == name)
+                        .map(|(_, value)| *value)
+                        .unwrap_or_else(|| {
+                            panic!("no value supplied for template field `{}`", name)
+                        });
+                    output.push_str(value);
+                }
+            }
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_substitutes_fields() {
+        let template = Template::parse(
+            "## {{ title }}\n{{review}}\n({{model}})",
+            &["title", "review", "model"],
+        )
+        .unwrap();
+        let output = template.render(&[("title", "T"), ("review", "R"), ("model", "M")]);
+        assert_eq!(output, "## T\nR\n(M)");
+    }
+
+    #[test]
+    fn parse_rejects_unknown_and_unclosed_placeholders() {
+        let error = Template::parse("{{reveiw}}", &["review"]).unwrap_err();
+        assert!(error.contains("unknown template placeholder `{{reveiw}}`"));
+        assert!(
+            Template::parse("{{review", &["review"])
+                .unwrap_err()
+                .contains("unclosed")
+        );
+    }
+}
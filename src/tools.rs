@@ -1,18 +1,43 @@
 use globset::{Glob, GlobSet, GlobSetBuilder};
+use rayon::ThreadPool;
+use rayon::prelude::*;
 use regex::Regex;
 use serde::Deserialize;
 use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
+use std::io::{BufRead, BufReader, ErrorKind};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 use walkdir::WalkDir;
 
 use crate::client::dto::{Tool, ToolFunctionDef};
+use crate::diff::{ChangedLines, parse_diff, render_diff};
+use crate::git::{self, Git};
 
 const DEFAULT_READ_LIMIT: usize = 2000;
 const MAX_READ_LIMIT: usize = 2000;
+/// Display width, in terminal columns, beyond which a line is truncated.
 const MAX_LINE_LENGTH: usize = 2000;
-const MAX_SEARCH_MATCHES: usize = 50;
+/// Length in bytes beyond which a line is truncated whatever its width, so
+/// zero-width characters cannot make one unbounded. Streamed reads keep only
+/// this much of each line, which is all `truncate_line` looks at.
+const MAX_LINE_BYTES: usize = MAX_LINE_LENGTH * 4;
+pub const DEFAULT_MAX_SEARCH_MATCHES: usize = 50;
+pub const MAX_SEARCH_MATCHES_CEILING: usize = 500;
 const SEARCH_CONTEXT_LINES: usize = 1;
+const MAX_PATH_CANDIDATES: usize = 20;
+const READ_ATTEMPTS: usize = 3;
+const READ_RETRY_DELAY: Duration = Duration::from_millis(100);
+/// Files larger than this are streamed by `read_file` rather than loaded
+/// whole, keeping only the lines a read asks for.
+const LARGE_FILE_BYTES: u64 = 8 * 1024 * 1024;
+/// Characters of `git show` output `commit_diff` returns.
+const MAX_COMMIT_DIFF_CHARS: usize = 30_000;
 
 #[derive(Debug, Deserialize)]
 pub struct ReadFileArgs {
@@ -20,7 +45,10 @@ pub struct ReadFileArgs {
     pub mode: Option<String>,
     pub offset: Option<usize>,
     pub limit: Option<usize>,
+    /// Specific 1-based line numbers to return instead of a range.
+    pub lines: Option<Vec<usize>>,
     pub indentation: Option<IndentationOptions>,
+    pub max_chars: Option<usize>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -30,6 +58,15 @@ pub struct IndentationOptions {
     pub include_siblings: Option<bool>,
     pub include_header: Option<bool>,
     pub max_lines: Option<usize>,
+    /// Prefix the anchor line with `>` (default true), as search results mark
+    /// matches.
+    pub mark_anchor: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReadSymbolArgs {
+    pub path: String,
+    pub symbol: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -37,10 +74,164 @@ pub struct SearchFilesArgs {
     pub path: String,
     pub regex: String,
     pub file_pattern: Option<String>,
+    #[serde(default)]
+    pub files_with_matches: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetDiffArgs {
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CommitDiffArgs {
+    #[serde(rename = "ref")]
+    pub reference: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CountMatchesArgs {
+    pub path: String,
+    pub regex: String,
+    pub file_pattern: Option<String>,
+}
+
+/// Per-review settings the tools need beyond the model-supplied arguments.
+#[derive(Debug, Clone)]
+pub struct ToolContext {
+    max_search_matches: usize,
+    changed_lines: ChangedLines,
+    /// When set, `search_files` ranks files in this set (the diff's changed
+    /// files) first, then files by match density, before applying the limit.
+    search_ranking: Option<BTreeSet<String>>,
+    /// The diff under review, when `--diff-as-tool` leaves it out of the
+    /// prompt for `get_diff` to return instead.
+    diff: Option<String>,
+    /// Runs `commit_diff`'s git commands.
+    git: Git,
+    /// Threads the search tools scan files on, bounded by `--concurrency`.
+    /// `None` scans on the calling thread.
+    pool: Option<Arc<ThreadPool>>,
+}
+
+impl ToolContext {
+    pub fn new(max_search_matches: usize) -> Self {
+        assert!(
+            (1..=MAX_SEARCH_MATCHES_CEILING).contains(&max_search_matches),
+            "max_search_matches must be between 1 and {}",
+            MAX_SEARCH_MATCHES_CEILING
+        );
+        Self {
+            max_search_matches,
+            changed_lines: ChangedLines::new(),
+            search_ranking: None,
+            diff: None,
+            git: Git::default(),
+            pool: None,
+        }
+    }
+
+    /// Scans files for the search tools on `pool`'s threads.
+    pub fn with_pool(mut self, pool: ThreadPool) -> Self {
+        self.pool = Some(Arc::new(pool));
+        self
+    }
+
+    /// Runs git for `commit_diff` through `git`, e.g. to honour `--debug-git`.
+    pub fn with_git(mut self, git: Git) -> Self {
+        self.git = git;
+        self
+    }
+
+    /// Lines added by the diff under review, so `read_file` can mark them.
+    pub fn with_changed_lines(mut self, changed_lines: ChangedLines) -> Self {
+        self.changed_lines = changed_lines;
+        self
+    }
+
+    /// Serves `diff` through the `get_diff` tool.
+    pub fn with_diff(mut self, diff: String) -> Self {
+        self.diff = Some(diff);
+        self
+    }
+
+    /// Ranks `search_files` results by relevance to the diff, which changed
+    /// `changed_files`.
+    pub fn with_search_ranking(mut self, changed_files: &[String]) -> Self {
+        self.search_ranking = Some(changed_files.iter().cloned().collect());
+        self
+    }
+
+    fn changed_lines_for(&self, path: &Path) -> Option<&BTreeSet<usize>> {
+        let normalized = path.strip_prefix(".").unwrap_or(path);
+        self.changed_lines.get(normalized.to_str()?)
+    }
+}
+
+impl Default for ToolContext {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_SEARCH_MATCHES)
+    }
+}
+
+/// The tools offered to the model; `get_diff` only when the diff is left out
+/// of the prompt.
+pub fn tool_definitions(include_get_diff: bool) -> Vec<Tool> {
+    let mut tools = vec![
+        read_file_tool(),
+        read_symbol_tool(),
+        search_files_tool(),
+        count_matches_tool(),
+        commit_diff_tool(),
+    ];
+    if include_get_diff {
+        tools.push(get_diff_tool());
+    }
+    tools
+}
+
+fn get_diff_tool() -> Tool {
+    Tool {
+        tool_type: "function".to_string(),
+        function: ToolFunctionDef {
+            name: "get_diff".to_string(),
+            description: "Return the git diff under review, which is not included in the prompt. Pass a path from TOUCHED FILES to get only that file's diff; omit it for the whole diff. Fetch per file when only some files need a close look, since everything a tool returns stays in the conversation.\n\nExample: { \"path\": \"src/main.rs\" }".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "path": {
+                        "type": ["string", "null"],
+                        "description": "Changed file whose diff to return; omit for the whole diff"
+                    }
+                },
+                "additionalProperties": false
+            }),
+        },
+    }
 }
 
-pub fn tool_definitions() -> Vec<Tool> {
-    vec![read_file_tool(), search_files_tool()]
+fn commit_diff_tool() -> Tool {
+    Tool {
+        tool_type: "function".to_string(),
+        function: ToolFunctionDef {
+            name: "commit_diff".to_string(),
+            description: format!(
+                "Return `git show` for one commit: its message, the files it touched and its diff. Use it to see how a change spanning several commits was built up, one commit at a time. Output past {} characters is cut.\n\nExample: {{ \"ref\": \"HEAD~1\" }}",
+                MAX_COMMIT_DIFF_CHARS
+            ),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "ref": {
+                        "type": "string",
+                        "description": "The commit to show: a hash, branch, tag or an expression such as HEAD~2"
+                    }
+                },
+                "required": ["ref"],
+                "additionalProperties": false
+            }),
+        },
+    }
 }
 
 fn read_file_tool() -> Tool {
@@ -69,6 +260,15 @@ fn read_file_tool() -> Tool {
                         "type": "integer",
                         "description": "Maximum number of lines to return (default 2000)"
                     },
+                    "lines": {
+                        "type": "array",
+                        "items": { "type": "integer" },
+                        "description": "Return only these 1-based line numbers (e.g. [10, 42, 88]), with '...' between non-adjacent ones. Cheaper than several range reads when you need a few specific lines. When given, mode, offset and limit are ignored."
+                    },
+                    "max_chars": {
+                        "type": "integer",
+                        "description": "Cap on characters returned; output is cut at a line boundary and the file's total size is reported. Use for large documents."
+                    },
                     "indentation": {
                         "type": "object",
                         "description": "Indentation mode options. Only used when mode='indentation'.",
@@ -92,6 +292,10 @@ fn read_file_tool() -> Tool {
                             "max_lines": {
                                 "type": "integer",
                                 "description": "Hard cap on lines returned for indentation mode."
+                            },
+                            "mark_anchor": {
+                                "type": "boolean",
+                                "description": "Mark the anchor line with '>' in the left margin (other lines get two spaces). Defaults to true."
                             }
                         },
                         "required": [],
@@ -110,7 +314,65 @@ fn search_files_tool() -> Tool {
         tool_type: "function".to_string(),
         function: ToolFunctionDef {
             name: "search_files".to_string(),
-            description: "Request to perform a regex search across files in a specified directory, providing context-rich results. This tool searches for patterns or specific content across multiple files, displaying each match with encapsulating context.\n\nCraft your regex patterns carefully to balance specificity and flexibility. Use this tool to find code patterns, TODO comments, function definitions, or any text-based information across the project. The results include surrounding context, so analyze the surrounding code to better understand the matches. Leverage this tool in combination with other tools for more comprehensive analysis.\n\nParameters:\n- path: (required) The path of the directory to search in (relative to the current workspace directory). This directory will be recursively searched.\n- regex: (required) The regular expression pattern to search for. Uses Rust regex syntax.\n- file_pattern: (optional) Glob pattern to filter files (e.g., '*.ts' for TypeScript files). If not provided, it will search all files (*).\n\nExample: Searching for all .ts files in the current directory\n{ \"path\": \".\", \"regex\": \".*\", \"file_pattern\": \"*.ts\" }\n\nExample: Searching for function definitions in JavaScript files\n{ \"path\": \"src\", \"regex\": \"function\\s+\\w+\", \"file_pattern\": \"*.js\" }".to_string(),
+            description: "Request to perform a regex search across files in a specified directory, providing context-rich results. This tool searches for patterns or specific content across multiple files, displaying each match with encapsulating context. Nearby matches in one file share a single block, headed by all their line numbers, with each matching line marked `>`. Files that cannot be read as text (binary or unreadable) are skipped, and a closing line counts them.\n\nCraft your regex patterns carefully to balance specificity and flexibility. Use this tool to find code patterns, TODO comments, function definitions, or any text-based information across the project. The results include surrounding context, so analyze the surrounding code to better understand the matches. Leverage this tool in combination with other tools for more comprehensive analysis.\n\nParameters:\n- path: (required) The path of the directory to search in (relative to the current workspace directory). This directory will be recursively searched.\n- regex: (required) The regular expression pattern to search for. Uses Rust regex syntax.\n- file_pattern: (optional) Glob pattern to filter files (e.g., '*.ts' for TypeScript files). If not provided, it will search all files (*).\n\nExample: Searching for all .ts files in the current directory\n{ \"path\": \".\", \"regex\": \".*\", \"file_pattern\": \"*.ts\" }\n\nExample: Searching for function definitions in JavaScript files\n{ \"path\": \"src\", \"regex\": \"function\\s+\\w+\", \"file_pattern\": \"*.js\" }".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "path": {
+                        "type": "string",
+                        "description": "Directory to search recursively, relative to the workspace"
+                    },
+                    "regex": {
+                        "type": "string",
+                        "description": "Rust-compatible regex pattern to match"
+                    },
+                    "file_pattern": {
+                        "type": ["string", "null"],
+                        "description": "Optional glob to limit which files are searched (e.g., *.rs)"
+                    },
+                    "files_with_matches": {
+                        "type": "boolean",
+                        "description": "Return only the paths of files containing a match, without matching lines (like grep -l). Not subject to the match limit."
+                    }
+                },
+                "required": ["path", "regex"],
+                "additionalProperties": false
+            }),
+        },
+    }
+}
+
+fn read_symbol_tool() -> Tool {
+    Tool {
+        tool_type: "function".to_string(),
+        function: ToolFunctionDef {
+            name: "read_symbol".to_string(),
+            description: "Read the full definition of a named function, type, class or similar symbol in one file, with line numbers. The definition line is found by a language-aware pattern (e.g. `fn name`, `struct name` in Rust; `def name`, `class name` in Python) and the block is extracted by indentation, including a closing `}` line. Use this instead of searching for a definition and then reading around it. If the name is defined more than once in the file, the candidate lines are listed; follow up with read_file in indentation mode.\n\nExample: { \"path\": \"src/tools.rs\", \"symbol\": \"handle_tool_call\" }".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "path": {
+                        "type": "string",
+                        "description": "File to read, relative to the workspace"
+                    },
+                    "symbol": {
+                        "type": "string",
+                        "description": "Bare name of the symbol, without module path or parameters"
+                    }
+                },
+                "required": ["path", "symbol"],
+                "additionalProperties": false
+            }),
+        },
+    }
+}
+
+fn count_matches_tool() -> Tool {
+    Tool {
+        tool_type: "function".to_string(),
+        function: ToolFunctionDef {
+            name: "count_matches".to_string(),
+            description: "Count regex matches across files in a directory without returning any matching lines. Returns the number of matching lines per file and the overall total. Use this to gauge how widespread a pattern is (e.g. how many call sites an API has) before deciding whether to search_files or read_file for details. Counts are exact and are not subject to the search_files match limit.\n\nExample: { \"path\": \"src\", \"regex\": \"deprecated_fn\\\\(\", \"file_pattern\": \"*.rs\" }".to_string(),
             parameters: json!({
                 "type": "object",
                 "properties": {
@@ -134,17 +396,82 @@ fn search_files_tool() -> Tool {
     }
 }
 
-pub fn handle_tool_call(name: &str, arguments: &str) -> String {
+pub fn handle_tool_call(name: &str, arguments: &str, context: &ToolContext) -> String {
     match name {
         "read_file" => match serde_json::from_str::<ReadFileArgs>(arguments) {
-            Ok(args) => read_file(&args),
+            Ok(args) => read_file(&args, context),
             Err(err) => format_tool_error("read_file", &format!("Invalid arguments: {}", err)),
         },
+        "read_symbol" => match serde_json::from_str::<ReadSymbolArgs>(arguments) {
+            Ok(args) => read_symbol(&args, context),
+            Err(err) => format_tool_error("read_symbol", &format!("Invalid arguments: {}", err)),
+        },
         "search_files" => match serde_json::from_str::<SearchFilesArgs>(arguments) {
-            Ok(args) => search_files(&args),
+            Ok(args) => search_files(&args, context),
             Err(err) => format_tool_error("search_files", &format!("Invalid arguments: {}", err)),
         },
-        _ => format_tool_error(name, "Unknown tool name"),
+        "count_matches" => match serde_json::from_str::<CountMatchesArgs>(arguments) {
+            Ok(args) => count_matches(&args, context),
+            Err(err) => format_tool_error("count_matches", &format!("Invalid arguments: {}", err)),
+        },
+        "get_diff" => match serde_json::from_str::<GetDiffArgs>(arguments) {
+            Ok(args) => get_diff(&args, context),
+            Err(err) => format_tool_error("get_diff", &format!("Invalid arguments: {}", err)),
+        },
+        "commit_diff" => match serde_json::from_str::<CommitDiffArgs>(arguments) {
+            Ok(args) => commit_diff(&args, context),
+            Err(err) => format_tool_error("commit_diff", &format!("Invalid arguments: {}", err)),
+        },
+        _ => format_tool_error(
+            name,
+            &format!(
+                "Unknown tool name. The available tools are: {}",
+                available_tools(context).join(", ")
+            ),
+        ),
+    }
+}
+
+/// Whether `name` is one of the tools offered with `context`.
+pub fn is_available_tool(name: &str, context: &ToolContext) -> bool {
+    available_tools(context).iter().any(|tool| tool == name)
+}
+
+fn available_tools(context: &ToolContext) -> Vec<String> {
+    tool_definitions(context.diff.is_some())
+        .into_iter()
+        .map(|tool| tool.function.name)
+        .collect()
+}
+
+/// What a tool call looked at, for the `--show-inspected` report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inspection {
+    File(String),
+    Search { path: String, regex: String },
+}
+
+pub fn inspection_for_call(name: &str, arguments: &str) -> Option<Inspection> {
+    match name {
+        "read_file" => serde_json::from_str::<ReadFileArgs>(arguments)
+            .ok()
+            .map(|args| Inspection::File(args.path)),
+        "read_symbol" => serde_json::from_str::<ReadSymbolArgs>(arguments)
+            .ok()
+            .map(|args| Inspection::File(args.path)),
+        "search_files" => serde_json::from_str::<SearchFilesArgs>(arguments)
+            .ok()
+            .map(|args| Inspection::Search {
+                path: args.path,
+                regex: args.regex,
+            }),
+        "count_matches" => serde_json::from_str::<CountMatchesArgs>(arguments)
+            .ok()
+            .map(|args| Inspection::Search {
+                path: args.path,
+                regex: args.regex,
+            }),
+        _ => None,
     }
 }
 
@@ -169,66 +496,704 @@ pub fn summarize_tool_call(name: &str, arguments: &str) -> String {
                     format!("read_file {}:{}-{}", args.path, offset, end)
                 }
             }
-            Err(_) => format!("read_file (invalid args)"),
+            Err(_) => "read_file (invalid args)".to_string(),
+        },
+        "read_symbol" => match serde_json::from_str::<ReadSymbolArgs>(arguments) {
+            Ok(args) => format!("read_symbol {} symbol={}", args.path, args.symbol),
+            Err(_) => "read_symbol (invalid args)".to_string(),
         },
         "search_files" => match serde_json::from_str::<SearchFilesArgs>(arguments) {
+            Ok(args) => {
+                let mut summary = format!("search_files {} regex={}", args.path, args.regex);
+                if let Some(pattern) = args.file_pattern.as_deref()
+                    && !pattern.trim().is_empty()
+                {
+                    summary.push_str(&format!(" files={}", pattern));
+                }
+                if args.files_with_matches {
+                    summary.push_str(" (paths only)");
+                }
+                summary
+            }
+            Err(_) => "search_files (invalid args)".to_string(),
+        },
+        "count_matches" => match serde_json::from_str::<CountMatchesArgs>(arguments) {
             Ok(args) => match args.file_pattern.as_deref() {
                 Some(pattern) if !pattern.trim().is_empty() => format!(
-                    "search_files {} regex={} files={}",
+                    "count_matches {} regex={} files={}",
                     args.path, args.regex, pattern
                 ),
-                _ => format!("search_files {} regex={}", args.path, args.regex),
+                _ => format!("count_matches {} regex={}", args.path, args.regex),
+            },
+            Err(_) => "count_matches (invalid args)".to_string(),
+        },
+        "get_diff" => match serde_json::from_str::<GetDiffArgs>(arguments) {
+            Ok(args) => match args.path {
+                Some(path) => format!("get_diff {}", path),
+                None => "get_diff".to_string(),
             },
-            Err(_) => format!("search_files (invalid args)"),
+            Err(_) => "get_diff (invalid args)".to_string(),
+        },
+        "commit_diff" => match serde_json::from_str::<CommitDiffArgs>(arguments) {
+            Ok(args) => format!("commit_diff {}", args.reference),
+            Err(_) => "commit_diff (invalid args)".to_string(),
         },
         _ => format!("{} (unknown tool)", name),
     }
 }
 
-fn read_file(args: &ReadFileArgs) -> String {
+fn get_diff(args: &GetDiffArgs, context: &ToolContext) -> String {
+    let Some(diff) = &context.diff else {
+        return format_tool_error("get_diff", "The diff is already in the prompt");
+    };
+    let Some(path) = args.path.as_deref().filter(|path| !path.trim().is_empty()) else {
+        return format!("DIFF:\n{}", diff);
+    };
+    let files: Vec<_> = parse_diff(diff)
+        .into_iter()
+        .filter(|file| file.new_path() == Some(path) || file.old_path() == Some(path))
+        .collect();
+    if files.is_empty() {
+        return format_tool_error(
+            "get_diff",
+            &format!(
+                "{} is not changed in the diff; use a path from TOUCHED FILES",
+                path
+            ),
+        );
+    }
+    format!("DIFF ({}):\n{}", path, render_diff(&files))
+}
+
+fn commit_diff(args: &CommitDiffArgs, context: &ToolContext) -> String {
+    match git::show_commit(context.git, args.reference.trim()) {
+        Ok(output) => format!(
+            "COMMIT ({}):\n{}",
+            args.reference.trim(),
+            cap_commit_diff(output, MAX_COMMIT_DIFF_CHARS)
+        ),
+        Err(err) => format_tool_error("commit_diff", &err.to_string()),
+    }
+}
+
+/// Cuts `output` at the last line boundary within `max_chars` characters.
+/// The stat near the top still lists every file the commit touched.
+fn cap_commit_diff(output: String, max_chars: usize) -> String {
+    let total = output.chars().count();
+    if total <= max_chars {
+        return format!("{}\n", output.trim_end());
+    }
+    let cut = output
+        .char_indices()
+        .nth(max_chars)
+        .map_or(output.len(), |(index, _)| index);
+    let kept = output[..cut].rfind('\n').map_or(0, |index| index + 1);
+    format!(
+        "{}[output truncated at {} of {} characters; the stat above lists every file the commit touched]\n",
+        &output[..kept],
+        max_chars,
+        total
+    )
+}
+
+fn read_file(args: &ReadFileArgs, context: &ToolContext) -> String {
+    let requested = Path::new(&args.path);
+    let (path, contents, resolution_note) = match read_with_retry(requested) {
+        Ok(value) => (requested.to_path_buf(), value, None),
+        Err(err) if err.kind() == ErrorKind::NotFound && requested.is_relative() => {
+            match resolve_missing_path(Path::new("."), requested) {
+                PathResolution::Unique(resolved) => match read_with_retry(&resolved) {
+                    Ok(value) => {
+                        let note = format!(
+                            "NOTE: {} does not exist; showing {}, the only file whose path ends with it.\n",
+                            requested.display(),
+                            resolved.display()
+                        );
+                        (resolved, value, Some(note))
+                    }
+                    Err(err) => {
+                        return format_tool_error(
+                            "read_file",
+                            &format!("Failed to read {}: {}", resolved.display(), err),
+                        );
+                    }
+                },
+                PathResolution::Ambiguous(candidates) => {
+                    let listing: Vec<String> = candidates
+                        .iter()
+                        .map(|candidate| format!("  {}", candidate.display()))
+                        .collect();
+                    return format_tool_error(
+                        "read_file",
+                        &format!(
+                            "{} does not exist; these files end with that path:\n{}",
+                            requested.display(),
+                            listing.join("\n")
+                        ),
+                    );
+                }
+                PathResolution::NotFound => {
+                    return format_tool_error(
+                        "read_file",
+                        &format!("Failed to read {}: {}", requested.display(), err),
+                    );
+                }
+            }
+        }
+        Err(err) => {
+            return format_tool_error(
+                "read_file",
+                &format!("Failed to read {}: {}", requested.display(), err),
+            );
+        }
+    };
+    let path = path.as_path();
+
+    let changed = context.changed_lines_for(path);
+    let output = match &contents {
+        FileContents::Whole(contents) => read_whole_file(path, contents, args, changed),
+        FileContents::Large => read_large_file(path, args, changed),
+    };
+    if is_tool_error(&output) {
+        return output;
+    }
+
+    let output = with_changed_lines_note(output, changed);
+    match resolution_note {
+        Some(note) => note + &output,
+        None => output,
+    }
+}
+
+fn read_whole_file(
+    path: &Path,
+    contents: &str,
+    args: &ReadFileArgs,
+    changed: Option<&BTreeSet<usize>>,
+) -> String {
+    let output = if let Some(line_numbers) = &args.lines
+        && !line_numbers.is_empty()
+    {
+        read_file_lines(path, contents, line_numbers, changed)
+    } else if args.mode.as_deref() == Some("indentation") {
+        read_file_indentation(path, contents, args, changed)
+    } else {
+        read_file_slice(path, contents, args, changed)
+    };
+    match args.max_chars {
+        Some(max_chars) if !is_tool_error(&output) => cap_output(
+            output,
+            max_chars,
+            contents.lines().count(),
+            contents.chars().count(),
+        ),
+        _ => output,
+    }
+}
+
+/// Reads a file over `LARGE_FILE_BYTES` in one pass, holding only the lines
+/// the slice or `lines` read asks for. Indentation mode needs the lines
+/// around its anchor before it knows which to keep, so it is refused.
+fn read_large_file(path: &Path, args: &ReadFileArgs, changed: Option<&BTreeSet<usize>>) -> String {
+    let line_numbers = args.lines.as_ref().filter(|lines| !lines.is_empty());
+    if line_numbers.is_none() && args.mode.as_deref() == Some("indentation") {
+        return format_tool_error(
+            "read_file",
+            &format!(
+                "{} is larger than {}MB, too large for indentation mode; use offset/limit or lines",
+                path.display(),
+                LARGE_FILE_BYTES / (1024 * 1024)
+            ),
+        );
+    }
+    let (offset, requested_limit, limit) = slice_range(args);
+    let requested: BTreeSet<usize> = line_numbers.into_iter().flatten().copied().collect();
+    let keep = |line_number: usize| match line_numbers {
+        Some(_) => requested.contains(&line_number),
+        None => (offset..offset.saturating_add(limit)).contains(&line_number),
+    };
+    let streamed = match stream_lines(path, keep) {
+        Ok(streamed) => streamed,
+        Err(err) => {
+            return format_tool_error(
+                "read_file",
+                &format!("Failed to read {}: {}", path.display(), err),
+            );
+        }
+    };
+    let output = match line_numbers {
+        Some(line_numbers) => {
+            let kept: BTreeMap<usize, &str> = streamed
+                .kept
+                .iter()
+                .map(|(line_number, line)| (*line_number, line.as_str()))
+                .collect();
+            format_lines(
+                path,
+                line_numbers,
+                streamed.total_lines,
+                |line_number| kept[&line_number],
+                changed,
+            )
+        }
+        None => {
+            let window: Vec<&str> = streamed
+                .kept
+                .iter()
+                .map(|(_, line)| line.as_str())
+                .collect();
+            format_slice(
+                path,
+                &window,
+                offset,
+                requested_limit,
+                streamed.total_lines,
+                changed,
+            )
+        }
+    };
+    match args.max_chars {
+        Some(max_chars) if !is_tool_error(&output) => cap_output(
+            output,
+            max_chars,
+            streamed.total_lines,
+            streamed.total_chars,
+        ),
+        _ => output,
+    }
+}
+
+/// What `stream_lines` found: the kept lines with their numbers, and the
+/// counts `str::lines` and `str::chars` would give for the whole file.
+struct StreamedLines {
+    kept: Vec<(usize, String)>,
+    total_lines: usize,
+    total_chars: usize,
+}
+
+/// Scans `path` line by line without loading it, keeping (up to
+/// `MAX_LINE_BYTES` of) the lines `keep` accepts. Invalid UTF-8 is
+/// replaced rather than failing the read.
+fn stream_lines(path: &Path, keep: impl Fn(usize) -> bool) -> std::io::Result<StreamedLines> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    let mut streamed = StreamedLines {
+        kept: Vec::new(),
+        total_lines: 0,
+        total_chars: 0,
+    };
+    let mut line = Vec::new();
+    let mut in_line = false;
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            break;
+        }
+        let (chunk, ends_line) = match buffer.iter().position(|&byte| byte == b'\n') {
+            Some(index) => (&buffer[..index], true),
+            None => (buffer, false),
+        };
+        // Every byte of UTF-8 except continuation bytes starts a character.
+        streamed.total_chars += chunk.iter().filter(|&&byte| byte & 0xc0 != 0x80).count();
+        let line_number = streamed.total_lines + 1;
+        if keep(line_number) {
+            let room = MAX_LINE_BYTES.saturating_sub(line.len());
+            line.extend_from_slice(&chunk[..chunk.len().min(room)]);
+        }
+        in_line |= !chunk.is_empty();
+        let consumed = chunk.len() + usize::from(ends_line);
+        reader.consume(consumed);
+        if ends_line {
+            streamed.total_chars += 1;
+            finish_line(&mut streamed, &mut line, &keep);
+            in_line = false;
+        }
+    }
+    if in_line {
+        finish_line(&mut streamed, &mut line, &keep);
+    }
+    Ok(streamed)
+}
+
+fn finish_line(streamed: &mut StreamedLines, line: &mut Vec<u8>, keep: impl Fn(usize) -> bool) {
+    streamed.total_lines += 1;
+    if keep(streamed.total_lines) {
+        let text = String::from_utf8_lossy(line.strip_suffix(b"\r").unwrap_or(line));
+        streamed
+            .kept
+            .push((streamed.total_lines, text.into_owned()));
+    }
+    line.clear();
+}
+
+/// A file's contents, unless it is too large to load whole.
+enum FileContents {
+    Whole(String),
+    /// Over `LARGE_FILE_BYTES`; see `read_large_file`.
+    Large,
+}
+
+/// `fs::read_to_string`, retried up to `READ_ATTEMPTS` times on errors that
+/// network filesystems report transiently. Other errors return at once.
+/// Files over `LARGE_FILE_BYTES` are not read.
+///
+/// The delay blocks the calling thread, an async runtime worker included.
+/// That is deliberate: tool calls are synchronous, like the file reads
+/// around this one, and the review loop has nothing else to run while it
+/// waits for their results.
+fn read_with_retry(path: &Path) -> std::io::Result<FileContents> {
+    let mut attempt = 1;
+    loop {
+        match read_unless_large(path) {
+            Err(err) if is_transient(err.kind()) && attempt < READ_ATTEMPTS => {
+                attempt += 1;
+                thread::sleep(READ_RETRY_DELAY);
+            }
+            result => return result,
+        }
+    }
+}
+
+fn read_unless_large(path: &Path) -> std::io::Result<FileContents> {
+    if fs::metadata(path)?.len() > LARGE_FILE_BYTES {
+        return Ok(FileContents::Large);
+    }
+    fs::read_to_string(path).map(FileContents::Whole)
+}
+
+fn is_transient(kind: ErrorKind) -> bool {
+    matches!(
+        kind,
+        ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+            | ErrorKind::TimedOut
+            | ErrorKind::ResourceBusy
+    )
+}
+
+/// Where a `read_file` path that does not exist might point.
+#[derive(Debug, PartialEq, Eq)]
+enum PathResolution {
+    Unique(PathBuf),
+    Ambiguous(Vec<PathBuf>),
+    NotFound,
+}
+
+/// Looks under `root` for files whose path ends with all of `requested`'s
+/// components, e.g. `src/app.ts` for a requested `app.ts`.
+fn resolve_missing_path(root: &Path, requested: &Path) -> PathResolution {
+    let requested = requested.strip_prefix(".").unwrap_or(requested);
+    let mut candidates: Vec<PathBuf> = searchable_files(root, None)
+        .filter(|path| path.ends_with(requested))
+        .map(|path| {
+            path.strip_prefix(".")
+                .map(Path::to_path_buf)
+                .unwrap_or(path)
+        })
+        .take(MAX_PATH_CANDIDATES + 1)
+        .collect();
+    match candidates.len() {
+        0 => PathResolution::NotFound,
+        1 => PathResolution::Unique(candidates.remove(0)),
+        _ => {
+            candidates.truncate(MAX_PATH_CANDIDATES);
+            candidates.sort();
+            PathResolution::Ambiguous(candidates)
+        }
+    }
+}
+
+/// Cuts `output` at the last line boundary within `max_chars` characters and
+/// says how much of the file exists, so the model knows what it is missing.
+fn cap_output(output: String, max_chars: usize, file_lines: usize, file_chars: usize) -> String {
+    if output.chars().count() <= max_chars {
+        return output;
+    }
+    let cut = output
+        .char_indices()
+        .nth(max_chars)
+        .map_or(output.len(), |(index, _)| index);
+    let kept = output[..cut].rfind('\n').map_or(0, |index| index + 1);
+    format!(
+        "{}[output truncated at max_chars={}; the file has {} lines and {} characters. Use offset/limit to read further.]\n",
+        &output[..kept],
+        max_chars,
+        file_lines,
+        file_chars
+    )
+}
+
+fn with_changed_lines_note(output: String, changed: Option<&BTreeSet<usize>>) -> String {
+    if changed.is_some() {
+        format!(
+            "NOTE: lines marked '+' were added or changed in the diff under review.\n{}",
+            output
+        )
+    } else {
+        output
+    }
+}
+
+fn read_symbol(args: &ReadSymbolArgs, context: &ToolContext) -> String {
     let path = Path::new(&args.path);
     let contents = match fs::read_to_string(path) {
         Ok(value) => value,
         Err(err) => {
             return format_tool_error(
-                "read_file",
+                "read_symbol",
                 &format!("Failed to read {}: {}", path.display(), err),
-            )
+            );
+        }
+    };
+
+    let definition = definition_regex(path, &args.symbol);
+    let lines: Vec<&str> = contents.lines().collect();
+    let candidates: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| definition.is_match(line))
+        .map(|(index, _)| index)
+        .collect();
+
+    let def_index = match candidates.as_slice() {
+        [] => {
+            return format_tool_error(
+                "read_symbol",
+                &format!(
+                    "No definition of `{}` found in {} (pattern: {})",
+                    args.symbol,
+                    path.display(),
+                    definition
+                ),
+            );
+        }
+        [index] => *index,
+        _ => {
+            let listing: Vec<String> = candidates
+                .iter()
+                .map(|index| {
+                    format!(
+                        "  line {}: {}",
+                        index + 1,
+                        truncate_line(lines[*index].trim())
+                    )
+                })
+                .collect();
+            return format_tool_error(
+                "read_symbol",
+                &format!(
+                    "`{}` is defined {} times in {}; use read_file in indentation mode with one of these anchor lines:\n{}",
+                    args.symbol,
+                    candidates.len(),
+                    path.display(),
+                    listing.join("\n")
+                ),
+            );
+        }
+    };
+
+    let end_index = find_definition_end(&lines, def_index);
+    let changed = context.changed_lines_for(path);
+    let numbered_lines: Vec<String> = lines[def_index..=end_index]
+        .iter()
+        .enumerate()
+        .map(|(i, line)| format_numbered_line(def_index + 1 + i, line, changed))
+        .collect();
+    with_changed_lines_note(format_file_output(path, &numbered_lines), changed)
+}
+
+/// Matches a line that defines `symbol`, using the definition keywords of the
+/// file's language (picked by extension) or a generic set for anything else.
+fn definition_regex(path: &Path, symbol: &str) -> Regex {
+    let keywords = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("rs") => r"(?:fn|struct|enum|trait|union|type|mod|const|static|macro_rules!)",
+        Some("py") => r"(?:def|class)",
+        Some("js" | "jsx" | "ts" | "tsx" | "mjs" | "cjs") => {
+            r"(?:function\*?|class|interface|type|enum|const|let|var)"
+        }
+        Some("go") => r"(?:func(?:\s*\([^)]*\))?|type)",
+        Some("fs" | "fsx" | "fsi") => {
+            r"(?:let|member|type|module|and)(?:\s+(?:rec|inline|private|internal|mutable))*(?:\s+\w+\.)?"
         }
+        _ => r"(?:fn|def|function|func|class|struct|enum|interface|trait|type)",
     };
+    Regex::new(&format!(r"\b{}\s+{}\b", keywords, regex::escape(symbol)))
+        .expect("definition pattern is built from fixed keywords and an escaped symbol")
+}
 
-    if args.mode.as_deref() == Some("indentation") {
-        return read_file_indentation(path, &contents, args);
+/// Last line of the definition starting at `def_index`: the indented body
+/// found with the indentation helpers, plus any closing line at the
+/// definition's own indentation (`}`, or `) -> T {` ahead of a further body).
+fn find_definition_end(lines: &[&str], def_index: usize) -> usize {
+    let def_indent = line_indent(lines[def_index]);
+    let mut end = def_index;
+    while let Some(next) = (end + 1..lines.len()).find(|&i| !lines[i].trim().is_empty()) {
+        let indent = line_indent(lines[next]);
+        if indent > def_indent {
+            end = find_block_end_down(lines, next, indent);
+            continue;
+        }
+        let trimmed = lines[next].trim();
+        if indent == def_indent && trimmed.starts_with(['}', ')', ']']) {
+            end = next;
+            if trimmed.ends_with(['{', ':']) {
+                continue;
+            }
+        }
+        break;
     }
+    end
+}
 
-    read_file_slice(path, &contents, args)
+fn read_file_slice(
+    path: &Path,
+    contents: &str,
+    args: &ReadFileArgs,
+    changed: Option<&BTreeSet<usize>>,
+) -> String {
+    let (offset, requested_limit, limit) = slice_range(args);
+    let lines: Vec<&str> = contents.lines().collect();
+    let start_index = (offset - 1).min(lines.len());
+    let end_index = start_index.saturating_add(limit).min(lines.len());
+    format_slice(
+        path,
+        &lines[start_index..end_index],
+        offset,
+        requested_limit,
+        lines.len(),
+        changed,
+    )
 }
 
-fn read_file_slice(path: &Path, contents: &str, args: &ReadFileArgs) -> String {
+/// The first line, the requested limit and the limit applied for a slice
+/// read.
+fn slice_range(args: &ReadFileArgs) -> (usize, usize, usize) {
     let offset = args.offset.unwrap_or(1).max(1);
-    let limit = args.limit.unwrap_or(DEFAULT_READ_LIMIT).min(MAX_READ_LIMIT);
+    let requested_limit = args.limit.unwrap_or(DEFAULT_READ_LIMIT);
+    (offset, requested_limit, requested_limit.min(MAX_READ_LIMIT))
+}
 
-    let lines: Vec<&str> = contents.lines().collect();
-    if lines.is_empty() {
-        return format_file_output(path, &[]);
+/// Formats `window`, the lines of a `total_lines`-line file from `offset` on.
+fn format_slice(
+    path: &Path,
+    window: &[&str],
+    offset: usize,
+    requested_limit: usize,
+    total_lines: usize,
+    changed: Option<&BTreeSet<usize>>,
+) -> String {
+    if total_lines == 0 {
+        return format!("FILE: {}\n(empty file)\n", path.display());
+    }
+    if offset > total_lines {
+        return format_tool_error(
+            "read_file",
+            &format!(
+                "offset {} is past the end of {}, which has {} line(s); use an offset from 1 to {}",
+                offset,
+                path.display(),
+                total_lines,
+                total_lines
+            ),
+        );
     }
 
-    let start_index = offset.saturating_sub(1);
-    if start_index >= lines.len() {
-        return format_file_output(path, &[]);
+    let numbered_lines: Vec<String> = window
+        .iter()
+        .enumerate()
+        .map(|(i, line)| format_numbered_line(offset + i, line, changed))
+        .collect();
+    let end_line = offset + window.len() - 1;
+
+    let mut output = format_file_output(path, &numbered_lines);
+    if requested_limit > MAX_READ_LIMIT && end_line < total_lines {
+        output.push_str(&format!(
+            "NOTE: limit {} exceeds the maximum of {}; showed lines {}-{} of {}. Continue with offset {}.\n",
+            requested_limit,
+            MAX_READ_LIMIT,
+            offset,
+            end_line,
+            total_lines,
+            end_line + 1
+        ));
+    }
+    output
+}
+
+/// The requested lines in file order, each once, with a `...` line where
+/// the numbers skip. Numbers past the end are listed in a note; if no number
+/// is in range, the read fails.
+fn read_file_lines(
+    path: &Path,
+    contents: &str,
+    line_numbers: &[usize],
+    changed: Option<&BTreeSet<usize>>,
+) -> String {
+    let lines: Vec<&str> = contents.lines().collect();
+    format_lines(
+        path,
+        line_numbers,
+        lines.len(),
+        |line_number| lines[line_number - 1],
+        changed,
+    )
+}
+
+/// Formats the requested lines of a `total_lines`-line file; `line_at` is
+/// only asked for line numbers within it.
+fn format_lines<'a>(
+    path: &Path,
+    line_numbers: &[usize],
+    total_lines: usize,
+    line_at: impl Fn(usize) -> &'a str,
+    changed: Option<&BTreeSet<usize>>,
+) -> String {
+    let requested: BTreeSet<usize> = line_numbers.iter().copied().collect();
+    let (in_range, out_of_range): (Vec<usize>, Vec<usize>) = requested
+        .into_iter()
+        .partition(|&line_number| (1..=total_lines).contains(&line_number));
+    if in_range.is_empty() {
+        return format_tool_error(
+            "read_file",
+            &format!(
+                "none of the requested lines are in {}, which has {} line(s)",
+                path.display(),
+                total_lines
+            ),
+        );
     }
 
-    let end_index = (start_index + limit).min(lines.len());
     let mut numbered_lines = Vec::new();
-    for (i, line) in lines[start_index..end_index].iter().enumerate() {
-        let line_number = offset + i;
-        numbered_lines.push(format!("{:>6}| {}", line_number, truncate_line(line)));
+    let mut previous = None;
+    for line_number in in_range {
+        if previous.is_some_and(|previous| line_number > previous + 1) {
+            numbered_lines.push("...".to_string());
+        }
+        numbered_lines.push(format_numbered_line(
+            line_number,
+            line_at(line_number),
+            changed,
+        ));
+        previous = Some(line_number);
     }
 
-    format_file_output(path, &numbered_lines)
+    let mut output = format_file_output(path, &numbered_lines);
+    if !out_of_range.is_empty() {
+        let listed: Vec<String> = out_of_range.iter().map(usize::to_string).collect();
+        output.push_str(&format!(
+            "NOTE: skipped line(s) {}, outside the file's {} line(s).\n",
+            listed.join(", "),
+            total_lines
+        ));
+    }
+    output
 }
 
-fn read_file_indentation(path: &Path, contents: &str, args: &ReadFileArgs) -> String {
+fn read_file_indentation(
+    path: &Path,
+    contents: &str,
+    args: &ReadFileArgs,
+    changed: Option<&BTreeSet<usize>>,
+) -> String {
     let lines: Vec<&str> = contents.lines().collect();
     if lines.is_empty() {
         return format_file_output(path, &[]);
@@ -248,6 +1213,7 @@ fn read_file_indentation(path: &Path, contents: &str, args: &ReadFileArgs) -> St
         .unwrap_or(true);
     let max_levels = indentation.and_then(|opt| opt.max_levels).unwrap_or(0);
     let max_lines = indentation.and_then(|opt| opt.max_lines);
+    let mark_anchor = indentation.and_then(|opt| opt.mark_anchor).unwrap_or(true);
 
     let anchor_index = find_non_blank_line(&lines, anchor_index);
     let base_indent = line_indent(lines[anchor_index]);
@@ -287,109 +1253,264 @@ fn read_file_indentation(path: &Path, contents: &str, args: &ReadFileArgs) -> St
     let mut numbered_lines = Vec::new();
     for (i, line) in lines[start_index..=end_index].iter().enumerate() {
         let line_number = start_index + 1 + i;
-        numbered_lines.push(format!("{:>6}| {}", line_number, truncate_line(line)));
+        let numbered = format_numbered_line(line_number, line, changed);
+        numbered_lines.push(match mark_anchor {
+            true if line_number == anchor_index + 1 => format!("> {}", numbered),
+            true => format!("  {}", numbered),
+            false => numbered,
+        });
     }
 
     format_file_output(path, &numbered_lines)
 }
 
-fn search_files(args: &SearchFilesArgs) -> String {
+fn search_files(args: &SearchFilesArgs, context: &ToolContext) -> String {
     let root = Path::new(&args.path);
-    if !root.exists() {
-        return format_tool_error(
-            "search_files",
-            &format!("Search path does not exist: {}", root.display()),
-        );
-    }
-    if !root.is_dir() {
-        return format_tool_error(
-            "search_files",
-            &format!("Search path is not a directory: {}", root.display()),
-        );
-    }
-
-    let regex = match Regex::new(&args.regex) {
-        Ok(re) => re,
-        Err(err) => return format_tool_error("search_files", &format!("Invalid regex: {}", err)),
-    };
-
-    let globset = match build_globset(args.file_pattern.as_deref()) {
+    let (regex, globset) = match prepare_search(root, &args.regex, args.file_pattern.as_deref()) {
         Ok(value) => value,
         Err(err) => return format_tool_error("search_files", &err),
     };
 
-    let mut results = Vec::new();
-    let mut total_matches = 0;
+    let pool = context.pool.as_deref();
+    if args.files_with_matches {
+        let (scanned, skipped) = scan_files(root, globset, pool, |_, text| {
+            text.lines().any(|line| regex.is_match(line))
+        });
+        let files: Vec<PathBuf> = scanned
+            .into_iter()
+            .filter_map(|(path, matched)| matched.then_some(path))
+            .collect();
+        let output = format_matching_files(root, &args.regex, args.file_pattern.as_deref(), &files);
+        return output + &skipped.footer();
+    }
 
-    let walker = WalkDir::new(root).follow_links(false).into_iter();
-    for entry in walker.filter_entry(|e| !is_ignored_dir(e.path())) {
-        let entry = match entry {
-            Ok(value) => value,
-            Err(_) => continue,
+    // Each file keeps the context of at most the limit's worth of matches,
+    // so that the results come from the same read as the counts.
+    let (scanned, skipped) = scan_files(root, globset, pool, |path, text| {
+        // `lines` already drops the `\r` of CRLF endings.
+        let total = text.lines().filter(|line| regex.is_match(line)).count();
+        let matches = match total {
+            0 => Vec::new(),
+            _ => search_matches(path, text, &regex, context.max_search_matches),
         };
+        (total, text.lines().count(), matches)
+    });
 
-        if !entry.file_type().is_file() {
+    let mut files = Vec::new();
+    let mut total_matches = 0;
+    for (path, (total, line_count, matches)) in scanned {
+        if total == 0 {
             continue;
         }
+        total_matches += total;
+        files.push(FileMatches {
+            density: total as f64 / line_count as f64,
+            changed: context
+                .search_ranking
+                .as_ref()
+                .is_some_and(|changed_files| {
+                    let normalized = path.strip_prefix(".").unwrap_or(&path);
+                    normalized
+                        .to_str()
+                        .is_some_and(|path| changed_files.contains(path))
+                }),
+            matches,
+        });
+    }
 
-        if let Some(ref set) = globset {
-            if !set.is_match(entry.path()) {
-                continue;
-            }
-        }
+    if context.search_ranking.is_some() {
+        files.sort_by(|a, b| {
+            b.changed
+                .cmp(&a.changed)
+                .then(b.density.total_cmp(&a.density))
+        });
+    }
+    let results: Vec<SearchMatch> = files
+        .into_iter()
+        .flat_map(|file| file.matches)
+        .take(context.max_search_matches)
+        .collect();
 
-        let content = match fs::read_to_string(entry.path()) {
-            Ok(value) => value,
-            Err(_) => continue,
-        };
+    let output = format_search_results(
+        root,
+        &args.regex,
+        args.file_pattern.as_deref(),
+        &results,
+        total_matches,
+    );
+    output + &skipped.footer()
+}
 
-        let lines: Vec<&str> = content.lines().collect();
-        for (index, line) in lines.iter().enumerate() {
-            if !regex.is_match(line) {
-                continue;
+/// Up to `limit` of `regex`'s matches in `text`, the contents of `path`, each
+/// with its context lines.
+fn search_matches(path: &Path, text: &str, regex: &Regex, limit: usize) -> Vec<SearchMatch> {
+    let lines: Vec<&str> = text.lines().collect();
+    lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| regex.is_match(line))
+        .take(limit)
+        .map(|(index, _)| {
+            let before = index.saturating_sub(SEARCH_CONTEXT_LINES);
+            let after = (index + SEARCH_CONTEXT_LINES + 1).min(lines.len());
+            SearchMatch {
+                path: path.to_path_buf(),
+                line_number: index + 1,
+                context_start: before + 1,
+                context: lines[before..after]
+                    .iter()
+                    .map(|line| truncate_line(line))
+                    .collect(),
             }
+        })
+        .collect()
+}
 
-            total_matches += 1;
-            if total_matches > MAX_SEARCH_MATCHES {
-                break;
-            }
+fn count_matches(args: &CountMatchesArgs, context: &ToolContext) -> String {
+    let root = Path::new(&args.path);
+    let (regex, globset) = match prepare_search(root, &args.regex, args.file_pattern.as_deref()) {
+        Ok(value) => value,
+        Err(err) => return format_tool_error("count_matches", &err),
+    };
 
-            let line_number = index + 1;
-            let before = index.saturating_sub(SEARCH_CONTEXT_LINES);
-            let after = (index + SEARCH_CONTEXT_LINES + 1).min(lines.len());
-            let context = lines[before..after]
-                .iter()
-                .enumerate()
-                .map(|(offset, line)| {
-                    let current_line = before + offset + 1;
-                    let marker = if current_line == line_number {
-                        '>'
-                    } else {
-                        ' '
-                    };
-                    format!("{} {:>6}| {}", marker, current_line, truncate_line(line))
-                })
-                .collect::<Vec<String>>();
+    let (scanned, skipped) = scan_files(root, globset, context.pool.as_deref(), |_, text| {
+        text.lines().filter(|line| regex.is_match(line)).count()
+    });
+    let counts: Vec<(PathBuf, usize)> = scanned
+        .into_iter()
+        .filter(|(_, count)| *count > 0)
+        .collect();
 
-            results.push(SearchMatch {
-                path: entry.path().to_path_buf(),
-                line_number,
-                context,
-            });
+    let mut output = format_search_header(root, &args.regex, args.file_pattern.as_deref());
+    if counts.is_empty() {
+        output.push_str("No matches found.\n");
+        return output + &skipped.footer();
+    }
+    output.push('\n');
+    for (path, count) in &counts {
+        output.push_str(&format!("{}: {}\n", path.display(), count));
+    }
+    let total: usize = counts.iter().map(|(_, count)| count).sum();
+    output.push_str(&format!(
+        "\nTOTAL: {} matches in {} files\n",
+        total,
+        counts.len()
+    ));
+    output + &skipped.footer()
+}
+
+/// Validates the search root and compiles the regex and optional glob shared
+/// by the search tools. Errors are returned as messages ready for `format_tool_error`.
+fn prepare_search(
+    root: &Path,
+    regex: &str,
+    file_pattern: Option<&str>,
+) -> Result<(Regex, Option<GlobSet>), String> {
+    if !root.exists() {
+        return Err(format!("Search path does not exist: {}", root.display()));
+    }
+    if !root.is_dir() {
+        return Err(format!(
+            "Search path is not a directory: {}",
+            root.display()
+        ));
+    }
+    let regex = Regex::new(regex).map_err(|err| format!("Invalid regex: {}", err))?;
+    let globset = build_globset(file_pattern)?;
+    Ok((regex, globset))
+}
+
+/// Walks `root` in filesystem order, skipping ignored directories and files
+/// that do not match the optional glob.
+fn searchable_files(root: &Path, globset: Option<GlobSet>) -> impl Iterator<Item = PathBuf> {
+    WalkDir::new(root)
+        .follow_links(false)
+        .into_iter()
+        .filter_entry(|e| !is_ignored_dir(e.path()))
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter(move |entry| {
+            globset
+                .as_ref()
+                .is_none_or(|set| set.is_match(entry.path()))
+        })
+        .map(|entry| entry.into_path())
+}
+
+/// Files a search walked past because they could not be read as text, so
+/// that a search finding nothing is not mistaken for one that covered them.
+#[derive(Debug, Default)]
+struct SkippedFiles {
+    binary: usize,
+    unreadable: usize,
+}
+
+impl SkippedFiles {
+    fn record(&mut self, reason: SkipReason) {
+        match reason {
+            SkipReason::Binary => self.binary += 1,
+            SkipReason::Unreadable => self.unreadable += 1,
         }
+    }
+
+    /// A closing line like `Skipped 12 files (3 binary, 9 unreadable).`, or
+    /// nothing if every file was searched.
+    fn footer(&self) -> String {
+        let total = self.binary + self.unreadable;
+        if total == 0 {
+            return String::new();
+        }
+        let reasons: Vec<String> = [(self.binary, "binary"), (self.unreadable, "unreadable")]
+            .into_iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, reason)| format!("{} {}", count, reason))
+            .collect();
+        let noun = if total == 1 { "file" } else { "files" };
+        format!("\nSkipped {} {} ({}).\n", total, noun, reasons.join(", "))
+    }
+}
+
+/// Reads the files `searchable_files` yields and runs `scan` on each text,
+/// with its BOM stripped, across `pool`'s threads (or on this one). Results
+/// come back in walk order, so output does not depend on scheduling.
+fn scan_files<T: Send>(
+    root: &Path,
+    globset: Option<GlobSet>,
+    pool: Option<&ThreadPool>,
+    scan: impl Fn(&Path, &str) -> T + Sync,
+) -> (Vec<(PathBuf, T)>, SkippedFiles) {
+    let paths: Vec<PathBuf> = searchable_files(root, globset).collect();
+    let scan_one =
+        |path: &PathBuf| read_searchable(path).map(|content| scan(path, strip_bom(&content)));
+    let scanned: Vec<Result<T, SkipReason>> = match pool {
+        Some(pool) => pool.install(|| paths.par_iter().map(scan_one).collect()),
+        None => paths.iter().map(scan_one).collect(),
+    };
 
-        if total_matches >= MAX_SEARCH_MATCHES {
-            break;
+    let mut skipped = SkippedFiles::default();
+    let mut results = Vec::new();
+    for (path, outcome) in paths.into_iter().zip(scanned) {
+        match outcome {
+            Ok(value) => results.push((path, value)),
+            Err(reason) => skipped.record(reason),
         }
     }
+    (results, skipped)
+}
 
-    format_search_results(
-        root,
-        &args.regex,
-        args.file_pattern.as_deref(),
-        &results,
-        total_matches,
-    )
+/// Why a search could not read a file as text.
+enum SkipReason {
+    /// Not valid UTF-8, e.g. images or compiled artifacts.
+    Binary,
+    /// Any other read error, e.g. permission denied.
+    Unreadable,
+}
+
+fn read_searchable(path: &Path) -> Result<String, SkipReason> {
+    fs::read_to_string(path).map_err(|err| match err.kind() {
+        ErrorKind::InvalidData => SkipReason::Binary,
+        _ => SkipReason::Unreadable,
+    })
 }
 
 fn format_file_output(path: &Path, lines: &[String]) -> String {
@@ -405,6 +1526,36 @@ fn format_file_output(path: &Path, lines: &[String]) -> String {
     output
 }
 
+fn format_search_header(root: &Path, regex: &str, file_pattern: Option<&str>) -> String {
+    let mut output = String::new();
+    output.push_str(&format!("SEARCH ROOT: {}\n", root.display()));
+    output.push_str(&format!("REGEX: {}\n", regex));
+    if let Some(pattern) = file_pattern {
+        output.push_str(&format!("FILE_PATTERN: {}\n", pattern));
+    }
+    output
+}
+
+fn format_matching_files(
+    root: &Path,
+    regex: &str,
+    file_pattern: Option<&str>,
+    files: &[PathBuf],
+) -> String {
+    let mut output = format_search_header(root, regex, file_pattern);
+    if files.is_empty() {
+        output.push_str("No matches found.\n");
+        return output;
+    }
+    output.push('\n');
+    for path in files {
+        output.push_str(&format!("{}\n", path.display()));
+    }
+    let noun = if files.len() == 1 { "file" } else { "files" };
+    output.push_str(&format!("\n{} {} with matches\n", files.len(), noun));
+    output
+}
+
 fn format_search_results(
     root: &Path,
     regex: &str,
@@ -412,44 +1563,110 @@ fn format_search_results(
     results: &[SearchMatch],
     total_matches: usize,
 ) -> String {
-    let mut output = String::new();
-    output.push_str(&format!("SEARCH ROOT: {}\n", root.display()));
-    output.push_str(&format!("REGEX: {}\n", regex));
-    if let Some(pattern) = file_pattern {
-        output.push_str(&format!("FILE_PATTERN: {}\n", pattern));
-    }
+    let mut output = format_search_header(root, regex, file_pattern);
 
     if results.is_empty() {
         output.push_str("No matches found.\n");
         return output;
     }
 
-    for match_result in results {
+    for block in merge_search_matches(results) {
+        let line_numbers: Vec<String> = block.line_numbers.iter().map(usize::to_string).collect();
         output.push_str(&format!(
             "\n{}:{}\n",
-            match_result.path.display(),
-            match_result.line_number
+            block.path.display(),
+            line_numbers.join(",")
         ));
-        for line in &match_result.context {
-            output.push_str(line);
-            output.push('\n');
+        for (offset, line) in block.lines.iter().enumerate() {
+            let current_line = block.start + offset;
+            let marker = if block.line_numbers.contains(&current_line) {
+                '>'
+            } else {
+                ' '
+            };
+            output.push_str(&format!("{} L{}: {}\n", marker, current_line, line));
         }
     }
 
-    if total_matches >= MAX_SEARCH_MATCHES {
-        output.push_str("\nMatches truncated at limit.\n");
+    if total_matches > results.len() {
+        output.push_str(&format!(
+            "\nMatches truncated at limit: showing {} of {} matches ({} omitted).\n",
+            results.len(),
+            total_matches,
+            total_matches - results.len()
+        ));
     }
 
     output
 }
 
+/// Formats one `read_file` line as `L42: content`, the same numbering
+/// `search_files` uses, so the model has a single citation convention. Files
+/// touched by the diff get a margin marking the lines it added with `+`.
+fn format_numbered_line(
+    line_number: usize,
+    line: &str,
+    changed: Option<&BTreeSet<usize>>,
+) -> String {
+    match changed {
+        Some(changed) => {
+            let marker = if changed.contains(&line_number) {
+                '+'
+            } else {
+                ' '
+            };
+            format!("{} L{}: {}", marker, line_number, truncate_line(line))
+        }
+        None => format!("L{}: {}", line_number, truncate_line(line)),
+    }
+}
+
+/// Lines `line - context..=line + context` of `path`, numbered like
+/// `search_files` output with `line` itself marked `>`. `None` if the file
+/// cannot be read or has fewer than `line` lines.
+pub fn format_snippet(contents: &str, line: usize, context: usize) -> Option<String> {
+    let lines: Vec<&str> = contents.lines().collect();
+    if line == 0 || line > lines.len() {
+        return None;
+    }
+    let start = line.saturating_sub(context).max(1);
+    let end = (line + context).min(lines.len());
+    let mut output = String::new();
+    for (line_number, text) in (start..=end).zip(&lines[start - 1..end]) {
+        let marker = if line_number == line { '>' } else { ' ' };
+        output.push_str(&format!(
+            "{} L{}: {}\n",
+            marker,
+            line_number,
+            truncate_line(text)
+        ));
+    }
+    Some(output)
+}
+
+/// Drops a UTF-8 byte order mark, which would otherwise show up at the
+/// start of the first line.
+fn strip_bom(content: &str) -> &str {
+    content.strip_prefix('\u{feff}').unwrap_or(content)
+}
+
+/// Cuts `line` to `MAX_LINE_LENGTH` columns and marks the cut with "...".
+/// Wide characters (e.g. CJK) take two columns and combining marks none, and
+/// a grapheme cluster (a letter with its accents, an emoji sequence) is never
+/// split. Lines over `MAX_LINE_BYTES` are cut there even if narrower.
 fn truncate_line(line: &str) -> String {
+    // No character is wider than its UTF-8 encoding is long.
     if line.len() <= MAX_LINE_LENGTH {
         return line.to_string();
     }
-    let mut truncated = line.chars().take(MAX_LINE_LENGTH).collect::<String>();
-    truncated.push_str("...");
-    truncated
+    let mut width = 0;
+    for (offset, grapheme) in line.grapheme_indices(true) {
+        width += grapheme.width();
+        if width > MAX_LINE_LENGTH || offset + grapheme.len() > MAX_LINE_BYTES {
+            return format!("{}...", &line[..offset]);
+        }
+    }
+    line.to_string()
 }
 
 fn find_non_blank_line(lines: &[&str], index: usize) -> usize {
@@ -599,6 +1816,12 @@ fn format_tool_error(tool: &str, message: &str) -> String {
     format!("ERROR ({tool}): {message}\n")
 }
 
+/// Whether `output` is an error produced by `format_tool_error` rather than
+/// a tool result.
+pub fn is_tool_error(output: &str) -> bool {
+    output.starts_with("ERROR (")
+}
+
 fn build_globset(pattern: Option<&str>) -> Result<Option<GlobSet>, String> {
     let Some(pattern) = pattern else {
         return Ok(None);
@@ -622,18 +1845,102 @@ fn is_ignored_dir(path: &Path) -> bool {
     name == ".git" || name == "target"
 }
 
+/// A file with `search_files` matches, with what ranking orders files by.
+struct FileMatches {
+    changed: bool,
+    /// Matching lines per line of the file.
+    density: f64,
+    /// The first of its matches, up to the search's limit.
+    matches: Vec<SearchMatch>,
+}
+
 struct SearchMatch {
     path: PathBuf,
     line_number: usize,
+    /// Line number of `context[0]`.
+    context_start: usize,
     context: Vec<String>,
 }
 
+/// Consecutive matches in one file whose context windows overlap or touch,
+/// shown as one block so no line is repeated.
+struct SearchBlock<'a> {
+    path: &'a Path,
+    line_numbers: Vec<usize>,
+    /// Line number of `lines[0]`.
+    start: usize,
+    lines: Vec<&'a str>,
+}
+
+fn merge_search_matches(results: &[SearchMatch]) -> Vec<SearchBlock<'_>> {
+    let mut blocks: Vec<SearchBlock> = Vec::new();
+    for result in results {
+        if let Some(block) = blocks.last_mut()
+            && block.path == result.path
+            && result.context_start <= block.start + block.lines.len()
+        {
+            let overlap = block.start + block.lines.len() - result.context_start;
+            block.line_numbers.push(result.line_number);
+            block
+                .lines
+                .extend(result.context.iter().skip(overlap).map(String::as_str));
+            continue;
+        }
+        blocks.push(SearchBlock {
+            path: &result.path,
+            line_numbers: vec![result.line_number],
+            start: result.context_start,
+            lines: result.context.iter().map(String::as_str).collect(),
+        });
+    }
+    blocks
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::io::Write;
     use tempfile::tempdir;
 
+    #[test]
+    fn unknown_tools_are_answered_with_the_available_ones() {
+        let context = ToolContext::default();
+        assert_eq!(
+            handle_tool_call("open_file", "{}", &context),
+            format_tool_error(
+                "open_file",
+                "Unknown tool name. The available tools are: read_file, read_symbol, search_files, count_matches, commit_diff"
+            )
+        );
+        assert!(is_available_tool("read_file", &context));
+        assert!(!is_available_tool("get_diff", &context));
+        assert!(is_available_tool(
+            "get_diff",
+            &ToolContext::default().with_diff(String::new())
+        ));
+    }
+
+    #[test]
+    fn truncate_line_counts_columns_and_keeps_graphemes_whole() {
+        let short_wide = "漢".repeat(MAX_LINE_LENGTH / 2);
+        assert_eq!(truncate_line(&short_wide), short_wide);
+        let long_wide = format!("{}字", short_wide);
+        assert_eq!(truncate_line(&long_wide), format!("{}...", short_wide));
+
+        // The accent on the last letter is a separate, zero-width character.
+        let accented = format!("{}e\u{301}", "a".repeat(MAX_LINE_LENGTH - 1));
+        assert_eq!(truncate_line(&accented), accented);
+        let too_long = format!("{}ab", accented);
+        assert_eq!(truncate_line(&too_long), format!("{}...", accented));
+
+        // Zero-width spaces take no columns but three bytes each.
+        let invisible = "\u{200b}".repeat(MAX_LINE_BYTES);
+        let kept = "\u{200b}".repeat(MAX_LINE_BYTES / 3);
+        assert_eq!(truncate_line(&invisible), format!("{}...", kept));
+        let zalgo = format!("a{}", "\u{301}".repeat(MAX_LINE_BYTES));
+        assert_eq!(truncate_line(&zalgo), "...");
+    }
+
     #[test]
     fn read_file_respects_offset_and_limit() {
         let dir = tempdir().expect("tempdir");
@@ -643,16 +1950,290 @@ mod tests {
         writeln!(file, "second").unwrap();
         writeln!(file, "third").unwrap();
 
-        let output = read_file(&ReadFileArgs {
+        let output = read_file(
+            &ReadFileArgs {
+                path: file_path.to_string_lossy().to_string(),
+                mode: None,
+                offset: Some(2),
+                limit: Some(1),
+                lines: None,
+                indentation: None,
+                max_chars: None,
+            },
+            &ToolContext::default(),
+        );
+
+        assert!(output.contains("L2: second"));
+        assert!(!output.contains("L1: first"));
+    }
+
+    #[test]
+    fn read_file_explains_out_of_range_offsets_and_clamped_limits() {
+        let dir = tempdir().expect("tempdir");
+        let file_path = dir.path().join("sample.txt");
+        fs::write(&file_path, "line\n".repeat(MAX_READ_LIMIT + 5)).unwrap();
+        let read = |offset: usize, limit: usize| {
+            read_file(
+                &ReadFileArgs {
+                    path: file_path.to_string_lossy().to_string(),
+                    mode: None,
+                    offset: Some(offset),
+                    limit: Some(limit),
+                    lines: None,
+                    indentation: None,
+                    max_chars: Some(usize::MAX),
+                },
+                &ToolContext::default(),
+            )
+        };
+
+        let past_end = read(MAX_READ_LIMIT + 10, 5);
+        assert!(is_tool_error(&past_end));
+        assert!(past_end.contains(&format!(
+            "which has {} line(s); use an offset from 1 to {}",
+            MAX_READ_LIMIT + 5,
+            MAX_READ_LIMIT + 5
+        )));
+
+        let clamped = read(1, MAX_READ_LIMIT + 100);
+        assert!(clamped.contains(&format!(
+            "NOTE: limit {} exceeds the maximum of {}; showed lines 1-{} of {}. Continue with offset {}.",
+            MAX_READ_LIMIT + 100,
+            MAX_READ_LIMIT,
+            MAX_READ_LIMIT,
+            MAX_READ_LIMIT + 5,
+            MAX_READ_LIMIT + 1
+        )));
+        assert!(!read(MAX_READ_LIMIT, MAX_READ_LIMIT + 100).contains("NOTE"));
+    }
+
+    #[test]
+    fn format_snippet_marks_the_requested_line() {
+        let contents = "one\ntwo\nthree\nfour\n";
+
+        let snippet = format_snippet(contents, 1, 1).unwrap();
+        assert_eq!(snippet, "> L1: one\n  L2: two\n");
+        let snippet = format_snippet(contents, 4, 2).unwrap();
+        assert_eq!(snippet, "  L2: two\n  L3: three\n> L4: four\n");
+        assert_eq!(format_snippet(contents, 5, 1), None);
+        assert_eq!(format_snippet(contents, 0, 1), None);
+    }
+
+    #[test]
+    fn only_transient_read_errors_are_retried() {
+        assert!(is_transient(ErrorKind::Interrupted));
+        assert!(is_transient(ErrorKind::TimedOut));
+        assert!(!is_transient(ErrorKind::NotFound));
+        assert!(!is_transient(ErrorKind::PermissionDenied));
+    }
+
+    #[test]
+    fn resolve_missing_path_matches_whole_trailing_components() {
+        let dir = tempdir().expect("tempdir");
+        fs::create_dir_all(dir.path().join("src/web")).unwrap();
+        fs::create_dir_all(dir.path().join("tests")).unwrap();
+        fs::write(dir.path().join("src/web/app.ts"), "").unwrap();
+        fs::write(dir.path().join("src/web/myapp.ts"), "").unwrap();
+        fs::write(dir.path().join("src/mod.rs"), "").unwrap();
+        fs::write(dir.path().join("tests/mod.rs"), "").unwrap();
+
+        assert_eq!(
+            resolve_missing_path(dir.path(), Path::new("web/app.ts")),
+            PathResolution::Unique(dir.path().join("src/web/app.ts"))
+        );
+        assert_eq!(
+            resolve_missing_path(dir.path(), Path::new("mod.rs")),
+            PathResolution::Ambiguous(vec![
+                dir.path().join("src/mod.rs"),
+                dir.path().join("tests/mod.rs"),
+            ])
+        );
+        assert_eq!(
+            resolve_missing_path(dir.path(), Path::new("pp.ts")),
+            PathResolution::NotFound
+        );
+    }
+
+    #[test]
+    fn read_file_returns_requested_lines_with_gaps_marked() {
+        let dir = tempdir().expect("tempdir");
+        let file_path = dir.path().join("sample.txt");
+        fs::write(&file_path, "one\ntwo\nthree\nfour\nfive\n").unwrap();
+        let read = |lines: Vec<usize>| {
+            read_file(
+                &ReadFileArgs {
+                    path: file_path.to_string_lossy().to_string(),
+                    mode: None,
+                    offset: None,
+                    limit: None,
+                    lines: Some(lines),
+                    indentation: None,
+                    max_chars: None,
+                },
+                &ToolContext::default(),
+            )
+        };
+
+        assert_eq!(
+            read(vec![5, 1, 2, 1, 9, 0]),
+            format!(
+                "FILE: {}\nL1: one\nL2: two\n...\nL5: five\n\
+                 NOTE: skipped line(s) 0, 9, outside the file's 5 line(s).\n",
+                file_path.display()
+            )
+        );
+        let past_end = read(vec![6, 7]);
+        assert!(is_tool_error(&past_end));
+        assert!(past_end.contains("which has 5 line(s)"));
+    }
+
+    #[test]
+    fn read_large_file_streams_what_a_whole_read_returns() {
+        let dir = tempdir().expect("tempdir");
+        let file_path = dir.path().join("big.txt");
+        let long = "x".repeat(MAX_LINE_BYTES + 10);
+        let contents = format!(
+            "first\r\nzweite Zeile ü\n\n{}\n{}\nlast",
+            long,
+            "line\n".repeat(2100)
+        );
+        fs::write(&file_path, &contents).unwrap();
+        let read_args = |offset, limit, lines: Option<Vec<usize>>, max_chars| ReadFileArgs {
             path: file_path.to_string_lossy().to_string(),
             mode: None,
-            offset: Some(2),
-            limit: Some(1),
+            offset,
+            limit,
+            lines,
             indentation: None,
-        });
+            max_chars,
+        };
+
+        for args in [
+            read_args(None, None, None, None),
+            read_args(Some(2), Some(3), None, None),
+            read_args(Some(100), Some(5000), None, None),
+            read_args(Some(9999), None, None, None),
+            read_args(None, None, Some(vec![4, 2, 2106, 3000]), None),
+            read_args(None, None, Some(vec![3000]), None),
+            read_args(Some(3), Some(10), None, Some(100)),
+        ] {
+            assert_eq!(
+                read_large_file(&file_path, &args, None),
+                read_whole_file(&file_path, &contents, &args, None)
+            );
+        }
+
+        let indentation = ReadFileArgs {
+            mode: Some("indentation".to_string()),
+            ..read_args(None, None, None, None)
+        };
+        assert!(
+            read_large_file(&file_path, &indentation, None)
+                .contains("too large for indentation mode")
+        );
+    }
+
+    #[test]
+    fn read_file_caps_output_at_max_chars() {
+        let dir = tempdir().expect("tempdir");
+        let file_path = dir.path().join("big.txt");
+        fs::write(&file_path, "alpha\nbravo\ncharlie\n").unwrap();
+
+        let output = read_file(
+            &ReadFileArgs {
+                path: file_path.to_string_lossy().to_string(),
+                mode: None,
+                offset: None,
+                limit: None,
+                lines: None,
+                indentation: None,
+                max_chars: Some(format!("FILE: {}\n", file_path.display()).len() + 12),
+            },
+            &ToolContext::default(),
+        );
+
+        assert!(output.contains("L1: alpha"));
+        assert!(!output.contains("bravo"));
+        assert!(output.contains("the file has 3 lines and 20 characters"));
+    }
+
+    #[test]
+    fn read_file_marks_lines_added_in_the_diff() {
+        let dir = tempdir().expect("tempdir");
+        let file_path = dir.path().join("changed.rs");
+        let mut file = fs::File::create(&file_path).expect("create file");
+        writeln!(file, "old").unwrap();
+        writeln!(file, "new").unwrap();
+
+        let path = file_path.to_string_lossy().to_string();
+        let mut changed_lines = ChangedLines::new();
+        changed_lines.insert(path.clone(), BTreeSet::from([2]));
+        let context = ToolContext::default().with_changed_lines(changed_lines);
+
+        let output = read_file(
+            &ReadFileArgs {
+                path,
+                mode: None,
+                offset: None,
+                limit: None,
+                lines: None,
+                indentation: None,
+                max_chars: None,
+            },
+            &context,
+        );
+
+        assert!(output.contains("NOTE: lines marked '+'"));
+        assert!(output.contains("  L1: old"));
+        assert!(output.contains("+ L2: new"));
+    }
 
-        assert!(output.contains("2| second"));
-        assert!(!output.contains("1| first"));
+    #[test]
+    fn get_diff_returns_the_whole_diff_or_one_file() {
+        let diff = "\
+diff --git a/a.rs b/a.rs
+--- a/a.rs
++++ b/a.rs
+@@ -1 +1 @@
+-old
++new
+diff --git a/gone.rs b/gone.rs
+deleted file mode 100644
+--- a/gone.rs
++++ /dev/null
+@@ -1 +0,0 @@
+-bye
+";
+        let context = ToolContext::default().with_diff(diff.to_string());
+        let call = |arguments: &str| handle_tool_call("get_diff", arguments, &context);
+
+        assert_eq!(call("{}"), format!("DIFF:\n{}", diff));
+        let one = call(r#"{"path": "gone.rs"}"#);
+        assert!(one.starts_with("DIFF (gone.rs):\ndiff --git a/gone.rs b/gone.rs\n"));
+        assert!(!one.contains("+new"));
+        assert!(
+            call(r#"{"path": "other.rs"}"#)
+                .starts_with("ERROR (get_diff): other.rs is not changed")
+        );
+        assert!(handle_tool_call("get_diff", "{}", &ToolContext::default()).starts_with("ERROR"));
+    }
+
+    #[test]
+    fn commit_diff_caps_output_and_rejects_options() {
+        let output = "commit abc\n\n a.rs | 2 +-\n\n-old\n+new\n".to_string();
+        assert_eq!(cap_commit_diff(output.clone(), 100), output);
+        assert_eq!(
+            cap_commit_diff(output, 30),
+            "commit abc\n\n a.rs | 2 +-\n\n[output truncated at 30 of 36 characters; the stat above lists every file the commit touched]\n"
+        );
+
+        let call =
+            |arguments: &str| handle_tool_call("commit_diff", arguments, &ToolContext::default());
+        assert_eq!(
+            call(r#"{"ref": "--output=/tmp/x"}"#),
+            format_tool_error("commit_diff", "`--output=/tmp/x` is not a commit reference")
+        );
+        assert!(call("{}").starts_with("ERROR (commit_diff): Invalid arguments"));
     }
 
     #[test]
@@ -662,14 +2243,308 @@ mod tests {
         let mut file = fs::File::create(&file_path).expect("create file");
         writeln!(file, "fn target() {{}}").unwrap();
 
-        let output = search_files(&SearchFilesArgs {
-            path: dir.path().to_string_lossy().to_string(),
-            regex: "target".to_string(),
-            file_pattern: Some("*.rs".to_string()),
-        });
+        let output = search_files(
+            &SearchFilesArgs {
+                path: dir.path().to_string_lossy().to_string(),
+                regex: "target".to_string(),
+                file_pattern: Some("*.rs".to_string()),
+                files_with_matches: false,
+            },
+            &ToolContext::default(),
+        );
 
         assert!(output.contains("lib.rs"));
-        assert!(output.contains("target"));
+        assert!(output.contains("> L1: fn target() {}"));
+    }
+
+    #[test]
+    fn search_files_counts_the_files_it_could_not_read() {
+        let dir = tempdir().expect("tempdir");
+        fs::write(dir.path().join("logo.png"), [0x89, b'P', b'N', b'G', 0xff]).unwrap();
+        let search = |files_with_matches| {
+            search_files(
+                &SearchFilesArgs {
+                    path: dir.path().to_string_lossy().to_string(),
+                    regex: "PNG".to_string(),
+                    file_pattern: None,
+                    files_with_matches,
+                },
+                &ToolContext::default(),
+            )
+        };
+
+        assert!(search(false).ends_with("No matches found.\n\nSkipped 1 file (1 binary).\n"));
+        assert!(search(true).ends_with("\nSkipped 1 file (1 binary).\n"));
+        let skipped = SkippedFiles {
+            binary: 3,
+            unreadable: 9,
+        };
+        assert_eq!(
+            skipped.footer(),
+            "\nSkipped 12 files (3 binary, 9 unreadable).\n"
+        );
+        assert_eq!(SkippedFiles::default().footer(), "");
+    }
+
+    #[test]
+    fn search_files_reports_how_many_matches_were_truncated() {
+        let dir = tempdir().expect("tempdir");
+        let mut file = fs::File::create(dir.path().join("lib.rs")).expect("create file");
+        for _ in 0..5 {
+            writeln!(file, "hit").unwrap();
+        }
+
+        let output = search_files(
+            &SearchFilesArgs {
+                path: dir.path().to_string_lossy().to_string(),
+                regex: "hit".to_string(),
+                file_pattern: None,
+                files_with_matches: false,
+            },
+            &ToolContext::new(2),
+        );
+
+        assert!(output.contains("lib.rs:1,2\n"));
+        assert!(!output.contains("> L3"));
+        assert!(output.contains("showing 2 of 5 matches (3 omitted)"));
+    }
+
+    #[test]
+    fn search_files_output_is_stable_across_parallel_scans() {
+        let dir = tempdir().expect("tempdir");
+        for i in 0..40 {
+            fs::write(dir.path().join(format!("f{}.rs", i)), "hit\nmiss\nhit\n").unwrap();
+        }
+        let search = |context: &ToolContext| {
+            search_files(
+                &SearchFilesArgs {
+                    path: dir.path().to_string_lossy().to_string(),
+                    regex: "hit".to_string(),
+                    file_pattern: None,
+                    files_with_matches: false,
+                },
+                context,
+            )
+        };
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .unwrap();
+        let parallel = ToolContext::new(5).with_pool(pool);
+
+        let output = search(&ToolContext::new(5));
+        assert!(output.contains("showing 5 of 80 matches (75 omitted)"));
+        for _ in 0..5 {
+            assert_eq!(search(&parallel), output);
+        }
+    }
+
+    #[test]
+    fn search_files_merges_overlapping_context() {
+        let dir = tempdir().expect("tempdir");
+        fs::write(dir.path().join("lib.rs"), "hit\nx\nhit\nx\nx\nx\nhit\n").unwrap();
+
+        let output = search_files(
+            &SearchFilesArgs {
+                path: dir.path().to_string_lossy().to_string(),
+                regex: "hit".to_string(),
+                file_pattern: None,
+                files_with_matches: false,
+            },
+            &ToolContext::default(),
+        );
+
+        assert!(output.contains("lib.rs:1,3\n> L1: hit\n  L2: x\n> L3: hit\n  L4: x\n\n"));
+        assert!(output.contains("lib.rs:7\n  L6: x\n> L7: hit\n"));
+        assert_eq!(output.matches("L2: x").count(), 1);
+    }
+
+    #[test]
+    fn search_files_ranks_changed_then_dense_files_first() {
+        let dir = tempdir().expect("tempdir");
+        let write = |name: &str, contents: &str| {
+            let path = dir.path().join(name);
+            fs::write(&path, contents).unwrap();
+            path.to_string_lossy().to_string()
+        };
+        write("a_sparse.rs", "hit\nx\nx\nx\n");
+        write("b_dense.rs", "hit\nhit\n");
+        let changed = write("c_changed.rs", "x\nx\nx\nhit\n");
+        let args = SearchFilesArgs {
+            path: dir.path().to_string_lossy().to_string(),
+            regex: "hit".to_string(),
+            file_pattern: None,
+            files_with_matches: false,
+        };
+
+        let output = search_files(
+            &args,
+            &ToolContext::new(3).with_search_ranking(std::slice::from_ref(&changed)),
+        );
+        let changed_at = output.find("c_changed.rs:4").expect("changed file first");
+        let dense_at = output.find("b_dense.rs:1,2").expect("dense file next");
+        assert!(changed_at < dense_at);
+        assert!(!output.contains("a_sparse.rs"));
+        assert!(output.contains("showing 3 of 4 matches (1 omitted)"));
+    }
+
+    #[test]
+    fn search_files_strips_bom_and_carriage_returns() {
+        let dir = tempdir().expect("tempdir");
+        fs::write(
+            dir.path().join("win.rs"),
+            "\u{feff}fn target() {}\r\nnext\r\n",
+        )
+        .unwrap();
+
+        let output = search_files(
+            &SearchFilesArgs {
+                path: dir.path().to_string_lossy().to_string(),
+                regex: "^fn target".to_string(),
+                file_pattern: None,
+                files_with_matches: false,
+            },
+            &ToolContext::default(),
+        );
+        assert!(output.contains("> L1: fn target() {}\n"));
+        assert!(output.contains("  L2: next\n"));
+        assert!(!output.contains('\r'));
+    }
+
+    #[test]
+    fn search_files_can_return_only_matching_paths() {
+        let dir = tempdir().expect("tempdir");
+        fs::write(dir.path().join("a.rs"), "use foo;\nfoo();\nfoo();\n").unwrap();
+        fs::write(dir.path().join("b.rs"), "nothing here\n").unwrap();
+
+        let output = search_files(
+            &SearchFilesArgs {
+                path: dir.path().to_string_lossy().to_string(),
+                regex: "foo".to_string(),
+                file_pattern: None,
+                files_with_matches: true,
+            },
+            &ToolContext::new(1),
+        );
+
+        assert!(output.contains("a.rs\n"));
+        assert!(!output.contains("b.rs"));
+        assert!(!output.contains("foo();"));
+        assert!(!output.contains("truncated"));
+        assert!(output.contains("1 file with matches"));
+    }
+
+    #[test]
+    fn inspection_for_call_extracts_targets() {
+        assert_eq!(
+            inspection_for_call("read_file", r#"{"path":"src/main.rs"}"#),
+            Some(Inspection::File("src/main.rs".to_string()))
+        );
+        assert_eq!(
+            inspection_for_call("count_matches", r#"{"path":"src","regex":"foo"}"#),
+            Some(Inspection::Search {
+                path: "src".to_string(),
+                regex: "foo".to_string()
+            })
+        );
+        assert_eq!(inspection_for_call("read_file", "not json"), None);
+        assert_eq!(inspection_for_call("bogus", "{}"), None);
+    }
+
+    #[test]
+    fn count_matches_reports_per_file_and_total_counts() {
+        let dir = tempdir().expect("tempdir");
+        let mut first = fs::File::create(dir.path().join("a.rs")).expect("create file");
+        writeln!(first, "old_api();").unwrap();
+        writeln!(first, "old_api();").unwrap();
+        let mut second = fs::File::create(dir.path().join("b.rs")).expect("create file");
+        writeln!(second, "new_api();").unwrap();
+
+        let output = count_matches(
+            &CountMatchesArgs {
+                path: dir.path().to_string_lossy().to_string(),
+                regex: "old_api".to_string(),
+                file_pattern: None,
+            },
+            &ToolContext::default(),
+        );
+
+        assert!(output.contains("a.rs: 2"));
+        assert!(!output.contains("b.rs"));
+        assert!(output.contains("TOTAL: 2 matches in 1 files"));
+        assert!(!output.contains("old_api();"));
+    }
+
+    #[test]
+    fn read_symbol_returns_the_whole_definition() {
+        let dir = tempdir().expect("tempdir");
+        let file_path = dir.path().join("sample.rs");
+        fs::write(
+            &file_path,
+            "fn before() {}\n\nfn target(\n    x: u32,\n) -> u32 {\n    x + 1\n}\n\nfn after() {}\n",
+        )
+        .unwrap();
+
+        let output = read_symbol(
+            &ReadSymbolArgs {
+                path: file_path.to_string_lossy().to_string(),
+                symbol: "target".to_string(),
+            },
+            &ToolContext::default(),
+        );
+
+        assert!(output.contains("L3: fn target("));
+        assert!(output.contains("L6:     x + 1"));
+        assert!(output.contains("L7: }"));
+        assert!(!output.contains("before"));
+        assert!(!output.contains("after"));
+    }
+
+    #[test]
+    fn read_symbol_uses_python_keywords_and_indentation() {
+        let dir = tempdir().expect("tempdir");
+        let file_path = dir.path().join("sample.py");
+        fs::write(
+            &file_path,
+            "class Thing:\n    def run(self):\n        return 1\n\n    def stop(self):\n        pass\n",
+        )
+        .unwrap();
+
+        let output = read_symbol(
+            &ReadSymbolArgs {
+                path: file_path.to_string_lossy().to_string(),
+                symbol: "run".to_string(),
+            },
+            &ToolContext::default(),
+        );
+
+        assert!(output.contains("L2:     def run(self):"));
+        assert!(output.contains("L3:         return 1"));
+        assert!(!output.contains("stop"));
+    }
+
+    #[test]
+    fn read_symbol_reports_missing_and_ambiguous_symbols() {
+        let dir = tempdir().expect("tempdir");
+        let file_path = dir.path().join("sample.rs");
+        fs::write(
+            &file_path,
+            "mod a {\n    fn dup() {}\n}\nmod b {\n    fn dup() {}\n}\n",
+        )
+        .unwrap();
+        let args = |symbol: &str| ReadSymbolArgs {
+            path: file_path.to_string_lossy().to_string(),
+            symbol: symbol.to_string(),
+        };
+
+        let missing = read_symbol(&args("absent"), &ToolContext::default());
+        assert!(missing.starts_with("ERROR (read_symbol): No definition of `absent`"));
+        assert!(is_tool_error(&missing));
+
+        let ambiguous = read_symbol(&args("dup"), &ToolContext::default());
+        assert!(ambiguous.contains("defined 2 times"));
+        assert!(ambiguous.contains("line 2: fn dup() {}"));
+        assert!(ambiguous.contains("line 5: fn dup() {}"));
     }
 
     #[test]
@@ -682,22 +2557,28 @@ mod tests {
         writeln!(file, "    println!(\"hi\");").unwrap();
         writeln!(file, "}}").unwrap();
 
-        let output = read_file(&ReadFileArgs {
-            path: file_path.to_string_lossy().to_string(),
-            mode: Some("indentation".to_string()),
-            offset: None,
-            limit: None,
-            indentation: Some(IndentationOptions {
-                anchor_line: Some(2),
-                max_levels: None,
-                include_siblings: None,
-                include_header: Some(false),
-                max_lines: None,
-            }),
-        });
+        let output = read_file(
+            &ReadFileArgs {
+                path: file_path.to_string_lossy().to_string(),
+                mode: Some("indentation".to_string()),
+                offset: None,
+                limit: None,
+                lines: None,
+                indentation: Some(IndentationOptions {
+                    anchor_line: Some(2),
+                    max_levels: None,
+                    include_siblings: None,
+                    include_header: Some(false),
+                    max_lines: None,
+                    mark_anchor: None,
+                }),
+                max_chars: None,
+            },
+            &ToolContext::default(),
+        );
 
-        assert!(output.contains("2|     let x = 1;"));
-        assert!(output.contains("3|     println!(\"hi\");"));
-        assert!(!output.contains("1| fn outer()"));
+        assert!(output.contains("\n> L2:     let x = 1;\n"));
+        assert!(output.contains("\n  L3:     println!(\"hi\");\n"));
+        assert!(!output.contains("L1: fn outer()"));
     }
 }
@@ -0,0 +1,161 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Entries listed per directory besides changed files and directories on the
+/// way to one; the rest are counted.
+const MAX_OTHER_ENTRIES: usize = 30;
+/// Skipped like in the search tools.
+const IGNORED_DIRS: &[&str] = &[".git", "target"];
+
+/// The `--directory-tree` section: the directories leading to each changed
+/// file, with the full contents (siblings) of the directories that hold one.
+/// Directories that only lead to a change show just that path. Paths are
+/// relative to the current directory. Returns `None` if nothing is listed.
+pub fn changed_tree(files_changed: &[String]) -> Option<String> {
+    render_tree(Path::new("."), files_changed)
+}
+
+fn render_tree(root: &Path, files_changed: &[String]) -> Option<String> {
+    let changed: BTreeSet<PathBuf> = files_changed.iter().map(PathBuf::from).collect();
+    let parents: BTreeSet<PathBuf> = changed
+        .iter()
+        .filter_map(|file| file.parent().map(Path::to_path_buf))
+        .collect();
+    let on_path: BTreeSet<PathBuf> = parents
+        .iter()
+        .flat_map(|dir| dir.ancestors().map(Path::to_path_buf))
+        .collect();
+    let tree = Tree {
+        root,
+        changed,
+        parents,
+        on_path,
+    };
+    let mut output = String::new();
+    tree.render_dir(Path::new(""), 0, &mut output);
+    (!output.is_empty()).then_some(output)
+}
+
+struct Tree<'a> {
+    root: &'a Path,
+    changed: BTreeSet<PathBuf>,
+    /// Directories holding a changed file; all their entries are listed.
+    parents: BTreeSet<PathBuf>,
+    /// Every directory from the root down to a changed file; these are expanded.
+    on_path: BTreeSet<PathBuf>,
+}
+
+impl Tree<'_> {
+    fn render_dir(&self, dir: &Path, depth: usize, output: &mut String) {
+        let mut entries: Vec<(PathBuf, bool)> = if self.parents.contains(dir) {
+            self.read_entries(dir)
+        } else {
+            Vec::new()
+        };
+        // Changed files that no longer exist, and directories on the way to a
+        // change, are listed even if the directory could not be read.
+        for file in &self.changed {
+            if file.parent() == Some(dir) && !entries.iter().any(|(path, _)| path == file) {
+                entries.push((file.clone(), false));
+            }
+        }
+        for path in &self.on_path {
+            if path.parent() == Some(dir) && !entries.iter().any(|(entry, _)| entry == path) {
+                entries.push((path.clone(), true));
+            }
+        }
+        entries.sort();
+
+        let mut others = 0;
+        let indent = "  ".repeat(depth);
+        for (path, is_dir) in &entries {
+            let important = self.changed.contains(path) || self.on_path.contains(path);
+            if !important {
+                others += 1;
+                if others > MAX_OTHER_ENTRIES {
+                    continue;
+                }
+            }
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy())
+                .unwrap_or_default();
+            let suffix = if *is_dir {
+                "/"
+            } else if self.changed.contains(path) {
+                " (changed)"
+            } else {
+                ""
+            };
+            output.push_str(&format!("{}{}{}\n", indent, name, suffix));
+            if *is_dir && self.on_path.contains(path) {
+                self.render_dir(path, depth + 1, output);
+            }
+        }
+        if others > MAX_OTHER_ENTRIES {
+            output.push_str(&format!(
+                "{}... and {} more\n",
+                indent,
+                others - MAX_OTHER_ENTRIES
+            ));
+        }
+    }
+
+    /// `dir`'s entries as paths relative to the root, with whether each is a
+    /// directory. Unreadable directories have no entries.
+    fn read_entries(&self, dir: &Path) -> Vec<(PathBuf, bool)> {
+        let Ok(read_dir) = fs::read_dir(self.root.join(dir)) else {
+            return Vec::new();
+        };
+        read_dir
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let is_dir = entry.file_type().ok()?.is_dir();
+                let name = entry.file_name();
+                if is_dir && IGNORED_DIRS.iter().any(|ignored| name == *ignored) {
+                    return None;
+                }
+                Some((dir.join(name), is_dir))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_tree_lists_siblings_of_changed_files_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for path in [
+            "src/client/mod.rs",
+            "src/client/dto.rs",
+            "src/main.rs",
+            "docs/guide.md",
+        ] {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        fs::create_dir_all(root.join("target/debug")).unwrap();
+
+        let files = vec!["src/client/mod.rs".to_string(), "src/gone.rs".to_string()];
+        assert_eq!(
+            render_tree(root, &files).unwrap(),
+            "\
+src/
+  client/
+    dto.rs
+    mod.rs (changed)
+  gone.rs (changed)
+  main.rs
+"
+        );
+
+        let tree = render_tree(root, &["README.md".to_string()]).unwrap();
+        assert_eq!(tree, "README.md (changed)\ndocs/\nsrc/\n");
+    }
+}
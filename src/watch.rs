@@ -0,0 +1,116 @@
+use anyhow::{Context, Result, anyhow};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc::{UnboundedReceiver, unbounded_channel};
+
+/// How long the tree must stay quiet after a change before `review --watch`
+/// starts the next review, so a burst of saves triggers one run.
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// Watches the working tree for `review --watch`.
+pub struct ChangeWatcher {
+    root: PathBuf,
+    events: UnboundedReceiver<notify::Result<Event>>,
+    // Dropping the watcher stops the events, so it lives as long as the receiver.
+    _watcher: RecommendedWatcher,
+}
+
+impl ChangeWatcher {
+    pub fn new(root: &Path) -> Result<Self> {
+        let root = root
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve {}", root.display()))?;
+        let (sender, events) = unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            // The receiver is only gone once blart is shutting down.
+            let _ = sender.send(event);
+        })
+        .context("Failed to start the file watcher")?;
+        watcher
+            .watch(&root, RecursiveMode::Recursive)
+            .with_context(|| format!("Failed to watch {}", root.display()))?;
+        Ok(Self {
+            root,
+            events,
+            _watcher: watcher,
+        })
+    }
+
+    /// Drops changes that arrived while a review was running; that review is
+    /// not re-run for them.
+    pub fn skip_pending(&mut self) {
+        while self.events.try_recv().is_ok() {}
+    }
+
+    /// Waits for a change to the working tree, then for the tree to stay
+    /// quiet for `DEBOUNCE`.
+    pub async fn wait_for_change(&mut self) -> Result<()> {
+        loop {
+            let event = self.next_event().await?;
+            if is_relevant_change(&self.root, &event) {
+                break;
+            }
+        }
+        while let Ok(event) = tokio::time::timeout(DEBOUNCE, self.events.recv()).await {
+            event
+                .ok_or_else(|| anyhow!("File watcher stopped unexpectedly"))?
+                .context("File watcher failed")?;
+        }
+        Ok(())
+    }
+
+    async fn next_event(&mut self) -> Result<Event> {
+        let event = self
+            .events
+            .recv()
+            .await
+            .ok_or_else(|| anyhow!("File watcher stopped unexpectedly"))?;
+        event.context("File watcher failed")
+    }
+}
+
+/// Whether `event` modifies a file a review could see. Reads are ignored, as
+/// are `.git` (which blart's own git commands touch) and `target`, the same
+/// directories the search tools skip.
+fn is_relevant_change(root: &Path, event: &Event) -> bool {
+    if matches!(event.kind, EventKind::Access(_)) {
+        return false;
+    }
+    event.paths.iter().any(|path| {
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        !relative
+            .components()
+            .any(|component| matches!(component.as_os_str().to_str(), Some(".git" | "target")))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{AccessKind, ModifyKind};
+
+    #[test]
+    fn is_relevant_change_ignores_reads_and_git_internals() {
+        let root = Path::new("/repo");
+        let event = |kind: EventKind, path: &str| Event::new(kind).add_path(PathBuf::from(path));
+        let modify = EventKind::Modify(ModifyKind::Any);
+
+        assert!(is_relevant_change(
+            root,
+            &event(modify, "/repo/src/main.rs")
+        ));
+        assert!(!is_relevant_change(
+            root,
+            &event(modify, "/repo/.git/index")
+        ));
+        assert!(!is_relevant_change(
+            root,
+            &event(modify, "/repo/target/debug/blart")
+        ));
+        assert!(!is_relevant_change(
+            root,
+            &event(EventKind::Access(AccessKind::Any), "/repo/src/main.rs")
+        ));
+    }
+}